@@ -96,7 +96,7 @@ async fn run(
 
   let manifest = if let Manifest::ML(manifest_list) = &manifest {
     let x = &manifest_list.manifests[0];
-    let (m, _) = client.get_manifest_and_ref(image, &x.digest).await?;
+    let (m, _) = client.get_manifest_and_ref(image, &x.digest.to_string()).await?;
     m
   } else {
     manifest
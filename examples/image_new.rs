@@ -1,7 +1,12 @@
 use std::{boxed, env, error, fs, io, path::Path, result::Result};
 
-use docker_registry::{render, render::LayerBlob, v2::manifest::Manifest};
-use futures::future::try_join_all;
+use docker_registry::{
+  render,
+  v2::{
+    manifest::Manifest,
+    pull::{PullEvent, PullOptions},
+  },
+};
 use tracing::{error, info, warn};
 
 #[tokio::main]
@@ -99,30 +104,28 @@ async fn run(
   }
 
   let manifest = if let Manifest::ML(manifest_list) = &manifest {
-    let x = &manifest_list.manifests[0];
-    let (m, _) = client.get_manifest_and_ref(image, &x.digest).await?;
+    let x = manifest_list
+      .get_platform("linux", "amd64", None)
+      .ok_or("no linux/amd64 image in the manifest list")?;
+    let (m, _) = client.get_manifest_and_ref(image, &x.digest.to_string()).await?;
     m
   } else {
     manifest
   };
 
-  let layers_digests = manifest.layers(Some("amd64"))?;
+  let layers = manifest.layers(Some("amd64"))?;
 
-  info!("{} -> got {} layer(s)", &image, layers_digests.len(),);
+  info!("{} -> got {} layer(s)", &image, layers.len(),);
 
-  let blob_futures = layers_digests
-    .iter()
-    .map(|layer_digest| client.get_blob_from_layer(image, layer_digest))
-    .collect::<Vec<_>>();
-
-  let layer_blobs = try_join_all(blob_futures)
-    .await?
-    .into_iter()
-    .map(|(bytes, media_type)| LayerBlob {
-      bytes,
-      media_type: Some(media_type),
+  // Layers are verified against the config while unpacking, when it lists their diff_ids.
+  let options = PullOptions::default().concurrency(4);
+  let layer_blobs = client
+    .pull_layers(image, &manifest, &options, |event| match event {
+      PullEvent::Verified { digest, .. } => info!("Layer {digest} downloaded"),
+      PullEvent::Retried { digest, error, .. } => warn!("Retrying layer {digest}: {error}"),
+      _ => {}
     })
-    .collect::<Vec<_>>();
+    .await?;
 
   println!("Downloaded {} layers", layer_blobs.len());
 
@@ -1,6 +1,9 @@
 use std::{boxed, env, error, fs, io, str::FromStr};
 
-use docker_registry::reference;
+use docker_registry::{
+  reference,
+  v2::pull::{PullEvent, PullOptions},
+};
 use tracing::{error, info, warn};
 
 #[tokio::main]
@@ -73,12 +76,18 @@ async fn run(
   let layers_digests = manifest.layers_digests(None)?;
   info!("{} -> got {} layer(s)", &image, layers_digests.len(),);
 
-  for layer_digest in &layers_digests {
-    let blob = client.get_blob(&image, layer_digest).await?;
-    info!("Layer {layer_digest}, got {} bytes.", blob.len());
+  let layers = client
+    .pull_layers(&image, &manifest, &PullOptions::default(), |event| {
+      if let PullEvent::Verified { digest, .. } = event {
+        info!("Layer {digest} downloaded");
+      }
+    })
+    .await?;
+  for (digest, layer) in layers_digests.iter().zip(&layers) {
+    info!("Layer {digest}, got {} bytes.", layer.bytes.len());
   }
 
-  info!("Downloaded {} layers", layers_digests.len());
+  info!("Downloaded {} layers", layers.len());
 
   Ok(())
 }
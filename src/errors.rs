@@ -22,13 +22,13 @@ pub enum Error {
   #[error("authentication information missing for index {0}")]
   AuthInfoMissing(String),
   #[error("unknown media type {0:?}")]
-  UnknownMimeType(mime::Mime),
-  #[error("unknown media type {0:?}")]
   UnsupportedMediaType(crate::mediatypes::MediaTypes),
   #[error("mime parse error")]
   MimeParse(#[from] mime::FromStrError),
   #[error("missing authentication header {0}")]
   MissingAuthHeader(&'static str),
+  #[error("missing header {0} in registry response")]
+  MissingHeader(&'static str),
   #[error("unexpected HTTP status {0}")]
   UnexpectedHttpStatus(reqwest::StatusCode),
   #[error("invalid auth token '{0}'")]
@@ -45,10 +45,32 @@ pub enum Error {
   Server { status: reqwest::StatusCode },
   #[error("content digest error")]
   ContentDigestParse(#[from] crate::v2::ContentDigestError),
+  #[error("io error")]
+  Io(#[from] std::io::Error),
+  #[error("blob size mismatch: expected {expected} bytes, got {got}")]
+  BlobSize { expected: u64, got: u64 },
+  #[error("invalid Content-Range {0:?}")]
+  InvalidContentRange(String),
   #[error("no header Content-Type given and no workaround to apply")]
   MediaTypeSniff,
   #[error("manifest error")]
   Manifest(#[from] crate::v2::manifest::ManifestError),
+  #[error("manifest build error")]
+  ManifestBuild(#[from] crate::v2::manifest::ManifestBuildError),
+  #[error("manifest conversion error")]
+  ManifestConversion(#[from] crate::v2::manifest::ConversionError),
+  #[error("manifest signature error")]
+  ManifestSignature(#[from] crate::v2::manifest::SignatureError),
+  #[error("artifact error")]
+  Artifact(#[from] crate::v2::ArtifactError),
+  #[error("attestation error")]
+  Attestation(#[from] crate::v2::attestations::AttestationError),
+  #[error("cosign error")]
+  Cosign(#[from] crate::v2::cosign::CosignError),
+  #[error("file error")]
+  File(#[from] crate::v2::files::FileError),
+  #[error("render error")]
+  Render(#[from] crate::render::RenderError),
   #[error("reference is invalid")]
   ReferenceParse(#[from] crate::reference::ReferenceParseError),
   #[error("requested operation requires that credentials are available")]
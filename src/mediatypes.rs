@@ -6,6 +6,7 @@ use crate::errors::Result;
 
 // For schema1 types, see https://docs.docker.com/registry/spec/manifest-v2-1/
 // For schema2 types, see https://docs.docker.com/registry/spec/manifest-v2-2/
+// For OCI types, see https://github.com/opencontainers/image-spec/blob/main/media-types.md
 
 #[derive(EnumProperty, EnumString, Display, Debug, Hash, PartialEq, Eq, Clone)]
 pub enum MediaTypes {
@@ -29,10 +30,18 @@ pub enum MediaTypes {
   #[strum(serialize = "application/vnd.docker.image.rootfs.diff.tar.gzip")]
   #[strum(props(Sub = "vnd.docker.image.rootfs.diff.tar.gzip"))]
   ImageLayerTgz,
+  /// Foreign image layer, as a gzip-compressed tar, usually fetched from `urls`.
+  #[strum(serialize = "application/vnd.docker.image.rootfs.foreign.diff.tar.gzip")]
+  #[strum(props(Sub = "vnd.docker.image.rootfs.foreign.diff.tar.gzip"))]
+  ImageLayerForeignTgz,
   /// Configuration object for a container.
   #[strum(serialize = "application/vnd.docker.container.image.v1+json")]
   #[strum(props(Sub = "vnd.docker.container.image.v1+json"))]
   ContainerConfigV1,
+  /// Configuration object for a Docker plugin.
+  #[strum(serialize = "application/vnd.docker.plugin.v1+json")]
+  #[strum(props(Sub = "vnd.docker.plugin.v1+json"))]
+  PluginConfigV1,
 
   /// OCI Manifest
   #[strum(serialize = "application/vnd.oci.image.manifest.v1+json")]
@@ -42,37 +51,110 @@ pub enum MediaTypes {
   #[strum(serialize = "application/vnd.oci.image.index.v1+json")]
   #[strum(props(Sub = "vnd.oci.image.index.v1+json"))]
   OciImageIndexV1,
+  /// OCI image configuration.
+  #[strum(serialize = "application/vnd.oci.image.config.v1+json")]
+  #[strum(props(Sub = "vnd.oci.image.config.v1+json"))]
+  OciImageConfig,
+  /// OCI image layer, as an uncompressed tar.
+  #[strum(serialize = "application/vnd.oci.image.layer.v1.tar")]
+  #[strum(props(Sub = "vnd.oci.image.layer.v1.tar"))]
+  OciImageLayerTar,
+  /// OCI image layer, as a gzip-compressed tar.
+  #[strum(serialize = "application/vnd.oci.image.layer.v1.tar+gzip")]
+  #[strum(props(Sub = "vnd.oci.image.layer.v1.tar+gzip"))]
+  OciImageLayerTgz,
+  /// OCI image layer, as a zstd-compressed tar.
+  #[strum(serialize = "application/vnd.oci.image.layer.v1.tar+zstd")]
+  #[strum(props(Sub = "vnd.oci.image.layer.v1.tar+zstd"))]
+  OciImageLayerTzstd,
+  /// OCI non-distributable image layer, as an uncompressed tar (deprecated by the spec).
+  #[strum(serialize = "application/vnd.oci.image.layer.nondistributable.v1.tar")]
+  #[strum(props(Sub = "vnd.oci.image.layer.nondistributable.v1.tar"))]
+  OciImageLayerNondistributableTar,
+  /// OCI non-distributable image layer, as a gzip-compressed tar (deprecated by the spec).
+  #[strum(serialize = "application/vnd.oci.image.layer.nondistributable.v1.tar+gzip")]
+  #[strum(props(Sub = "vnd.oci.image.layer.nondistributable.v1.tar+gzip"))]
+  OciImageLayerNondistributableTgz,
+  /// OCI non-distributable image layer, as a zstd-compressed tar (deprecated by the spec).
+  #[strum(serialize = "application/vnd.oci.image.layer.nondistributable.v1.tar+zstd")]
+  #[strum(props(Sub = "vnd.oci.image.layer.nondistributable.v1.tar+zstd"))]
+  OciImageLayerNondistributableTzstd,
+  /// OCI empty descriptor content (`{}`), used as config for artifacts.
+  #[strum(serialize = "application/vnd.oci.empty.v1+json")]
+  #[strum(props(Sub = "vnd.oci.empty.v1+json"))]
+  OciEmpty,
+
+  /// Helm chart configuration.
+  #[strum(serialize = "application/vnd.cncf.helm.config.v1+json")]
+  #[strum(props(Sub = "vnd.cncf.helm.config.v1+json"))]
+  HelmChartConfig,
+  /// Helm chart content, as a gzip-compressed tar.
+  #[strum(serialize = "application/vnd.cncf.helm.chart.content.v1.tar+gzip")]
+  #[strum(props(Sub = "vnd.cncf.helm.chart.content.v1.tar+gzip"))]
+  HelmChartContent,
+  /// Helm chart provenance file.
+  #[strum(serialize = "application/vnd.cncf.helm.chart.provenance.v1.prov")]
+  #[strum(props(Sub = "vnd.cncf.helm.chart.provenance.v1.prov"))]
+  HelmChartProvenance,
+  /// Cosign simple-signing payload.
+  #[strum(serialize = "application/vnd.dev.cosign.simplesigning.v1+json")]
+  #[strum(props(Sub = "vnd.dev.cosign.simplesigning.v1+json"))]
+  CosignSimpleSigning,
+  /// Sigstore bundle.
+  #[strum(serialize = "application/vnd.dev.sigstore.bundle.v0.3+json")]
+  #[strum(props(Sub = "vnd.dev.sigstore.bundle.v0.3+json"))]
+  SigstoreBundle,
+  /// In-toto attestation statement.
+  #[strum(serialize = "application/vnd.in-toto+json")]
+  #[strum(props(Sub = "vnd.in-toto+json"))]
+  InTotoStatement,
+  /// DSSE envelope, usually wrapping an in-toto statement.
+  #[strum(serialize = "application/vnd.dsse.envelope.v1+json")]
+  #[strum(props(Sub = "vnd.dsse.envelope.v1+json"))]
+  DsseEnvelope,
 
   /// Generic JSON
   #[strum(serialize = "application/json")]
   #[strum(props(Sub = "json"))]
   ApplicationJson,
+
+  /// Any other media type, carried through verbatim.
+  #[strum(default)]
+  Other(String),
+}
+
+/// Compression applied to the content of a blob.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Compression {
+  Uncompressed,
+  Gzip,
+  Zstd,
 }
 
 impl MediaTypes {
-  // TODO(lucab): proper error types
+  /// Parse a `MediaTypes` from a MIME type, ignoring any parameters.
+  ///
+  /// Unknown types are returned as `MediaTypes::Other`.
   pub fn from_mime(mtype: &mime::Mime) -> Result<Self> {
     match (mtype.type_(), mtype.subtype(), mtype.suffix()) {
       (mime::APPLICATION, mime::JSON, _) => Ok(MediaTypes::ApplicationJson),
-      (mime::APPLICATION, subt, Some(suff)) => match (subt.to_string().as_str(), suff.to_string().as_str()) {
-        // Docker
-        ("vnd.docker.distribution.manifest.v1", "json") => Ok(MediaTypes::ManifestV2S1),
-        ("vnd.docker.distribution.manifest.v1", "prettyjws") => Ok(MediaTypes::ManifestV2S1Signed),
-        ("vnd.docker.distribution.manifest.v2", "json") => Ok(MediaTypes::ManifestV2S2),
-        ("vnd.docker.distribution.manifest.list.v2", "json") => Ok(MediaTypes::ManifestList),
-        ("vnd.docker.image.rootfs.diff.tar.gzip", _) => Ok(MediaTypes::ImageLayerTgz),
-        ("vnd.docker.container.image.v1", "json") => Ok(MediaTypes::ContainerConfigV1),
-        // OCI
-        ("vnd.oci.image.manifest.v1", "json") => Ok(MediaTypes::OciImageManifest),
-        ("vnd.oci.image.index.v1", "json") => Ok(MediaTypes::OciImageIndexV1),
-        _ => Err(crate::Error::UnknownMimeType(mtype.clone())),
-      },
-      _ => Err(crate::Error::UnknownMimeType(mtype.clone())),
+      // Some registries serve Docker layers with an arbitrary suffix.
+      (mime::APPLICATION, subt, Some(_)) if subt == "vnd.docker.image.rootfs.diff.tar.gzip" => {
+        Ok(MediaTypes::ImageLayerTgz)
+      }
+      _ => Ok(
+        mtype
+          .essence_str()
+          .parse()
+          .expect("parsing falls back to MediaTypes::Other"),
+      ),
     }
   }
+
   pub fn to_mime(&self) -> mime::Mime {
     match self {
       &MediaTypes::ApplicationJson => Ok(mime::APPLICATION_JSON),
+      MediaTypes::Other(s) => s.parse().or(Ok(mime::APPLICATION_OCTET_STREAM)),
       m => {
         if let Some(s) = m.get_str("Sub") {
           ("application/".to_string() + s).parse()
@@ -83,4 +165,137 @@ impl MediaTypes {
     }
     .expect("to_mime should be always successful")
   }
+
+  /// Whether this is the media type of a single-image manifest.
+  pub fn is_manifest(&self) -> bool {
+    matches!(
+      self,
+      MediaTypes::ManifestV2S1
+        | MediaTypes::ManifestV2S1Signed
+        | MediaTypes::ManifestV2S2
+        | MediaTypes::OciImageManifest
+    )
+  }
+
+  /// Whether this is the media type of a manifest list or image index.
+  pub fn is_index(&self) -> bool {
+    matches!(self, MediaTypes::ManifestList | MediaTypes::OciImageIndexV1)
+  }
+
+  /// Whether this is the media type of an image filesystem layer.
+  pub fn is_layer(&self) -> bool {
+    matches!(
+      self,
+      MediaTypes::ImageLayerTgz
+        | MediaTypes::ImageLayerForeignTgz
+        | MediaTypes::OciImageLayerTar
+        | MediaTypes::OciImageLayerTgz
+        | MediaTypes::OciImageLayerTzstd
+        | MediaTypes::OciImageLayerNondistributableTar
+        | MediaTypes::OciImageLayerNondistributableTgz
+        | MediaTypes::OciImageLayerNondistributableTzstd
+    )
+  }
+
+  /// Whether this is the media type of a foreign (Docker) or non-distributable (OCI) layer.
+  pub fn is_foreign_layer(&self) -> bool {
+    matches!(
+      self,
+      MediaTypes::ImageLayerForeignTgz
+        | MediaTypes::OciImageLayerNondistributableTar
+        | MediaTypes::OciImageLayerNondistributableTgz
+        | MediaTypes::OciImageLayerNondistributableTzstd
+    )
+  }
+
+  /// Whether this is the media type of an image configuration.
+  pub fn is_config(&self) -> bool {
+    matches!(self, MediaTypes::ContainerConfigV1 | MediaTypes::OciImageConfig)
+  }
+
+  /// Compression of the content described by this media type.
+  ///
+  /// For `MediaTypes::Other` this is inferred from the `+gzip` / `+zstd` suffix.
+  pub fn compression(&self) -> Compression {
+    match self {
+      MediaTypes::ImageLayerTgz
+      | MediaTypes::ImageLayerForeignTgz
+      | MediaTypes::OciImageLayerTgz
+      | MediaTypes::OciImageLayerNondistributableTgz
+      | MediaTypes::HelmChartContent => Compression::Gzip,
+      MediaTypes::OciImageLayerTzstd | MediaTypes::OciImageLayerNondistributableTzstd => Compression::Zstd,
+      MediaTypes::Other(s) if s.ends_with("+gzip") || s.ends_with(".gzip") => Compression::Gzip,
+      MediaTypes::Other(s) if s.ends_with("+zstd") => Compression::Zstd,
+      _ => Compression::Uncompressed,
+    }
+  }
+
+  /// Compression of a layer with this media type.
+  ///
+  /// Unlike `compression`, unknown types without a `+zstd` suffix are assumed to be
+  /// gzip-compressed, as Docker layers are.
+  pub fn layer_compression(&self) -> Compression {
+    match self {
+      MediaTypes::Other(s) if !s.ends_with("+zstd") => Compression::Gzip,
+      mt => mt.compression(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::str::FromStr;
+
+  use test_case::test_case;
+
+  use super::*;
+
+  #[test_case("application/vnd.oci.image.layer.v1.tar+gzip" => MediaTypes::OciImageLayerTgz; "oci gzip layer")]
+  #[test_case("application/vnd.oci.image.layer.v1.tar+zstd" => MediaTypes::OciImageLayerTzstd; "oci zstd layer")]
+  #[test_case("application/vnd.oci.image.layer.v1.tar" => MediaTypes::OciImageLayerTar; "oci tar layer")]
+  #[test_case("application/vnd.oci.empty.v1+json" => MediaTypes::OciEmpty; "oci empty")]
+  #[test_case("application/vnd.docker.image.rootfs.foreign.diff.tar.gzip" => MediaTypes::ImageLayerForeignTgz; "docker foreign layer")]
+  #[test_case("application/vnd.docker.image.rootfs.diff.tar.gzip+foo" => MediaTypes::ImageLayerTgz; "docker layer with suffix")]
+  #[test_case("application/json; charset=utf-8" => MediaTypes::ApplicationJson; "json with params")]
+  #[test_case("application/vnd.example.thing.v1+json" => MediaTypes::Other("application/vnd.example.thing.v1+json".into()); "unknown")]
+  fn from_mime(input: &str) -> MediaTypes {
+    MediaTypes::from_mime(&input.parse().unwrap()).unwrap()
+  }
+
+  #[test]
+  fn other_roundtrips() {
+    let raw = "application/vnd.wasm.content.layer.v1+wasm";
+    let mt = MediaTypes::from_str(raw).unwrap();
+    assert_eq!(MediaTypes::Other(raw.to_string()), mt);
+    assert_eq!(raw, mt.to_string());
+    assert_eq!(raw, mt.to_mime().essence_str());
+  }
+
+  #[test]
+  fn helpers() {
+    assert!(MediaTypes::OciImageManifest.is_manifest());
+    assert!(!MediaTypes::OciImageIndexV1.is_manifest());
+    assert!(MediaTypes::ManifestList.is_index());
+    assert!(MediaTypes::OciImageLayerNondistributableTgz.is_layer());
+    assert!(MediaTypes::OciImageLayerNondistributableTgz.is_foreign_layer());
+    assert!(!MediaTypes::OciImageLayerTgz.is_foreign_layer());
+    assert!(MediaTypes::OciImageConfig.is_config());
+    assert_eq!(Compression::Zstd, MediaTypes::OciImageLayerTzstd.compression());
+    assert_eq!(Compression::Uncompressed, MediaTypes::OciImageLayerTar.compression());
+    assert_eq!(
+      Compression::Gzip,
+      MediaTypes::Other("application/vnd.example.layer.tar+gzip".into()).compression()
+    );
+    let unknown = MediaTypes::Other("application/vnd.example.layer".into());
+    assert_eq!(Compression::Uncompressed, unknown.compression());
+    assert_eq!(Compression::Gzip, unknown.layer_compression());
+    assert_eq!(
+      Compression::Zstd,
+      MediaTypes::Other("application/vnd.example.layer+zstd".into()).layer_compression()
+    );
+    assert_eq!(
+      Compression::Uncompressed,
+      MediaTypes::OciImageLayerTar.layer_compression()
+    );
+  }
 }
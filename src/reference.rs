@@ -31,6 +31,8 @@ use std::{collections::VecDeque, fmt, str, str::FromStr};
 
 use regex_lite::Regex;
 
+use crate::v2::{ContentDigestError, Digest};
+
 pub static DEFAULT_REGISTRY: &str = "registry-1.docker.io";
 static DEFAULT_TAG: &str = "latest";
 static DEFAULT_SCHEME: &str = "docker";
@@ -39,7 +41,7 @@ static DEFAULT_SCHEME: &str = "docker";
 #[derive(Clone)]
 pub enum Version {
   Tag(String),
-  Digest(String, String),
+  Digest(Digest),
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -50,6 +52,8 @@ pub enum VersionParseError {
   UnknownPrefix,
   #[error("empty string is invalid digest")]
   Empty,
+  #[error("invalid digest")]
+  InvalidDigest(#[from] ContentDigestError),
 }
 
 impl str::FromStr for Version {
@@ -58,11 +62,11 @@ impl str::FromStr for Version {
     let v = match s.chars().next() {
       Some(':') => Version::Tag(s.trim_start_matches(':').to_string()),
       Some('@') => {
-        let r: Vec<&str> = s.trim_start_matches('@').splitn(2, ':').collect();
-        if r.len() != 2 {
+        let d = s.trim_start_matches('@');
+        if !d.contains(':') {
           return Err(VersionParseError::WrongDigestFormat);
         };
-        Version::Digest(r[0].to_string(), r[1].to_string())
+        Version::Digest(d.parse()?)
       }
       Some(_) => return Err(VersionParseError::UnknownPrefix),
       None => return Err(VersionParseError::Empty),
@@ -81,7 +85,7 @@ impl fmt::Debug for Version {
   fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
     let v = match *self {
       Version::Tag(ref s) => ":".to_string() + s,
-      Version::Digest(ref t, ref d) => "@".to_string() + t + ":" + d,
+      Version::Digest(ref d) => format!("@{d}"),
     };
     write!(f, "{v}")
   }
@@ -91,7 +95,7 @@ impl fmt::Display for Version {
   fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
     let v = match *self {
       Version::Tag(ref s) => s.to_string(),
-      Version::Digest(ref t, ref d) => t.to_string() + ":" + d,
+      Version::Digest(ref d) => d.to_string(),
     };
     write!(f, "{v}")
   }
@@ -130,6 +134,14 @@ impl Reference {
     self.version.to_string()
   }
 
+  /// Get the digest of this reference, if it is pinned by digest.
+  pub fn digest(&self) -> Option<&Digest> {
+    match self.version {
+      Version::Digest(ref d) => Some(d),
+      Version::Tag(_) => None,
+    }
+  }
+
   pub fn to_raw_string(&self) -> String {
     self.raw_input.clone()
   }
@@ -2,21 +2,136 @@
 
 // Docker image format is specified at
 // https://github.com/moby/moby/blob/v17.05.0-ce/image/spec/v1.md
-use std::{fs, io, io::Read, path, path::Path};
+use std::{collections::BTreeSet, ffi::OsString, fs, io, io::Read, path, path::Path};
 
 use libflate::gzip;
 use tar::EntryType;
+use tokio::io::AsyncRead;
+use tokio_util::io::SyncIoBridge;
+
+use crate::{
+  mediatypes::Compression,
+  v2::{ContentDigestError, Digest, DigestAlgorithm, Digester, manifest::parse_media_type},
+};
 
 #[derive(Debug)]
 pub struct LayerBlob {
   pub bytes: Vec<u8>,
   pub media_type: Option<String>,
+  /// Expected digest of the uncompressed layer, from the `rootfs.diff_ids` of the image config.
+  ///
+  /// If set, it is verified while the layer is unpacked. As layers are unpacked in place,
+  /// the content of a mismatching layer is left in the target directory.
+  pub diff_id: Option<Digest>,
+}
+
+impl LayerBlob {
+  /// Create a layer blob, without an expected `diff_id`.
+  pub fn new(bytes: Vec<u8>, media_type: Option<String>) -> Self {
+    Self {
+      bytes,
+      media_type,
+      diff_id: None,
+    }
+  }
+
+  /// Set the expected digest of the uncompressed layer.
+  pub fn diff_id(mut self, diff_id: Digest) -> Self {
+    self.diff_id = Some(diff_id);
+    self
+  }
+}
+
+/// A layer unpacked as it is read, e.g. straight from a `BlobResponse`, rather than from memory.
+#[derive(Debug)]
+pub struct LayerReader<R> {
+  /// The layer blob, as stored in the registry.
+  pub reader: R,
+  pub media_type: Option<String>,
+  /// Expected digest of the uncompressed layer, see `LayerBlob`.
+  pub diff_id: Option<Digest>,
+}
+
+/// Options of `unpack_layers_with` and the other `*_with` functions.
+///
+/// Whatever the options, every path of a layer is resolved inside the target directory:
+/// symlinks planted by lower layers cannot make entries, whiteouts or hard links escape it.
+#[derive(Clone, Debug)]
+pub struct UnpackOptions {
+  preserve_permissions: bool,
+  unpack_xattrs: bool,
+  rootless: bool,
+  ownership: Ownership,
+}
+
+impl Default for UnpackOptions {
+  fn default() -> Self {
+    Self {
+      preserve_permissions: true,
+      unpack_xattrs: true,
+      rootless: false,
+      ownership: Ownership::Ignore,
+    }
+  }
+}
+
+impl UnpackOptions {
+  /// Set whether file modes are applied as is, rather than masked by the umask. On by default.
+  pub fn preserve_permissions(mut self, preserve_permissions: bool) -> Self {
+    self.preserve_permissions = preserve_permissions;
+    self
+  }
+
+  /// Set whether extended attributes of the layers are applied (on Unix). On by default.
+  pub fn unpack_xattrs(mut self, unpack_xattrs: bool) -> Self {
+    self.unpack_xattrs = unpack_xattrs;
+    self
+  }
+
+  /// Set whether to skip what unprivileged users cannot create: block and character devices.
+  ///
+  /// Combine with `Ownership::Map` to render an image for a user namespace.
+  pub fn rootless(mut self, rootless: bool) -> Self {
+    self.rootless = rootless;
+    self
+  }
+
+  /// Set the owner of the unpacked files, `Ownership::Ignore` by default.
+  pub fn ownership(mut self, ownership: Ownership) -> Self {
+    self.ownership = ownership;
+    self
+  }
+}
+
+/// Owner of the files unpacked from a layer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Ownership {
+  /// Files belong to the user unpacking the layers.
+  Ignore,
+  /// Files get the uid and gid recorded in the layers, which usually requires root.
+  Preserve,
+  /// Files get the uid and gid recorded in the layers, mapped to host ids as in
+  /// `/etc/subuid`; ids without mapping are ignored.
+  Map { uids: Vec<IdMapping>, gids: Vec<IdMapping> },
+}
+
+/// A range of `size` ids starting at `container_id` in the layers, mapped to the host
+/// ids starting at `host_id`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IdMapping {
+  pub container_id: u32,
+  pub host_id: u32,
+  pub size: u32,
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum RenderError {
   #[error("wrong target path {}: must be absolute path to existing directory", _0.display())]
   WrongTargetPath(path::PathBuf),
+  #[error("layer diff_id mismatch: expected {expected}, got {got}")]
+  DiffIdMismatch { expected: Digest, got: Digest },
+  #[error("content digest error")]
+  ContentDigest(#[from] ContentDigestError),
   #[error("io error")]
   Io(#[from] std::io::Error),
 }
@@ -33,6 +148,10 @@ pub fn unpack(layers: &[Vec<u8>], target_dir: &Path) -> Result<(), RenderError>
 ///
 /// Layers must be provided as gzip- or zstd-compressed tar archives, with lower layers
 /// coming first. Target directory must be an existing absolute path.
+///
+/// The `diff_id` of a layer is only known to mismatch once it has been unpacked: on
+/// `RenderError::DiffIdMismatch`, the target directory holds that layer and must be
+/// discarded.
 pub fn unpack_layers(layers: &[LayerBlob], target_dir: &Path) -> Result<(), RenderError> {
   filter_unpack_layers(layers, target_dir, |_| true)
 }
@@ -43,10 +162,7 @@ where
 {
   let layers = layers
     .iter()
-    .map(|b| LayerBlob {
-      bytes: b.clone(),
-      media_type: None,
-    })
+    .map(|b| LayerBlob::new(b.clone(), None))
     .collect::<Vec<_>>();
 
   filter_unpack_layers(layers.as_slice(), target_dir, predicate)
@@ -58,16 +174,230 @@ where
 /// Layers must be provided as gzip- or zstd-compressed tar archives, with lower layers
 /// coming first. Target directory must be an existing absolute path.
 pub fn filter_unpack_layers<P>(layers: &[LayerBlob], target_dir: &Path, predicate: P) -> Result<(), RenderError>
+where
+  P: Fn(&Path) -> bool,
+{
+  _unpack_layers(layers, target_dir, &predicate, &UnpackOptions::default())
+}
+
+/// Unpack an ordered list of layers to a target directory, with the given options.
+///
+/// See `unpack_layers`.
+pub fn unpack_layers_with(layers: &[LayerBlob], target_dir: &Path, options: &UnpackOptions) -> Result<(), RenderError> {
+  _unpack_layers(layers, target_dir, &|_| true, options)
+}
+
+fn _unpack_layers<P>(
+  layers: &[LayerBlob],
+  target_dir: &Path,
+  predicate: &P,
+  options: &UnpackOptions,
+) -> Result<(), RenderError>
 where
   P: Fn(&Path) -> bool,
 {
   for l in layers {
-    _unpack_layer(l, target_dir, &predicate)?;
+    _unpack_layer(
+      l.bytes.as_slice(),
+      l.media_type.as_deref(),
+      l.diff_id.as_ref(),
+      target_dir,
+      predicate,
+      options,
+      Whiteouts::Apply,
+    )?;
   }
   Ok(())
 }
 
-fn _unpack_archive<'a, P>(dst: &Path, archive: &mut tar::Archive<Box<dyn Read + 'a>>, predicate: P) -> io::Result<()>
+/// Unpack a single layer to a target directory, as it is read.
+///
+/// The layer is decompressed on the fly, so only a small buffer is held in memory; it must
+/// be applied on top of the layers below it. Whatever the archive leaves unread is drained
+/// afterwards, so that a verifying reader sees the whole blob. Target directory must be an
+/// existing absolute path.
+pub fn unpack_layer_reader<R: Read>(layer: LayerReader<R>, target_dir: &Path) -> Result<(), RenderError> {
+  filter_unpack_layer_reader(layer, target_dir, |_| true)
+}
+
+/// Unpack a single layer to a target directory as it is read, filtering file entries by path.
+///
+/// See `unpack_layer_reader`.
+pub fn filter_unpack_layer_reader<R, P>(
+  layer: LayerReader<R>,
+  target_dir: &Path,
+  predicate: P,
+) -> Result<(), RenderError>
+where
+  R: Read,
+  P: Fn(&Path) -> bool,
+{
+  _unpack_layer(
+    layer.reader,
+    layer.media_type.as_deref(),
+    layer.diff_id.as_ref(),
+    target_dir,
+    &predicate,
+    &UnpackOptions::default(),
+    Whiteouts::Apply,
+  )?;
+  Ok(())
+}
+
+/// Unpack a single layer to a target directory as it is read, with the given options.
+///
+/// See `unpack_layer_reader`.
+pub fn unpack_layer_reader_with<R: Read>(
+  layer: LayerReader<R>,
+  target_dir: &Path,
+  options: &UnpackOptions,
+) -> Result<(), RenderError> {
+  _unpack_layer(
+    layer.reader,
+    layer.media_type.as_deref(),
+    layer.diff_id.as_ref(),
+    target_dir,
+    &|_| true,
+    options,
+    Whiteouts::Apply,
+  )?;
+  Ok(())
+}
+
+/// Unpack a single layer to a target directory, as it is read asynchronously.
+///
+/// Extraction runs on a blocking thread of the current Tokio runtime. See `unpack_layer_reader`.
+pub async fn unpack_layer_async<R>(layer: LayerReader<R>, target_dir: &Path) -> Result<(), RenderError>
+where
+  R: AsyncRead + Unpin + Send + 'static,
+{
+  unpack_layer_async_with(layer, target_dir, &UnpackOptions::default()).await
+}
+
+/// Unpack a single layer to a target directory as it is read asynchronously, with the given options.
+///
+/// See `unpack_layer_async`.
+pub async fn unpack_layer_async_with<R>(
+  layer: LayerReader<R>,
+  target_dir: &Path,
+  options: &UnpackOptions,
+) -> Result<(), RenderError>
+where
+  R: AsyncRead + Unpin + Send + 'static,
+{
+  let layer = LayerReader {
+    reader: SyncIoBridge::new(layer.reader),
+    media_type: layer.media_type,
+    diff_id: layer.diff_id,
+  };
+  let target_dir = target_dir.to_path_buf();
+  let options = options.clone();
+  tokio::task::spawn_blocking(move || unpack_layer_reader_with(layer, &target_dir, &options))
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// Unpack each layer of an ordered list into its own directory, to be stacked with overlayfs.
+///
+/// Layers are unpacked to `<dir>/<diff_id>` (see `overlay_layer_path`), which are returned
+/// in order, lower layers first; overlayfs expects them the other way round in `lowerdir`.
+/// See `unpack_layer_overlay_reader`.
+pub fn unpack_layers_overlay(
+  layers: &[LayerBlob],
+  dir: &Path,
+  options: &UnpackOptions,
+) -> Result<Vec<path::PathBuf>, RenderError> {
+  layers
+    .iter()
+    .map(|l| {
+      let layer = LayerReader {
+        reader: l.bytes.as_slice(),
+        media_type: l.media_type.clone(),
+        diff_id: l.diff_id.clone(),
+      };
+      unpack_layer_overlay_reader(layer, dir, options)
+    })
+    .collect()
+}
+
+/// Unpack a single layer, as it is read, into its own directory `<dir>/<diff_id>` for overlayfs.
+///
+/// Whiteouts are converted to the overlayfs format: a `0/0` character device for each
+/// removed file, and the `trusted.overlay.opaque` extended attribute (`user.overlay.opaque`
+/// in rootless mode) on opaque directories. Creating them requires `CAP_MKNOD` and, for
+/// `trusted.` attributes, `CAP_SYS_ADMIN`.
+///
+/// If the layer `diff_id` is known and its directory already exists, e.g. because another
+/// image shares the layer, it is reused without reading the layer. Otherwise the layer is
+/// unpacked to a temporary directory, which is moved into place once complete and verified,
+/// its `diff_id` being computed if unknown. `dir` must be an existing absolute path.
+///
+/// Directories are keyed by `diff_id` only, whatever `options` they were unpacked with: a
+/// layer unpacked in rootless mode or with mapped owners is reused as is by later calls
+/// with other options. Use a separate `dir` for each set of options.
+pub fn unpack_layer_overlay_reader<R: Read>(
+  layer: LayerReader<R>,
+  dir: &Path,
+  options: &UnpackOptions,
+) -> Result<path::PathBuf, RenderError> {
+  if !dir.is_absolute() || !dir.is_dir() {
+    return Err(RenderError::WrongTargetPath(dir.to_path_buf()));
+  }
+  if let Some(diff_id) = &layer.diff_id {
+    let path = overlay_layer_path(dir, diff_id);
+    if path.is_dir() {
+      return Ok(path);
+    }
+  }
+
+  let tmp = tempfile::Builder::new().prefix(".unpack-").tempdir_in(dir)?;
+  let diff_id = _unpack_layer(
+    layer.reader,
+    layer.media_type.as_deref(),
+    layer.diff_id.as_ref(),
+    tmp.path(),
+    &|_| true,
+    options,
+    Whiteouts::Overlay,
+  )?
+  .expect("diff_ids are computed in overlay mode");
+  // Temporary directories are private, layers are not.
+  #[cfg(unix)]
+  fs::set_permissions(tmp.path(), std::os::unix::fs::PermissionsExt::from_mode(0o755))?;
+
+  let path = overlay_layer_path(dir, &diff_id);
+  // Another image may have unpacked the same layer meanwhile, which is then reused.
+  if let Err(e) = fs::rename(tmp.path(), &path) {
+    if !path.is_dir() {
+      return Err(e.into());
+    }
+  }
+  Ok(path)
+}
+
+/// Directory of the layer `diff_id` unpacked by `unpack_layers_overlay` in `dir`.
+///
+/// The path does not depend on the `UnpackOptions`, see `unpack_layer_overlay_reader`.
+pub fn overlay_layer_path(dir: &Path, diff_id: &Digest) -> path::PathBuf {
+  dir.join(diff_id.encoded())
+}
+
+/// How whiteouts of a layer are handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Whiteouts {
+  /// Remove the files hidden by whiteouts, to render a merged filesystem.
+  Apply,
+  /// Convert whiteouts to the overlayfs format, to render a single layer.
+  Overlay,
+}
+
+fn _unpack_archive<'a, P>(
+  dst: &Path,
+  archive: &mut tar::Archive<Box<dyn Read + 'a>>,
+  predicate: P,
+  options: &UnpackOptions,
+  whiteouts: Whiteouts,
+) -> io::Result<()>
 where
   P: Fn(&Path) -> bool,
 {
@@ -86,35 +416,59 @@ where
   // descendants), to ensure that directory permissions do not interfer with descendant
   // extraction.
   let mut directories = Vec::new();
+  // Paths provided by this layer, which opaque whiteouts must keep wherever they come in the archive.
+  let mut layer_paths = BTreeSet::new();
   for entry in archive.entries()? {
     let mut file = entry.map_err(|e| io::Error::other(format!("failed to iterate over archive. {e}")))?;
-    if file.header().entry_type() == EntryType::Directory {
-      directories.push(file);
-    } else {
-      // Check for whiteouts else unpack file
-      let path = file.path()?;
-      let parent = path.parent().unwrap_or_else(|| Path::new("/"));
-
-      if let Some(fname) = path.file_name() {
-        let wh_name = fname.to_string_lossy();
-        if wh_name == ".wh..wh..opq" {
-          //TODO(lucab): opaque whiteout, dir removal
-        } else if wh_name.starts_with(".wh.") {
-          let rel_parent = path::PathBuf::from("./".to_string() + &parent.to_string_lossy());
-
-          // Remove real file behind whiteout
-          let real_name = wh_name.trim_start_matches(".wh.");
-          let abs_real_path = dst.join(&rel_parent).join(real_name);
-          remove_whiteout(abs_real_path)?;
-
-          // Remove whiteout place-holder
-          let abs_wh_path = dst.join(&rel_parent).join(fname);
-          remove_whiteout(abs_wh_path)?;
-        } else if predicate(&path) {
-          //println!("unpack {}", file.path()?.display());
-          file.unpack_in(dst)?;
+    let path = normalize_path(&file.path()?);
+    // Skip the root itself, and paths trying to get out of it as `unpack_in` does.
+    let Some(fname) = path.file_name() else {
+      continue;
+    };
+    if path.components().any(|c| c == path::Component::ParentDir) {
+      continue;
+    }
+
+    let entry_type = file.header().entry_type();
+    if entry_type == EntryType::Directory {
+      // Only the parent is resolved: a directory replaces whatever else is in its place, even a
+      // symlink, right away so that the following entries of the layer end up in it.
+      let parent = path.parent().unwrap_or_else(|| Path::new(""));
+      let target = resolve_in_root(dst, parent, true)?.join(fname);
+      if target.symlink_metadata().is_ok_and(|m| !m.is_dir()) {
+        fs::remove_file(&target)?;
+        fs::create_dir_all(&target)?;
+      }
+      layer_paths.insert(path.clone());
+      directories.push((path, file));
+      continue;
+    }
+    if options.rootless && matches!(entry_type, EntryType::Block | EntryType::Char) {
+      continue;
+    }
+
+    // Check for whiteouts else unpack file
+    match parse_whiteout(&path) {
+      Some(WhiteoutEntry::Opaque(dir)) => match whiteouts {
+        Whiteouts::Apply => clear_opaque_dir(dst, &dir, &layer_paths)?,
+        Whiteouts::Overlay => overlay::opaque_dir(&resolve_in_root(dst, &dir, true)?, options.rootless)?,
+      },
+      Some(WhiteoutEntry::Remove(hidden)) => {
+        // Remove real file behind whiteout
+        let real_path = resolve_in_root(dst, &hidden, false)?;
+        match whiteouts {
+          Whiteouts::Apply => remove_whiteout(real_path)?,
+          Whiteouts::Overlay => overlay::whiteout(&real_path)?,
         }
       }
+      None if predicate(&path) => {
+        let parent = path.parent().unwrap_or_else(|| Path::new(""));
+        let target = resolve_in_root(dst, parent, true)?.join(fname);
+        unpack_entry(dst, &mut file, &target)?;
+        set_ownership(&target, file.header(), &options.ownership)?;
+        layer_paths.insert(path);
+      }
+      None => {}
     }
   }
 
@@ -125,47 +479,400 @@ where
   // child directories within those of more restrictive permissions. See [0] for details.
   //
   // [0]: <https://github.com/alexcrichton/tar-rs/issues/242>
-  directories.sort_by(|a, b| b.path_bytes().cmp(&a.path_bytes()));
-  for mut dir in directories {
-    dir.unpack_in(dst)?;
+  //
+  // Later entries of the layer may have planted symlinks in the meantime, so the parent is
+  // resolved again, and a symlink in place of the directory itself is refused.
+  directories.sort_by(|(a, _), (b, _)| b.cmp(a));
+  for (path, mut dir) in directories {
+    let parent = resolve_in_root(dst, path.parent().unwrap_or_else(|| Path::new("")), true)?;
+    let target = parent.join(path.file_name().expect("directories have a file name"));
+    if target.symlink_metadata().is_ok_and(|m| m.file_type().is_symlink()) {
+      return Err(io::Error::other(format!(
+        "directory {} is replaced by a symlink",
+        path.display()
+      )));
+    }
+    fs::create_dir_all(&parent)?;
+    dir.unpack(&target)?;
+    set_ownership(&target, dir.header(), &options.ownership)?;
   }
 
   Ok(())
 }
 
-fn _unpack_layer<'a, P>(layer: &'a LayerBlob, target_dir: &Path, predicate: &P) -> Result<(), RenderError>
+/// Unpack a non-directory entry to `target`, whose parent was resolved inside `dst`.
+///
+/// Hard links are resolved inside `dst` too, so they can point to files of lower layers
+/// but not outside of the image.
+fn unpack_entry<R: Read>(dst: &Path, file: &mut tar::Entry<R>, target: &Path) -> io::Result<()> {
+  if let Some(parent) = target.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  // A directory from a lower layer replaced by something else.
+  if target.symlink_metadata().is_ok_and(|m| m.is_dir()) {
+    fs::remove_dir_all(target)?;
+  }
+
+  if !file.header().entry_type().is_hard_link() {
+    file.unpack(target)?;
+    return Ok(());
+  }
+  let link_name = file
+    .link_name()?
+    .ok_or_else(|| io::Error::other(format!("hard link {} has no target", target.display())))?;
+  // Hard links do not follow symlinks.
+  let source = resolve_in_root(dst, &link_name, false)?;
+  if target.symlink_metadata().is_ok() {
+    fs::remove_file(target)?;
+  }
+  fs::hard_link(&source, target)
+    .map_err(|e| io::Error::new(e.kind(), format!("failed to hard link {}: {e}", target.display())))
+}
+
+/// Resolve `path` inside `root`, as if `root` were the root of the filesystem.
+///
+/// See `resolve_symlinks`, with the symlinks of the filesystem under `root`.
+fn resolve_in_root(root: &Path, path: &Path, follow: bool) -> io::Result<path::PathBuf> {
+  let read_link = |candidate: &Path| {
+    let abs_candidate = root.join(candidate);
+    match abs_candidate.symlink_metadata() {
+      Ok(m) if m.file_type().is_symlink() => fs::read_link(&abs_candidate).map(Some),
+      _ => Ok(None),
+    }
+  };
+  let too_many = || {
+    io::Error::other(format!(
+      "too many levels of symbolic links resolving {}",
+      path.display()
+    ))
+  };
+  resolve_symlinks(path, follow, read_link, too_many).map(|resolved| root.join(resolved))
+}
+
+/// Resolve the symlinks of `path`, relative to the root of an image filesystem.
+///
+/// Symlinks, absolute or not, and `..` components are resolved without ever leaving the
+/// root, as the kernel would do in a chroot. `read_link` gives the target of a (relative)
+/// path if it is a symlink. The last component is only followed if it is a symlink and
+/// `follow` is set. Components that do not exist are kept as is. Following more than
+/// `MAX_SYMLINKS` symlinks fails with `too_many`.
+pub(crate) fn resolve_symlinks<E>(
+  path: &Path,
+  follow: bool,
+  mut read_link: impl FnMut(&Path) -> Result<Option<path::PathBuf>, E>,
+  too_many: impl FnOnce() -> E,
+) -> Result<path::PathBuf, E> {
+  /// Maximum number of symlinks followed, as `MAXSYMLINKS` on Linux.
+  const MAX_SYMLINKS: usize = 40;
+
+  let components = |path: &Path| -> Vec<OsString> {
+    path
+      .components()
+      .rev()
+      .filter_map(|c| match c {
+        path::Component::Normal(name) => Some(name.to_os_string()),
+        path::Component::ParentDir => Some(OsString::from("..")),
+        _ => None,
+      })
+      .collect()
+  };
+  let mut pending = components(path);
+  let mut resolved = path::PathBuf::new();
+  let mut symlinks = 0;
+  while let Some(name) = pending.pop() {
+    if name == ".." {
+      resolved.pop();
+      continue;
+    }
+    let candidate = resolved.join(&name);
+    let target = match read_link(&candidate)? {
+      Some(target) if follow || !pending.is_empty() => target,
+      _ => {
+        resolved = candidate;
+        continue;
+      }
+    };
+
+    symlinks += 1;
+    if symlinks > MAX_SYMLINKS {
+      return Err(too_many());
+    }
+    if target.has_root() {
+      resolved = path::PathBuf::new();
+    }
+    pending.extend(components(&target));
+  }
+  Ok(resolved)
+}
+
+/// A whiteout entry of a layer, hiding files of lower layers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum WhiteoutEntry {
+  /// `.wh.<name>`: the path `<name>` is removed.
+  Remove(path::PathBuf),
+  /// `.wh..wh..opq`: the content of the directory is replaced by the one of the layer.
+  Opaque(path::PathBuf),
+}
+
+/// Classify the normalized path of a layer entry as a whiteout, if it is one.
+pub(crate) fn parse_whiteout(path: &Path) -> Option<WhiteoutEntry> {
+  let (parent, name) = (path.parent()?, path.file_name()?.to_string_lossy());
+  if name == ".wh..wh..opq" {
+    return Some(WhiteoutEntry::Opaque(parent.to_path_buf()));
+  }
+  let hidden = name.strip_prefix(".wh.")?;
+  Some(WhiteoutEntry::Remove(parent.join(hidden)))
+}
+
+/// Set the owner of `target` from `header`, if ids are mapped.
+///
+/// `tar` itself takes care of `Ownership::Preserve`. As changing the owner clears the
+/// setuid and setgid bits, the mode `tar` applied is restored afterwards.
+fn set_ownership(target: &Path, header: &tar::Header, ownership: &Ownership) -> io::Result<()> {
+  let Ownership::Map { uids, gids } = ownership else {
+    return Ok(());
+  };
+  let uid = map_id(uids, header.uid()?);
+  let gid = map_id(gids, header.gid()?);
+  #[cfg(unix)]
+  if uid.is_some() || gid.is_some() {
+    let metadata = target.symlink_metadata()?;
+    std::os::unix::fs::lchown(target, uid, gid)?;
+    if !metadata.file_type().is_symlink() {
+      fs::set_permissions(target, metadata.permissions())?;
+    }
+  }
+  #[cfg(not(unix))]
+  let _ = (target, uid, gid);
+  Ok(())
+}
+
+fn map_id(mappings: &[IdMapping], id: u64) -> Option<u32> {
+  mappings.iter().find_map(|m| {
+    let offset = id.checked_sub(m.container_id.into())?;
+    if offset >= m.size.into() {
+      return None;
+    }
+    u32::try_from(u64::from(m.host_id) + offset).ok()
+  })
+}
+
+/// Unpack a layer, returning its diff_id if it was computed, i.e. if it is known or in overlay mode.
+fn _unpack_layer<R, P>(
+  mut blob: R,
+  media_type: Option<&str>,
+  diff_id: Option<&Digest>,
+  target_dir: &Path,
+  predicate: &P,
+  options: &UnpackOptions,
+  whiteouts: Whiteouts,
+) -> Result<Option<Digest>, RenderError>
 where
+  R: Read,
   P: Fn(&Path) -> bool,
 {
   if !target_dir.is_absolute() || !target_dir.exists() || !target_dir.is_dir() {
     return Err(RenderError::WrongTargetPath(target_dir.to_path_buf()));
   }
 
-  let decompressed_reader: Box<dyn Read + 'a> = {
-    let l = &layer.bytes;
-
-    match layer.media_type {
-      Some(ref media_type) if media_type.ends_with("+zstd") => Box::new(zstd::Decoder::new(l.as_slice())?),
-      _ => Box::new(gzip::Decoder::new(l.as_slice())?),
-    }
+  // Layers without a media type are assumed to be gzip-compressed, as Docker ones.
+  let compression = match media_type {
+    Some(media_type) => parse_media_type(media_type).layer_compression(),
+    None => Compression::Gzip,
+  };
+  let digester = match (diff_id, whiteouts) {
+    (Some(diff_id), _) => Some(Digester::new(diff_id.algorithm().clone())?),
+    (None, Whiteouts::Overlay) => Some(Digester::new(DigestAlgorithm::Sha256)?),
+    (None, Whiteouts::Apply) => None,
+  };
+  let mut reader = DigestReader {
+    inner: decompress(&mut blob, compression)?,
+    digester,
   };
 
   // Unpack layers
-  let mut archive = tar::Archive::new(decompressed_reader);
-  archive.set_preserve_permissions(true);
-  archive.set_unpack_xattrs(true);
+  {
+    let decompressed_reader: Box<dyn Read + '_> = Box::new(&mut reader);
+    let mut archive = tar::Archive::new(decompressed_reader);
+    archive.set_preserve_permissions(options.preserve_permissions);
+    archive.set_unpack_xattrs(options.unpack_xattrs);
+    archive.set_preserve_ownerships(options.ownership == Ownership::Preserve);
+
+    _unpack_archive(target_dir, &mut archive, predicate, options, whiteouts)?;
+  }
+
+  let mut got = None;
+  if reader.digester.is_some() {
+    // The end-of-archive padding is part of the diff_id too.
+    io::copy(&mut reader, &mut io::sink())?;
+    got = reader.digester.take().map(Digester::finalize);
+  }
+  if let (Some(expected), Some(got)) = (diff_id, &got) {
+    if got != expected {
+      return Err(RenderError::DiffIdMismatch {
+        expected: expected.clone(),
+        got: got.clone(),
+      });
+    }
+  }
+  drop(reader);
+  io::copy(&mut blob, &mut io::sink())?;
+
+  Ok(got)
+}
+
+/// Overlayfs whiteouts, see `unpack_layer_overlay_reader`.
+#[cfg(target_os = "linux")]
+mod overlay {
+  use std::{fs, io, path::Path};
+
+  use rustix::fs::{CWD, FileType, Mode, XattrFlags};
+
+  /// Mark the file `path` as removed, with a `0/0` character device.
+  pub(super) fn whiteout(path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    if path.symlink_metadata().is_ok() {
+      super::remove_whiteout(path.to_path_buf())?;
+    }
+    rustix::fs::mknodat(
+      CWD,
+      path,
+      FileType::CharacterDevice,
+      Mode::empty(),
+      rustix::fs::makedev(0, 0),
+    )?;
+    Ok(())
+  }
+
+  /// Mark the directory `path` as opaque, hiding the content of lower layers.
+  pub(super) fn opaque_dir(path: &Path, rootless: bool) -> io::Result<()> {
+    fs::create_dir_all(path)?;
+    let name = match rootless {
+      true => "user.overlay.opaque",
+      false => "trusted.overlay.opaque",
+    };
+    rustix::fs::lsetxattr(path, name, b"y", XattrFlags::empty())?;
+    Ok(())
+  }
+}
+
+/// Overlayfs is only available on Linux.
+#[cfg(not(target_os = "linux"))]
+mod overlay {
+  use std::{io, path::Path};
+
+  pub(super) fn whiteout(_path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+      io::ErrorKind::Unsupported,
+      "overlayfs whiteouts require Linux",
+    ))
+  }
+
+  pub(super) fn opaque_dir(_path: &Path, _rootless: bool) -> io::Result<()> {
+    Err(io::Error::new(
+      io::ErrorKind::Unsupported,
+      "overlayfs whiteouts require Linux",
+    ))
+  }
+}
 
-  _unpack_archive(target_dir, &mut archive, predicate)?;
+/// Remove the content of directory `dir` (relative to `dst`) from lower layers, keeping the
+/// paths of the current layer, `layer_paths`, and their parent directories.
+///
+/// Symlinks are resolved inside `dst`, see `resolve_in_root`.
+fn clear_opaque_dir(dst: &Path, dir: &Path, layer_paths: &BTreeSet<path::PathBuf>) -> io::Result<()> {
+  let abs_dir = resolve_in_root(dst, dir, true)?;
+  match abs_dir.symlink_metadata() {
+    Ok(metadata) if metadata.is_dir() => {}
+    _ => return Ok(()),
+  }
 
+  for child in fs::read_dir(&abs_dir)? {
+    let child = child?;
+    let rel_path = dir.join(child.file_name());
+    // Paths are ordered component-wise, so `rel_path` and its descendants come first from it on.
+    let provided = layer_paths
+      .range::<path::PathBuf, _>(&rel_path..)
+      .next()
+      .is_some_and(|p| p.starts_with(&rel_path));
+    if !provided {
+      remove_whiteout(child.path())?;
+    } else if child.file_type()?.is_dir() {
+      clear_opaque_dir(dst, &rel_path, layer_paths)?;
+    }
+  }
   Ok(())
 }
 
+/// Strip the leading `/` or `./` of a path from a tarball.
+pub(crate) fn normalize_path(path: &Path) -> path::PathBuf {
+  path
+    .components()
+    .filter(|c| !matches!(c, path::Component::RootDir | path::Component::CurDir))
+    .collect()
+}
+
+/// Reader hashing the (uncompressed) content it reads, to compute a diff_id.
+struct DigestReader<R> {
+  inner: R,
+  digester: Option<Digester>,
+}
+
+impl<R: Read> Read for DigestReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let n = self.inner.read(buf)?;
+    if let Some(digester) = &mut self.digester {
+      digester.update(&buf[..n]);
+    }
+    Ok(n)
+  }
+}
+
+/// Compute the chain IDs of an ordered list of layers, from their diff_ids.
+///
+/// The chain ID of a layer identifies the filesystem obtained by applying it and all
+/// the layers below it, see
+/// <https://github.com/opencontainers/image-spec/blob/main/config.md#layer-chainid>.
+/// Layers must be ordered starting with the base image first.
+pub fn chain_ids(diff_ids: &[Digest]) -> Vec<Digest> {
+  let mut chain_ids: Vec<Digest> = Vec::with_capacity(diff_ids.len());
+  for diff_id in diff_ids {
+    let chain_id = match chain_ids.last() {
+      Some(parent) => Digest::sha256(format!("{parent} {diff_id}").as_bytes()),
+      None => diff_id.clone(),
+    };
+    chain_ids.push(chain_id);
+  }
+  chain_ids
+}
+
+/// Compute the chain ID of the topmost layer, identifying the whole image filesystem.
+///
+/// See `chain_ids`.
+pub fn chain_id(diff_ids: &[Digest]) -> Option<Digest> {
+  chain_ids(diff_ids).pop()
+}
+
+/// Wrap `reader` to decompress the content of a layer on the fly.
+pub(crate) fn decompress<'a, R: Read + 'a>(reader: R, compression: Compression) -> io::Result<Box<dyn Read + 'a>> {
+  Ok(match compression {
+    Compression::Zstd => Box::new(zstd::Decoder::new(reader)?),
+    Compression::Gzip => Box::new(gzip::Decoder::new(reader)?),
+    Compression::Uncompressed => Box::new(reader),
+  })
+}
+
 // Whiteout files in archive may not exist on filesystem if they were
 // filtered out via filter_unpack.  If not found, that's ok and the
 // error is non-fatal.  Otherwise still return error for other
 // failures.
 fn remove_whiteout(path: path::PathBuf) -> io::Result<()> {
-  let res = fs::remove_dir_all(path);
+  let res = match path.symlink_metadata() {
+    Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+    _ => fs::remove_file(path),
+  };
 
   match res {
     Ok(_) => res,
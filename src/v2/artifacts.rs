@@ -0,0 +1,177 @@
+//! OCI artifacts, stored as image manifests with an empty config.
+//!
+//! Artifacts follow the conventions of ORAS: each file is a layer, named by its
+//! `org.opencontainers.image.title` annotation.
+
+use std::{
+  collections::{BTreeMap, HashSet},
+  path::{Path, PathBuf},
+};
+
+use log::trace;
+
+use crate::{
+  errors::Result,
+  mediatypes::MediaTypes,
+  v2::{
+    manifest::{Descriptor, ManifestSchema2Spec},
+    *,
+  },
+};
+
+/// Annotation holding the file name of an artifact layer.
+pub const ANNOTATION_TITLE: &str = "org.opencontainers.image.title";
+
+/// Content of the empty descriptor, used as config of artifacts.
+const EMPTY_JSON: &[u8] = b"{}";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ArtifactError {
+  #[error("invalid artifact file name {0:?}")]
+  InvalidTitle(String),
+  #[error("duplicate artifact file name {0:?}")]
+  DuplicateTitle(String),
+  #[error("artifact {0} not found")]
+  NotFound(String),
+  #[error("manifest with media type {0} is not an artifact")]
+  NotAnArtifact(MediaTypes),
+}
+
+/// An OCI artifact to push, made of files with their media types.
+#[derive(Debug)]
+pub struct Artifact {
+  artifact_type: String,
+  files: Vec<(PathBuf, String)>,
+  annotations: BTreeMap<String, String>,
+  subject: Option<Descriptor>,
+}
+
+impl Artifact {
+  /// Create an empty artifact of the given type.
+  pub fn new(artifact_type: &str) -> Self {
+    Self {
+      artifact_type: artifact_type.to_string(),
+      files: Vec::new(),
+      annotations: BTreeMap::new(),
+      subject: None,
+    }
+  }
+
+  /// Add a file, stored as a layer of the given media type and named after the file name.
+  pub fn file<P: AsRef<Path>>(mut self, path: P, media_type: &str) -> Self {
+    self.files.push((path.as_ref().to_path_buf(), media_type.to_string()));
+    self
+  }
+
+  /// Add an annotation to the artifact manifest.
+  pub fn annotation(mut self, key: &str, value: &str) -> Self {
+    self.annotations.insert(key.to_string(), value.to_string());
+    self
+  }
+
+  /// Attach the artifact to another manifest, e.g. an image it signs or describes.
+  pub fn subject(mut self, subject: Descriptor) -> Self {
+    self.subject = Some(subject);
+    self
+  }
+}
+
+impl Client {
+  /// Push an artifact to repository `name`, optionally tagging it.
+  ///
+  /// Files are uploaded as blobs, then an OCI image manifest with an empty config
+  /// is written, by `tag` or else by digest. If the artifact has a subject and the
+  /// registry does not support the referrers API, the referrers tag of the subject
+  /// is updated as well. Returns the digest of the artifact manifest.
+  pub async fn push_artifact(&self, name: &str, tag: Option<&str>, artifact: &Artifact) -> Result<Digest> {
+    let empty = Descriptor::new(
+      &MediaTypes::OciEmpty.to_string(),
+      Digest::sha256(EMPTY_JSON),
+      EMPTY_JSON.len() as u64,
+    );
+    self.push_blob(name, &empty.digest, EMPTY_JSON.to_vec()).await?;
+
+    let mut builder = ManifestSchema2Spec::builder()
+      .artifact_type(&artifact.artifact_type)
+      .config(empty.clone());
+
+    let mut titles = HashSet::new();
+    for (path, media_type) in &artifact.files {
+      let title = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| ArtifactError::InvalidTitle(path.display().to_string()))?;
+      if !titles.insert(title) {
+        return Err(ArtifactError::DuplicateTitle(title.to_string()).into());
+      }
+
+      let mut layer = self.push_blob_file(name, path, media_type).await?;
+      layer.annotations = Some(BTreeMap::from([(ANNOTATION_TITLE.to_string(), title.to_string())]));
+      trace!("Pushed artifact file {} as {}", path.display(), layer.digest);
+      builder = builder.layer(layer);
+    }
+    // Manifests should have at least one layer, the empty descriptor is recommended otherwise.
+    if artifact.files.is_empty() {
+      builder = builder.layer(empty);
+    }
+
+    for (key, value) in &artifact.annotations {
+      builder = builder.annotation(key, value);
+    }
+    if let Some(subject) = &artifact.subject {
+      builder = builder.subject(subject.clone());
+    }
+
+    let manifest = builder.build()?.encode()?;
+    let reference = tag.map_or_else(|| manifest.digest.to_string(), str::to_string);
+    let (digest, subject_processed) = self.put_manifest_response(name, &reference, &manifest).await?;
+
+    if let (Some(subject), false) = (&artifact.subject, subject_processed) {
+      let mut referrer = manifest.descriptor();
+      referrer.artifact_type = Some(artifact.artifact_type.clone());
+      referrer.annotations = Some(artifact.annotations.clone()).filter(|a| !a.is_empty());
+      self.add_referrer_to_tag(name, &subject.digest, referrer).await?;
+    }
+
+    Ok(digest)
+  }
+
+  /// Pull the files of an artifact into `target_dir`, which must exist.
+  ///
+  /// Layers are written to files named after their title annotation; layers without
+  /// a title are skipped. Each file is streamed to disk and only moved into place once
+  /// verified, see `download_blob_to`. Returns the paths of the written files.
+  pub async fn pull_artifact(&self, name: &str, reference: &str, target_dir: &Path) -> Result<Vec<PathBuf>> {
+    let (media_type, raw) = self
+      .get_raw_manifest(name, reference, &[MediaTypes::OciImageManifest])
+      .await?
+      .ok_or_else(|| ArtifactError::NotFound(reference.to_string()))?;
+    if media_type != MediaTypes::OciImageManifest {
+      return Err(ArtifactError::NotAnArtifact(media_type).into());
+    }
+    let manifest: ManifestSchema2Spec = serde_json::from_slice(&raw)?;
+
+    let mut paths = Vec::new();
+    for layer in manifest.layers() {
+      let Some(title) = layer.annotation(ANNOTATION_TITLE) else {
+        continue;
+      };
+      // Titles come from the registry, so they must not escape the target directory.
+      let path = target_dir.join(title);
+      if title.is_empty()
+        || path.parent() != Some(target_dir)
+        || path.file_name().and_then(|n| n.to_str()) != Some(title)
+      {
+        return Err(ArtifactError::InvalidTitle(title.to_string()).into());
+      }
+      if paths.contains(&path) {
+        return Err(ArtifactError::DuplicateTitle(title.to_string()).into());
+      }
+
+      self.download_blob_to(name, layer, &path).await?;
+      paths.push(path);
+    }
+
+    Ok(paths)
+  }
+}
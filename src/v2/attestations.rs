@@ -0,0 +1,100 @@
+//! In-toto attestations, as attached to images by BuildKit.
+//!
+//! BuildKit stores provenance and SBOM attestations as extra image index entries
+//! (with an `unknown/unknown` platform), each pointing to an image manifest whose
+//! layers are in-toto statements about the attested image.
+
+use std::collections::BTreeMap;
+
+use log::trace;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  errors::Result,
+  mediatypes::MediaTypes,
+  v2::{manifest::ManifestList, *},
+};
+
+/// Annotation holding the predicate type of an attestation layer.
+pub const ANNOTATION_PREDICATE_TYPE: &str = "in-toto.io/predicate-type";
+
+#[derive(Debug, thiserror::Error)]
+pub enum AttestationError {
+  #[error("attestation manifest {0} not found")]
+  ManifestNotFound(Digest),
+}
+
+/// An in-toto statement.
+///
+/// Specification is at <https://github.com/in-toto/attestation/blob/main/spec/v1/statement.md>.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Statement {
+  #[serde(rename = "_type")]
+  pub statement_type: String,
+  pub subject: Vec<Subject>,
+  #[serde(rename = "predicateType")]
+  pub predicate_type: String,
+  #[serde(default)]
+  pub predicate: serde_json::Value,
+}
+
+/// Artifact an in-toto statement is about.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Subject {
+  pub name: String,
+  /// Digests of the subject, keyed by algorithm.
+  pub digest: BTreeMap<String, String>,
+}
+
+impl Statement {
+  /// Whether the predicate is an SPDX software bill of materials.
+  pub fn is_spdx(&self) -> bool {
+    self.predicate_type.starts_with("https://spdx.dev/Document")
+  }
+
+  /// Whether the predicate is a SLSA build provenance, of any version.
+  pub fn is_slsa_provenance(&self) -> bool {
+    self.predicate_type.starts_with("https://slsa.dev/provenance/")
+  }
+
+  /// Whether `digest` is one of the subjects of this statement.
+  pub fn has_subject(&self, digest: &Digest) -> bool {
+    self
+      .subject
+      .iter()
+      .any(|s| s.digest.get(digest.algorithm().as_str()).map(String::as_str) == Some(digest.encoded()))
+  }
+}
+
+impl Client {
+  /// Fetch the in-toto statements attached to `image` by the attestation manifests of `index`.
+  ///
+  /// `image` is the digest of a platform image of `index`, as found by `ManifestList::get_platform`.
+  /// Attestation layers which are not plain in-toto statements are skipped.
+  pub async fn get_attestations(&self, name: &str, index: &ManifestList, image: &Digest) -> Result<Vec<Statement>> {
+    let mut statements = Vec::new();
+    for attestation in index
+      .attestations()
+      .filter(|a| a.reference_digest().as_ref() == Some(image))
+    {
+      let (_, raw) = self
+        .get_raw_manifest(name, &attestation.digest.to_string(), &[MediaTypes::OciImageManifest])
+        .await?
+        .ok_or_else(|| AttestationError::ManifestNotFound(attestation.digest.clone()))?;
+      let manifest: manifest::ManifestSchema2Spec = serde_json::from_slice(&raw)?;
+
+      for layer in manifest.layers() {
+        if layer.media_type != MediaTypes::InTotoStatement.to_string() {
+          trace!(
+            "Skipping attestation layer {} of type {}",
+            layer.digest, layer.media_type
+          );
+          continue;
+        }
+        let (blob, _) = self.get_blob_from_descriptor(name, layer).await?;
+        statements.push(serde_json::from_slice(&blob)?);
+      }
+    }
+    Ok(statements)
+  }
+}
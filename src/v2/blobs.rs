@@ -1,22 +1,34 @@
-use std::pin::Pin;
+use std::{
+  fs, io,
+  path::{Path, PathBuf},
+  pin::{Pin, pin},
+};
 
 use bytes::Bytes;
 use futures::{
-  stream::Stream,
+  future::Either,
+  stream::{Stream, StreamExt, TryStreamExt},
   task::{Context, Poll},
 };
-use log::{error, trace};
+use log::{debug, error, trace};
 use pin_project::pin_project;
 use reqwest::{self, Method, StatusCode};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::{ReaderStream, StreamReader};
 
 use crate::{
   errors::{Error, Result},
-  v2::{manifest::Layer, *},
+  mediatypes::MediaTypes,
+  v2::{
+    cache::{BlobCache, CacheWriter},
+    manifest::Descriptor,
+    *,
+  },
 };
 
 impl Client {
   /// Check if a blob exists.
-  pub async fn has_blob(&self, name: &str, digest: &str) -> Result<bool> {
+  pub async fn has_blob(&self, name: &str, digest: &Digest) -> Result<bool> {
     let url = {
       let ep = format!("{}/v2/{}/blobs/{}", self.base_url, name, digest);
       reqwest::Url::parse(&ep)?
@@ -32,19 +44,56 @@ impl Client {
     }
   }
 
-  pub async fn get_blob_response(&self, name: &str, digest: &str) -> Result<BlobResponse> {
-    let layer = Layer {
-      digest: digest.to_string(),
-      media_type: "application/vnd.docker.image.rootfs.diff.tar.gzip".to_string(),
-    };
-    self.get_blob_response_from_layer(name, &layer).await
+  pub async fn get_blob_response(&self, name: &str, digest: &Digest) -> Result<BlobResponse> {
+    let descriptor = Descriptor::new(&MediaTypes::ImageLayerTgz.to_string(), digest.clone(), 0);
+    self.get_blob_response_from_descriptor(name, &descriptor).await
   }
 
-  pub async fn get_blob_response_from_layer(&self, name: &str, layer: &Layer) -> Result<BlobResponse> {
-    let ep = format!("{}/v2/{}/blobs/{}", self.base_url, name, &layer.digest);
-    let url = reqwest::Url::parse(&ep)?;
+  /// Get the response for the blob described by `descriptor`.
+  ///
+  /// The descriptor's digest and (non-zero) size are enforced while the blob is read.
+  ///
+  /// If the client has a `BlobCache`, the blob is read from it when cached, and added to
+  /// it once downloaded and verified otherwise.
+  pub async fn get_blob_response_from_descriptor(&self, name: &str, descriptor: &Descriptor) -> Result<BlobResponse> {
+    let size = match descriptor.size {
+      0 => None,
+      size => Some(size),
+    };
 
-    println!("get_blob_response_from_layer: {ep}");
+    if let Some(cache) = &self.cache {
+      // The cache is only an optimization, the registry is asked on any cache failure.
+      let digest = descriptor.digest.clone();
+      let cached = cache
+        .blocking(move |cache| {
+          let file = cache.open(&digest)?;
+          file.map(|f| Ok((f.metadata()?.len(), f))).transpose()
+        })
+        .await
+        .unwrap_or_else(|e| {
+          debug!("Failed to read cached blob {}: {e}", descriptor.digest);
+          None
+        });
+      if let Some((len, file)) = cached {
+        if size.is_none_or(|size| size == len) {
+          return Ok(BlobResponse::new(
+            BlobSource::Cached(tokio::fs::File::from_std(file), len),
+            ContentDigest::new(descriptor.digest.clone())?,
+            size,
+            descriptor.media_type.clone(),
+            self.cache.clone(),
+          ));
+        }
+        debug!("Cached blob {} has an unexpected size, removing it", descriptor.digest);
+        let digest = descriptor.digest.clone();
+        if let Err(e) = cache.blocking(move |cache| cache.remove(&digest)).await {
+          debug!("Failed to remove cached blob {}: {e}", descriptor.digest);
+        }
+      }
+    }
+
+    let ep = format!("{}/v2/{}/blobs/{}", self.base_url, name, &descriptor.digest);
+    let url = reqwest::Url::parse(&ep)?;
 
     let resp = self.build_reqwest(Method::GET, url.clone()).send().await?;
 
@@ -58,10 +107,17 @@ impl Client {
         } else {
           trace!("Receiving a blob");
         }
+        if let (Some(expected), Some(got)) = (size, resp.content_length()) {
+          if got > expected {
+            return Err(Error::BlobSize { expected, got });
+          }
+        }
         Ok(BlobResponse::new(
-          resp,
-          ContentDigest::try_new(&layer.digest)?,
-          layer.media_type.clone(),
+          BlobSource::Remote(resp),
+          ContentDigest::new(descriptor.digest.clone())?,
+          size,
+          descriptor.media_type.clone(),
+          self.cache.clone(),
         ))
       }
       Err(_) if status.is_client_error() => Err(ApiErrors::from(resp).await),
@@ -73,55 +129,528 @@ impl Client {
     }
   }
 
+  /// Get the response for the blob described by `layer`.
+  #[deprecated(note = "use `get_blob_response_from_descriptor`")]
+  pub async fn get_blob_response_from_layer(&self, name: &str, layer: &Descriptor) -> Result<BlobResponse> {
+    self.get_blob_response_from_descriptor(name, layer).await
+  }
+
   /// Retrieve blob.
-  pub async fn get_blob(&self, name: &str, digest: &str) -> Result<Vec<u8>> {
+  pub async fn get_blob(&self, name: &str, digest: &Digest) -> Result<Vec<u8>> {
     self.get_blob_response(name, digest).await?.bytes().await
   }
 
-  /// Retrieve blob from `Layer` struct
-  pub async fn get_blob_from_layer(&self, name: &str, layer: &Layer) -> Result<(Vec<u8>, String)> {
-    let blob_response = self.get_blob_response_from_layer(name, layer).await?;
+  /// Retrieve blob described by a `Descriptor`, along with its media type.
+  pub async fn get_blob_from_descriptor(&self, name: &str, descriptor: &Descriptor) -> Result<(Vec<u8>, String)> {
+    let blob_response = self.get_blob_response_from_descriptor(name, descriptor).await?;
     let media_type = blob_response.media_type.clone();
     Ok((blob_response.bytes().await?, media_type))
   }
 
+  /// Retrieve blob from a layer `Descriptor`, along with its media type.
+  #[deprecated(note = "use `get_blob_from_descriptor`")]
+  pub async fn get_blob_from_layer(&self, name: &str, layer: &Descriptor) -> Result<(Vec<u8>, String)> {
+    self.get_blob_from_descriptor(name, layer).await
+  }
+
+  /// Upload a blob, unless the registry already has it.
+  ///
+  /// The blob is uploaded in a single request, after checking it matches `digest`.
+  pub async fn push_blob(&self, name: &str, digest: &Digest, blob: Vec<u8>) -> Result<()> {
+    digest.verify(&blob)?;
+    let size = blob.len() as u64;
+    self.upload_blob(name, digest, size, blob.into()).await
+  }
+
+  /// Upload the file at `path` as a blob with `media_type`, unless the registry already has it.
+  ///
+  /// The file is hashed, then streamed to the registry in a single request. Returns the
+  /// descriptor of the blob.
+  pub(crate) async fn push_blob_file(&self, name: &str, path: &Path, media_type: &str) -> Result<Descriptor> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut digester = Digester::new(DigestAlgorithm::Sha256)?;
+    let mut size = 0;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+      match file.read(&mut buf).await? {
+        0 => break,
+        n => {
+          digester.update(&buf[..n]);
+          size += n as u64;
+        }
+      }
+    }
+    let descriptor = Descriptor::new(media_type, digester.finalize(), size);
+
+    file.rewind().await?;
+    let body = reqwest::Body::wrap_stream(ReaderStream::new(file));
+    self.upload_blob(name, &descriptor.digest, size, body).await?;
+    Ok(descriptor)
+  }
+
+  async fn upload_blob(&self, name: &str, digest: &Digest, size: u64, body: reqwest::Body) -> Result<()> {
+    if self.has_blob(name, digest).await? {
+      trace!("Blob {digest} already exists, skipping upload");
+      return Ok(());
+    }
+
+    let url = {
+      let ep = format!("{}/v2/{}/blobs/uploads/", self.base_url, name);
+      reqwest::Url::parse(&ep)?
+    };
+    let resp = self.build_reqwest(Method::POST, url.clone()).send().await?;
+    trace!("POST {} status: {}", resp.url(), resp.status());
+    let resp = check_status(resp, StatusCode::ACCEPTED).await?;
+
+    // The upload location may be relative to the registry.
+    let location = resp
+      .headers()
+      .get(reqwest::header::LOCATION)
+      .ok_or(Error::MissingHeader("Location"))?
+      .to_str()?;
+    let mut url = url.join(location)?;
+    url.query_pairs_mut().append_pair("digest", &digest.to_string());
+
+    let resp = self
+      .build_reqwest(Method::PUT, url)
+      .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+      .header(reqwest::header::CONTENT_LENGTH, size)
+      .body(body)
+      .send()
+      .await?;
+    trace!("PUT {} status: {}", resp.url(), resp.status());
+    check_status(resp, StatusCode::CREATED).await?;
+    Ok(())
+  }
+
+  /// Read the blob described by `descriptor` with `f`, on a blocking thread.
+  ///
+  /// Whatever `f` leaves unread is drained afterwards, so that the digest and size of
+  /// the blob are always verified.
+  pub(crate) async fn read_blob_blocking<T, F>(&self, name: &str, descriptor: &Descriptor, f: F) -> Result<T>
+  where
+    F: FnOnce(&mut dyn io::Read) -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+  {
+    let stream = self
+      .get_blob_response_from_descriptor(name, descriptor)
+      .await?
+      .bytes_stream();
+    let mut reader = BlockingBlobReader::new(stream);
+    let res = tokio::task::spawn_blocking(move || {
+      let res = f(&mut reader)?;
+      io::copy(&mut reader, &mut io::sink())?;
+      Ok::<_, io::Error>(res)
+    })
+    .await
+    .map_err(io::Error::other)??;
+    Ok(res)
+  }
+
   /// Retrieve blob stream.
-  pub async fn get_blob_stream(&self, name: &str, digest: &str) -> Result<impl Stream<Item = Result<Vec<u8>>>> {
+  pub async fn get_blob_stream(&self, name: &str, digest: &Digest) -> Result<impl Stream<Item = Result<Vec<u8>>>> {
     Ok(self.get_blob_response(name, digest).await?.stream())
   }
+
+  /// Retrieve the bytes `start..=end` of a blob, or from `start` to its end if `end` is `None`.
+  ///
+  /// The registry must answer with partial content, whose `Content-Range` matches the
+  /// requested range. A range alone cannot be verified against the blob digest.
+  pub async fn get_blob_range(&self, name: &str, digest: &Digest, start: u64, end: Option<u64>) -> Result<Vec<u8>> {
+    let resp = self.send_blob_request(name, digest, Some((start, end))).await?;
+    let status = resp.status();
+    if status != StatusCode::PARTIAL_CONTENT {
+      error!("Received unexpected HTTP status '{status}' for a range request");
+      return Err(Error::UnexpectedHttpStatus(status));
+    }
+
+    let content_range = ContentRange::from_response(&resp)?;
+    if content_range.start != start || end.is_some_and(|end| content_range.end != end) {
+      return Err(Error::InvalidContentRange(format!(
+        "requested bytes {start}-{}, got {content_range}",
+        end.map(|e| e.to_string()).unwrap_or_default()
+      )));
+    }
+
+    let blob = resp.bytes().await?.to_vec();
+    let expected = content_range.end - content_range.start + 1;
+    if blob.len() as u64 != expected {
+      return Err(Error::BlobSize {
+        expected,
+        got: blob.len() as u64,
+      });
+    }
+    Ok(blob)
+  }
+
+  /// Download the blob described by `descriptor` to `path`.
+  ///
+  /// The blob is streamed to a temporary file in the directory of `path`, which is synced
+  /// to disk and atomically renamed to `path` once its digest and declared size are
+  /// verified. On failure the temporary file is removed, and any existing file at `path`
+  /// is left untouched.
+  pub async fn download_blob_to(&self, name: &str, descriptor: &Descriptor, path: &Path) -> Result<()> {
+    let stream = self.get_blob_response_from_descriptor(name, descriptor).await?.stream();
+    write_blob_stream(stream, path).await
+  }
+
+  /// Download the blob described by `descriptor` to `path`, resuming any previous attempt.
+  ///
+  /// Content is written to `<path>.partial`, which is moved to `path` once complete and
+  /// verified. If the partial file exists, its content is rehashed and only the rest of
+  /// the blob is requested; registries ignoring the range restart the download from
+  /// scratch. Connection drops while downloading are resumed up to 3 times.
+  pub async fn download_blob_resumable(&self, name: &str, descriptor: &Descriptor, path: &Path) -> Result<()> {
+    let partial = partial_path(path);
+    let mut attempts = 0;
+    loop {
+      match self.resume_blob_download(name, descriptor, &partial).await {
+        Ok(()) => break,
+        Err(Error::Reqwest(e)) if attempts < RESUME_ATTEMPTS => {
+          attempts += 1;
+          debug!("Download of blob {} interrupted, resuming: {e}", descriptor.digest);
+        }
+        Err(e) => return Err(e),
+      }
+    }
+    tokio::fs::rename(&partial, path).await?;
+    Ok(())
+  }
+
+  async fn resume_blob_download(&self, name: &str, descriptor: &Descriptor, partial: &Path) -> Result<()> {
+    let size = match descriptor.size {
+      0 => None,
+      size => Some(size),
+    };
+    let mut file = tokio::fs::OpenOptions::new()
+      .create(true)
+      .append(true)
+      .read(true)
+      .open(partial)
+      .await?;
+    let mut offset = file.metadata().await?.len();
+    if size.is_some_and(|size| offset > size) {
+      file.set_len(0).await?;
+      offset = 0;
+    }
+
+    // Rehash what was downloaded so far, the digest covers the whole blob.
+    let mut digest = ContentDigest::new(descriptor.digest.clone())?;
+    let mut prefix = tokio::fs::File::open(partial).await?.take(offset);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+      match prefix.read(&mut buf).await? {
+        0 => break,
+        n => digest.update(&buf[..n]),
+      }
+    }
+
+    if offset == 0 || Some(offset) != size {
+      let range = (offset > 0).then_some((offset, None));
+      let resp = self.send_blob_request(name, &descriptor.digest, range).await?;
+      let resp = match resp.status() {
+        StatusCode::PARTIAL_CONTENT => {
+          let content_range = ContentRange::from_response(&resp)?;
+          if content_range.start != offset || size.is_some_and(|size| content_range.total.is_some_and(|t| t != size)) {
+            return Err(Error::InvalidContentRange(format!(
+              "requested bytes {offset}-, got {content_range}"
+            )));
+          }
+          trace!("Resuming download of blob {} at {offset}", descriptor.digest);
+          Some(resp)
+        }
+        StatusCode::OK => {
+          if offset > 0 {
+            trace!("Range ignored, restarting download of blob {}", descriptor.digest);
+            file.set_len(0).await?;
+            offset = 0;
+            digest = ContentDigest::new(descriptor.digest.clone())?;
+          }
+          Some(resp)
+        }
+        // The partial file already holds the whole blob.
+        StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => None,
+        status => {
+          error!("Received unexpected HTTP status '{status}'");
+          return Err(Error::UnexpectedHttpStatus(status));
+        }
+      };
+
+      if let Some(resp) = resp {
+        let mut stream = resp.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+          let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+              // Complete pending writes, the next attempt resumes from the end of the file.
+              file.flush().await?;
+              return Err(e.into());
+            }
+          };
+          offset += chunk.len() as u64;
+          if let Some(expected) = size.filter(|size| offset > *size) {
+            file.set_len(0).await?;
+            return Err(Error::BlobSize { expected, got: offset });
+          }
+          file.write_all(&chunk).await?;
+          digest.update(&chunk);
+        }
+        file.flush().await?;
+      }
+    }
+
+    if let Some(expected) = size.filter(|size| offset != *size) {
+      file.set_len(0).await?;
+      return Err(Error::BlobSize { expected, got: offset });
+    }
+    if let Err(e) = digest.verify() {
+      // Corrupted content cannot be resumed from, start over next time.
+      file.set_len(0).await?;
+      return Err(e.into());
+    }
+    Ok(())
+  }
+
+  /// Send a GET request for a blob, for the given `(start, end)` byte range if any.
+  ///
+  /// Successful and range not satisfiable responses are returned as-is.
+  async fn send_blob_request(
+    &self,
+    name: &str,
+    digest: &Digest,
+    range: Option<(u64, Option<u64>)>,
+  ) -> Result<reqwest::Response> {
+    let ep = format!("{}/v2/{}/blobs/{}", self.base_url, name, digest);
+    let url = reqwest::Url::parse(&ep)?;
+
+    let mut request = self.build_reqwest(Method::GET, url);
+    if let Some((start, end)) = range {
+      let end = end.map(|e| e.to_string()).unwrap_or_default();
+      request = request.header(reqwest::header::RANGE, format!("bytes={start}-{end}"));
+    }
+    let resp = request.send().await?;
+
+    let status = resp.status();
+    trace!("GET {} status: {}", resp.url(), status);
+    match status {
+      s if s.is_success() || s == StatusCode::RANGE_NOT_SATISFIABLE => Ok(resp),
+      s if s.is_client_error() => Err(ApiErrors::from(resp).await),
+      s if s.is_server_error() => Err(Error::Server { status }),
+      _ => {
+        error!("Received unexpected HTTP status '{status}'");
+        Err(Error::UnexpectedHttpStatus(status))
+      }
+    }
+  }
+}
+
+/// Write a verifying blob `stream` to `path` atomically, see `Client::download_blob_to`.
+pub(crate) async fn write_blob_stream<S>(stream: S, path: &Path) -> Result<()>
+where
+  S: Stream<Item = Result<Vec<u8>>>,
+{
+  let dir = match path.parent() {
+    Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+    _ => PathBuf::from("."),
+  };
+  let path = path.to_path_buf();
+  // The temporary file is removed if anything fails before it is moved into place.
+  let tmp_dir = dir.clone();
+  let file = tokio::task::spawn_blocking(move || {
+    tempfile::Builder::new()
+      .prefix(".download-")
+      .suffix(".tmp")
+      .tempfile_in(tmp_dir)
+  })
+  .await
+  .map_err(io::Error::other)??;
+
+  let mut writer = tokio::fs::File::from_std(file.as_file().try_clone()?);
+  let mut stream = pin!(stream);
+  while let Some(chunk) = stream.next().await {
+    writer.write_all(&chunk?).await?;
+  }
+  writer.sync_all().await?;
+
+  trace!("Moving blob to {}", path.display());
+  tokio::task::spawn_blocking(move || {
+    file.persist(&path).map_err(|e| e.error)?;
+    // Persist the rename itself.
+    #[cfg(unix)]
+    fs::File::open(&dir)?.sync_all()?;
+    Ok::<_, io::Error>(())
+  })
+  .await
+  .map_err(io::Error::other)??;
+  Ok(())
+}
+
+/// Number of times an interrupted download is resumed by `Client::download_blob_resumable`.
+const RESUME_ATTEMPTS: usize = 3;
+
+/// Path of the partial file where `path` is downloaded to.
+fn partial_path(path: &Path) -> PathBuf {
+  let mut partial = path.as_os_str().to_owned();
+  partial.push(".partial");
+  PathBuf::from(partial)
+}
+
+/// Parsed `Content-Range` header of a partial content response.
+#[derive(Debug)]
+struct ContentRange {
+  start: u64,
+  end: u64,
+  total: Option<u64>,
+}
+
+impl ContentRange {
+  fn from_response(resp: &reqwest::Response) -> Result<Self> {
+    let header = resp
+      .headers()
+      .get(reqwest::header::CONTENT_RANGE)
+      .ok_or(Error::MissingHeader("Content-Range"))?
+      .to_str()?;
+    header
+      .parse()
+      .map_err(|_| Error::InvalidContentRange(header.to_string()))
+  }
+}
+
+impl std::str::FromStr for ContentRange {
+  type Err = ();
+
+  // Format is `bytes <start>-<end>/<total or *>`, see RFC 9110.
+  fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+    let (range, total) = s.strip_prefix("bytes ").and_then(|r| r.split_once('/')).ok_or(())?;
+    let (start, end) = range.split_once('-').ok_or(())?;
+    let (start, end) = (start.parse().map_err(|_| ())?, end.parse().map_err(|_| ())?);
+    let total = match total {
+      "*" => None,
+      total => Some(total.parse().map_err(|_| ())?),
+    };
+    if end < start || total.is_some_and(|total| end >= total) {
+      return Err(());
+    }
+    Ok(Self { start, end, total })
+  }
+}
+
+impl std::fmt::Display for ContentRange {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "bytes {}-{}/", self.start, self.end)?;
+    match self.total {
+      Some(total) => write!(f, "{total}"),
+      None => write!(f, "*"),
+    }
+  }
+}
+
+/// Check that `resp` has the `expected` status, turning registry errors into `Error`s.
+pub(crate) async fn check_status(resp: reqwest::Response, expected: StatusCode) -> Result<reqwest::Response> {
+  let status = resp.status();
+  match status {
+    s if s == expected => Ok(resp),
+    s if s.is_client_error() => Err(ApiErrors::from(resp).await),
+    s if s.is_server_error() => Err(Error::Server { status }),
+    _ => {
+      error!("Received unexpected HTTP status '{status}'");
+      Err(Error::UnexpectedHttpStatus(status))
+    }
+  }
 }
 
 #[derive(Debug)]
 pub struct BlobResponse {
-  resp: reqwest::Response,
+  source: BlobSource,
   digest: ContentDigest,
+  size: Option<u64>,
   media_type: String,
+  cache: Option<BlobCache>,
+}
+
+#[derive(Debug)]
+enum BlobSource {
+  Remote(reqwest::Response),
+  /// A blob from the `BlobCache`, with its length.
+  Cached(tokio::fs::File, u64),
 }
 
 impl BlobResponse {
-  fn new(resp: reqwest::Response, digest: ContentDigest, media_type: String) -> Self {
+  fn new(
+    source: BlobSource,
+    digest: ContentDigest,
+    size: Option<u64>,
+    media_type: String,
+    cache: Option<BlobCache>,
+  ) -> Self {
     Self {
-      resp,
+      source,
       digest,
+      size,
       media_type,
+      cache,
     }
   }
 
   /// Get size of the blob.
   /// This method can be useful to render progress bar when downloading a blob.
+  ///
+  /// The size declared by the descriptor is preferred over the `Content-Length` header.
   pub fn size(&self) -> Option<u64> {
-    self.resp.content_length()
+    self.size.or(match &self.source {
+      BlobSource::Remote(resp) => resp.content_length(),
+      BlobSource::Cached(_, len) => Some(*len),
+    })
+  }
+
+  /// Whether the blob is read from the local `BlobCache` rather than the registry.
+  pub fn is_cached(&self) -> bool {
+    matches!(self.source, BlobSource::Cached(..))
   }
 
   /// Retrieve content of the blob.
   pub async fn bytes(self) -> Result<Vec<u8>> {
-    let blob = self.resp.bytes().await?.to_vec();
+    let cached = self.is_cached();
+    let blob = match self.source {
+      BlobSource::Remote(resp) => Vec::from(resp.bytes().await?),
+      BlobSource::Cached(mut file, len) => {
+        let mut blob = Vec::with_capacity(len as usize);
+        file.read_to_end(&mut blob).await?;
+        blob
+      }
+    };
 
+    if let Some(expected) = self.size {
+      let got = blob.len() as u64;
+      if got != expected {
+        return Err(Error::BlobSize { expected, got });
+      }
+    }
+
+    let expected = self.digest.expected().clone();
     let mut digest = self.digest;
     digest.update(&blob);
-    digest.verify()?;
-
-    Ok(blob)
+    match (digest.verify(), self.cache) {
+      (Err(e), Some(cache)) if cached => {
+        debug!("Cached blob {expected} is corrupted, removing it");
+        let digest = expected.clone();
+        if let Err(e) = cache.blocking(move |cache| cache.remove(&digest)).await {
+          debug!("Failed to remove cached blob {expected}: {e}");
+        }
+        Err(e.into())
+      }
+      (Err(e), _) => Err(e.into()),
+      (Ok(()), Some(cache)) if !cached => {
+        // Shared with the blocking thread, the buffer is handed back without a copy.
+        let blob = Bytes::from(blob);
+        let (digest, cached_blob) = (expected.clone(), blob.clone());
+        if let Err(e) = cache.blocking(move |cache| cache.insert(&digest, &cached_blob)).await {
+          debug!("Failed to cache blob {expected}: {e}");
+        }
+        Ok(Vec::from(blob))
+      }
+      (Ok(()), _) => Ok(blob),
+    }
   }
 
   /// Get MIME content-type of blob
@@ -130,60 +659,213 @@ impl BlobResponse {
   }
 
   /// Get bytes stream of the blob.
+  ///
+  /// See `bytes_stream`, which avoids converting chunks to `Vec`s.
   pub fn stream(self) -> impl Stream<Item = Result<Vec<u8>>> {
-    BlobStream::new(self.resp.bytes_stream(), self.digest)
+    self.bytes_stream().map_ok(Vec::from)
+  }
+
+  /// Get the stream of the chunks of the blob, as received.
+  ///
+  /// The digest and declared size of the blob are verified once the content is exhausted:
+  /// a mismatch is returned as the last item of the stream.
+  pub fn bytes_stream(self) -> impl Stream<Item = Result<Bytes>> + Unpin {
+    let expected = self.digest.expected().clone();
+    match self.source {
+      BlobSource::Remote(resp) => {
+        let cache = self.cache.and_then(|cache| match cache.writer(&expected) {
+          Ok(writer) => Some(CacheState::Fill(writer)),
+          Err(e) => {
+            debug!("Failed to cache blob {expected}: {e}");
+            None
+          }
+        });
+        Either::Left(BlobStream::new(
+          resp.bytes_stream().map_err(Error::from),
+          self.digest,
+          self.size,
+          cache,
+        ))
+      }
+      BlobSource::Cached(file, _) => {
+        let cache = self.cache.map(|cache| CacheState::Read(cache, expected));
+        let stream = ReaderStream::new(file).map_err(Error::from);
+        Either::Right(BlobStream::new(stream, self.digest, self.size, cache))
+      }
+    }
+  }
+
+  /// Get an `AsyncRead` view of the blob, e.g. to feed an asynchronous decompressor.
+  ///
+  /// As with `bytes_stream`, the blob is verified at EOF: the final read fails with an
+  /// error of kind `Other` wrapping the crate `Error` if the digest or size mismatch.
+  pub fn reader(self) -> impl tokio::io::AsyncRead + Unpin {
+    StreamReader::new(self.bytes_stream().map_err(io::Error::other))
   }
 }
 
+/// Interaction of a `BlobStream` with the `BlobCache`.
+enum CacheState {
+  /// The blob is downloaded, and added to the cache once verified.
+  Fill(CacheWriter),
+  /// The blob is read from the cache, and removed from it if corrupted.
+  Read(BlobCache, Digest),
+}
+
 #[pin_project]
 struct BlobStream<S>
 where
-  S: Stream<Item = reqwest::Result<Bytes>>,
+  S: Stream<Item = Result<Bytes>>,
 {
   #[pin]
   stream: S,
   #[pin]
   digest: Option<ContentDigest>,
+  expected_size: Option<u64>,
+  received: u64,
+  cache: Option<CacheState>,
+  /// Cache update to wait for before ending the stream, with its last item if it failed.
+  finishing: Option<(tokio::task::JoinHandle<()>, Option<Error>)>,
 }
 
 impl<S> BlobStream<S>
 where
-  S: Stream<Item = reqwest::Result<Bytes>> + Unpin,
+  S: Stream<Item = Result<Bytes>>,
 {
-  fn new(stream: S, digest: ContentDigest) -> Self {
+  fn new(stream: S, digest: ContentDigest, expected_size: Option<u64>, cache: Option<CacheState>) -> Self {
     Self {
       stream,
       digest: Some(digest),
+      expected_size,
+      received: 0,
+      cache,
+      finishing: None,
     }
   }
 }
 
 impl<S> Stream for BlobStream<S>
 where
-  S: Stream<Item = reqwest::Result<Bytes>> + Unpin,
+  S: Stream<Item = Result<Bytes>>,
 {
-  type Item = Result<Vec<u8>>;
+  type Item = Result<Bytes>;
 
   fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
     let mut this = self.project();
+    if this.finishing.is_some() {
+      return poll_finishing(this.finishing, cx);
+    }
     match this.stream.poll_next(cx) {
       Poll::Ready(Some(chunk_res)) => {
-        let mut digest = match this.digest.as_pin_mut() {
-          Some(digest) => digest,
-          None => return Poll::Ready(None),
-        };
+        if this.digest.is_none() {
+          return Poll::Ready(None);
+        }
         let chunk = chunk_res?;
-        digest.update(&chunk);
-        Poll::Ready(Some(Ok(chunk.to_vec())))
+        *this.received += chunk.len() as u64;
+        if let Some(expected) = *this.expected_size {
+          if *this.received > expected {
+            this.digest.set(None);
+            return Poll::Ready(Some(Err(Error::BlobSize {
+              expected,
+              got: *this.received,
+            })));
+          }
+        }
+        if let Some(digest) = this.digest.as_pin_mut() {
+          digest.get_mut().update(&chunk);
+        }
+        if let Some(CacheState::Fill(writer)) = this.cache {
+          writer.write(chunk.clone());
+        }
+        Poll::Ready(Some(Ok(chunk)))
       }
       Poll::Ready(None) => match this.digest.take() {
-        Some(digest) => match digest.verify() {
-          Ok(()) => Poll::Ready(None),
-          Err(err) => Poll::Ready(Some(Err(err.into()))),
-        },
+        Some(digest) => {
+          let res = match (*this.expected_size, digest.verify()) {
+            (Some(expected), _) if expected != *this.received => Err(Error::BlobSize {
+              expected,
+              got: *this.received,
+            }),
+            (_, res) => res.map_err(Error::from),
+          };
+          // The cache is updated on a blocking thread, the stream ends once it is done.
+          let task = match (this.cache.take(), &res) {
+            (Some(CacheState::Fill(writer)), Ok(())) => writer.commit(),
+            (Some(CacheState::Read(cache, digest)), Err(_)) => {
+              debug!("Cached blob {digest} is corrupted, removing it");
+              tokio::task::spawn_blocking(move || {
+                if let Err(e) = cache.remove(&digest) {
+                  debug!("Failed to remove cached blob {digest}: {e}");
+                }
+              })
+            }
+            _ => {
+              return match res {
+                Ok(()) => Poll::Ready(None),
+                Err(e) => Poll::Ready(Some(Err(e))),
+              };
+            }
+          };
+          *this.finishing = Some((task, res.err()));
+          poll_finishing(this.finishing, cx)
+        }
         None => Poll::Ready(None),
       },
       Poll::Pending => Poll::Pending,
     }
   }
 }
+
+/// Wait for the cache update of a `BlobStream`, then yield its last item.
+fn poll_finishing(
+  finishing: &mut Option<(tokio::task::JoinHandle<()>, Option<Error>)>,
+  cx: &mut Context<'_>,
+) -> Poll<Option<Result<Bytes>>> {
+  let (task, _) = finishing.as_mut().expect("a cache update is pending");
+  if let Err(e) = futures::ready!(Pin::new(task).poll(cx)) {
+    debug!("Failed to update the cache: {e}");
+  }
+  let (_, error) = finishing.take().expect("a cache update is pending");
+  Poll::Ready(error.map(Err))
+}
+
+/// Blocking `Read` adapter over a blob stream, for synchronous consumers such as `tar`.
+///
+/// It must be used from a blocking thread, e.g. within `tokio::task::spawn_blocking`,
+/// of the runtime that created the stream.
+pub(crate) struct BlockingBlobReader<S> {
+  stream: Pin<Box<S>>,
+  handle: tokio::runtime::Handle,
+  chunk: Bytes,
+}
+
+impl<S> BlockingBlobReader<S>
+where
+  S: Stream<Item = Result<Bytes>>,
+{
+  pub(crate) fn new(stream: S) -> Self {
+    Self {
+      stream: Box::pin(stream),
+      handle: tokio::runtime::Handle::current(),
+      chunk: Bytes::new(),
+    }
+  }
+}
+
+impl<S> io::Read for BlockingBlobReader<S>
+where
+  S: Stream<Item = Result<Bytes>>,
+{
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    while self.chunk.is_empty() {
+      match self.handle.block_on(self.stream.next()) {
+        Some(Ok(chunk)) => self.chunk = chunk,
+        Some(Err(e)) => return Err(io::Error::other(e)),
+        None => return Ok(0),
+      }
+    }
+    let n = buf.len().min(self.chunk.len());
+    buf[..n].copy_from_slice(&self.chunk.split_to(n));
+    Ok(n)
+  }
+}
@@ -0,0 +1,269 @@
+//! Content-addressable on-disk cache of blobs and manifests.
+//!
+//! Entries are stored as `<root>/blobs/<algorithm>/<encoded>`, the layout of OCI image
+//! layouts, and are only ever added once verified against their digest. Writers move
+//! complete files into place, so readers never see partial entries.
+//!
+//! Several processes may share a cache: every access holds a shared lock on
+//! `<root>/lock`, while evicting least recently used entries requires an exclusive one.
+//!
+//! The methods of `BlobCache` block; the client only calls them from blocking threads, see
+//! `BlobCache::blocking` and `CacheWriter`.
+
+use std::{
+  fs,
+  io::{self, Write},
+  path::{Path, PathBuf},
+  time::SystemTime,
+};
+
+use bytes::Bytes;
+use fs4::fs_std::FileExt;
+use log::{debug, trace};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::v2::Digest;
+
+/// An on-disk cache of blobs, keyed by digest, optionally limited in size.
+#[derive(Clone, Debug)]
+pub struct BlobCache {
+  root: PathBuf,
+  max_size: Option<u64>,
+}
+
+impl BlobCache {
+  /// Open the cache rooted at `root`, creating its directories if needed.
+  pub fn new<P: AsRef<Path>>(root: P) -> io::Result<Self> {
+    let root = root.as_ref().to_path_buf();
+    fs::create_dir_all(root.join("blobs"))?;
+    fs::create_dir_all(root.join("tmp"))?;
+    Ok(Self { root, max_size: None })
+  }
+
+  /// Limit the total size of the cached blobs, evicting the least recently used ones.
+  pub fn max_size(mut self, max_size: u64) -> Self {
+    self.max_size = Some(max_size);
+    self
+  }
+
+  /// Get the path where the blob `digest` is cached.
+  pub fn path(&self, digest: &Digest) -> PathBuf {
+    self
+      .root
+      .join("blobs")
+      .join(digest.algorithm().as_str())
+      .join(digest.encoded())
+  }
+
+  /// Whether the blob `digest` is cached.
+  pub fn contains(&self, digest: &Digest) -> bool {
+    self.path(digest).is_file()
+  }
+
+  /// Total size of the cached blobs.
+  pub fn size(&self) -> io::Result<u64> {
+    let _lock = self.lock_shared()?;
+    Ok(self.entries()?.iter().map(|e| e.size).sum())
+  }
+
+  /// Remove the blob `digest` from the cache, e.g. because it turned out to be corrupted.
+  pub fn remove(&self, digest: &Digest) -> io::Result<()> {
+    let _lock = self.lock_shared()?;
+    match fs::remove_file(self.path(digest)) {
+      Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+      _ => Ok(()),
+    }
+  }
+
+  /// Open the cached blob `digest`, marking it as recently used.
+  pub(crate) fn open(&self, digest: &Digest) -> io::Result<Option<fs::File>> {
+    let _lock = self.lock_shared()?;
+    let file = match fs::File::open(self.path(digest)) {
+      Ok(file) => file,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+      Err(e) => return Err(e),
+    };
+    // The modification time serves as last access time for eviction.
+    if let Err(e) = file.set_modified(SystemTime::now()) {
+      debug!("Failed to mark cached blob {digest} as used: {e}");
+    }
+    trace!("Cache hit for blob {digest}");
+    Ok(Some(file))
+  }
+
+  /// Read the cached blob `digest`, if any.
+  pub(crate) fn read(&self, digest: &Digest) -> io::Result<Option<Vec<u8>>> {
+    let Some(mut file) = self.open(digest)? else {
+      return Ok(None);
+    };
+    let mut blob = Vec::new();
+    io::Read::read_to_end(&mut file, &mut blob)?;
+    Ok(Some(blob))
+  }
+
+  /// Add the already verified `blob` under `digest`.
+  pub(crate) fn insert(&self, digest: &Digest, blob: &[u8]) -> io::Result<()> {
+    let mut file = self.tempfile(digest)?;
+    file.write_all(blob)?;
+    self.commit(digest, file)
+  }
+
+  /// Run `f` on a blocking thread of the current tokio runtime.
+  pub(crate) async fn blocking<T, F>(&self, f: F) -> io::Result<T>
+  where
+    T: Send + 'static,
+    F: FnOnce(&BlobCache) -> io::Result<T> + Send + 'static,
+  {
+    let cache = self.clone();
+    tokio::task::spawn_blocking(move || f(&cache))
+      .await
+      .map_err(io::Error::other)?
+  }
+
+  /// Start writing the blob `digest` into the cache, from a blocking thread of the current
+  /// tokio runtime; see `CacheWriter`.
+  pub(crate) fn writer(&self, digest: &Digest) -> io::Result<CacheWriter> {
+    let runtime = tokio::runtime::Handle::try_current().map_err(io::Error::other)?;
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let (cache, digest) = (self.clone(), digest.clone());
+    let task = runtime.spawn_blocking(move || {
+      if let Err(e) = cache.fill(&digest, &mut receiver) {
+        debug!("Failed to cache blob {digest}: {e}");
+      }
+    });
+    Ok(CacheWriter { sender, task })
+  }
+
+  /// Write the chunks sent by a `CacheWriter` for the blob `digest`, committing it if asked to.
+  fn fill(&self, digest: &Digest, receiver: &mut mpsc::UnboundedReceiver<WriterMessage>) -> io::Result<()> {
+    let mut file = self.tempfile(digest)?;
+    while let Some(message) = receiver.blocking_recv() {
+      match message {
+        WriterMessage::Chunk(chunk) => file.write_all(&chunk)?,
+        WriterMessage::Commit => return self.commit(digest, file),
+      }
+    }
+    trace!("Discarding partially cached blob {digest}");
+    Ok(())
+  }
+
+  fn tempfile(&self, digest: &Digest) -> io::Result<tempfile::NamedTempFile> {
+    tempfile::Builder::new()
+      .prefix(digest.encoded())
+      .tempfile_in(self.root.join("tmp"))
+  }
+
+  /// Move the verified blob `digest` written to `file` into the cache, then evict old
+  /// entries if it is too large.
+  fn commit(&self, digest: &Digest, file: tempfile::NamedTempFile) -> io::Result<()> {
+    let size = file.as_file().metadata()?.len();
+    if self.max_size.is_some_and(|max_size| size > max_size) {
+      trace!("Blob {digest} is larger than the cache, not caching it");
+      return Ok(());
+    }
+
+    let path = self.path(digest);
+    {
+      let _lock = self.lock_shared()?;
+      fs::create_dir_all(path.parent().expect("cache paths have a parent"))?;
+      file.persist(&path).map_err(|e| e.error)?;
+    }
+    trace!("Cached blob {digest}");
+    self.evict()
+  }
+
+  fn lock_file(&self) -> io::Result<fs::File> {
+    fs::OpenOptions::new()
+      .create(true)
+      .truncate(false)
+      .write(true)
+      .open(self.root.join("lock"))
+  }
+
+  /// Hold a shared lock on the cache until the returned file is dropped.
+  fn lock_shared(&self) -> io::Result<fs::File> {
+    let lock = self.lock_file()?;
+    FileExt::lock_shared(&lock)?;
+    Ok(lock)
+  }
+
+  fn entries(&self) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for algorithm in fs::read_dir(self.root.join("blobs"))? {
+      for entry in fs::read_dir(algorithm?.path())? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        entries.push(Entry {
+          path: entry.path(),
+          size: metadata.len(),
+          used: metadata.modified()?,
+        });
+      }
+    }
+    Ok(entries)
+  }
+
+  /// Evict the least recently used blobs until the cache fits in its maximum size.
+  ///
+  /// This is skipped if another process is using the cache, the next insertion evicts instead.
+  fn evict(&self) -> io::Result<()> {
+    let Some(max_size) = self.max_size else {
+      return Ok(());
+    };
+    let lock = self.lock_file()?;
+    if !FileExt::try_lock_exclusive(&lock)? {
+      trace!("Cache is in use, skipping eviction");
+      return Ok(());
+    }
+
+    let mut entries = self.entries()?;
+    let mut size: u64 = entries.iter().map(|e| e.size).sum();
+    entries.sort_by_key(|e| e.used);
+    for entry in entries {
+      if size <= max_size {
+        break;
+      }
+      debug!("Evicting cached blob {}", entry.path.display());
+      fs::remove_file(&entry.path)?;
+      size -= entry.size;
+    }
+    Ok(())
+  }
+}
+
+#[derive(Debug)]
+struct Entry {
+  path: PathBuf,
+  size: u64,
+  used: SystemTime,
+}
+
+/// Writer of a blob into a `BlobCache`.
+///
+/// Chunks are sent to a blocking task, which writes them to a temporary file and, once the
+/// caller verified the blob, moves it into the cache on `commit`. Dropping the writer
+/// discards the content. Failures are only logged: the blob is then not cached.
+#[derive(Debug)]
+pub(crate) struct CacheWriter {
+  sender: mpsc::UnboundedSender<WriterMessage>,
+  task: JoinHandle<()>,
+}
+
+#[derive(Debug)]
+enum WriterMessage {
+  Chunk(Bytes),
+  Commit,
+}
+
+impl CacheWriter {
+  pub(crate) fn write(&mut self, chunk: Bytes) {
+    // The task only stops early on failure, which it logs.
+    let _ = self.sender.send(WriterMessage::Chunk(chunk));
+  }
+
+  /// Add the written blob to the cache, which is done once the returned task completes.
+  pub(crate) fn commit(self) -> JoinHandle<()> {
+    let _ = self.sender.send(WriterMessage::Commit);
+    self.task
+  }
+}
@@ -14,6 +14,7 @@ pub struct Config {
   accept_invalid_certs: bool,
   root_certificates: Vec<Certificate>,
   accepted_types: Option<Vec<(MediaTypes, Option<f64>)>>,
+  cache: Option<BlobCache>,
 }
 
 impl Config {
@@ -47,6 +48,12 @@ impl Config {
     self
   }
 
+  /// Set a local cache for blobs and manifests fetched by digest.
+  pub fn cache(mut self, cache: Option<BlobCache>) -> Self {
+    self.cache = cache;
+    self
+  }
+
   /// Set the user-agent to be used for registry authentication.
   pub fn user_agent(mut self, user_agent: Option<String>) -> Self {
     self.user_agent = user_agent;
@@ -130,6 +137,7 @@ impl Config {
       auth: None,
       client,
       accepted_types,
+      cache: self.cache,
     };
     Ok(c)
   }
@@ -147,6 +155,7 @@ impl Default for Config {
       user_agent: Some(crate::USER_AGENT.to_owned()),
       username: None,
       password: None,
+      cache: None,
     }
   }
 }
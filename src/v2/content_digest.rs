@@ -1,21 +1,81 @@
-use std::str;
+use std::{fmt, io, str};
 
+use futures::{Stream, TryStreamExt};
+use serde::{Deserialize, Serialize};
 /// Implements types and methods for content verification
-use sha2::{self, Digest};
+use sha2::{self, Digest as _};
 
-/// DigestAlgorithm declares the supported algorithms
-#[derive(strum::Display, Clone, Debug)]
+/// DigestAlgorithm declares the algorithm part of a digest.
+///
+/// Registered algorithms are listed at
+/// <https://github.com/opencontainers/image-spec/blob/main/descriptor.md#registered-algorithms>.
+/// Algorithms which conform to the digest grammar but are not registered are kept as `Unknown`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum DigestAlgorithm {
-  Sha256(sha2::Sha256),
+  Sha256,
+  Sha512,
+  /// Registered, but hashing is not supported by this library.
+  Blake3,
+  Unknown(String),
+}
+
+impl DigestAlgorithm {
+  /// Name of the algorithm, as used in the digest string.
+  pub fn as_str(&self) -> &str {
+    match self {
+      DigestAlgorithm::Sha256 => "sha256",
+      DigestAlgorithm::Sha512 => "sha512",
+      DigestAlgorithm::Blake3 => "blake3",
+      DigestAlgorithm::Unknown(name) => name,
+    }
+  }
+
+  /// Whether this library is able to compute digests with this algorithm.
+  pub fn is_supported(&self) -> bool {
+    matches!(self, DigestAlgorithm::Sha256 | DigestAlgorithm::Sha512)
+  }
+
+  // Validate the encoded part of a digest for this algorithm.
+  fn validate_encoded(&self, encoded: &str) -> bool {
+    let is_lower_hex = |len: usize| {
+      encoded.len() == len
+        && encoded
+          .bytes()
+          .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    };
+    match self {
+      DigestAlgorithm::Sha256 | DigestAlgorithm::Blake3 => is_lower_hex(64),
+      DigestAlgorithm::Sha512 => is_lower_hex(128),
+      DigestAlgorithm::Unknown(_) => {
+        !encoded.is_empty()
+          && encoded
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'=' || b == b'_' || b == b'-')
+      }
+    }
+  }
+}
+
+impl fmt::Display for DigestAlgorithm {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
 }
 
-impl std::str::FromStr for DigestAlgorithm {
+impl str::FromStr for DigestAlgorithm {
   type Err = ContentDigestError;
 
   fn from_str(name: &str) -> Result<Self, Self::Err> {
+    // algorithm ::= algorithm-component (algorithm-separator algorithm-component)*
+    let valid = name
+      .split(['+', '.', '_', '-'])
+      .all(|c| !c.is_empty() && c.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit()));
     match name {
-      "sha256" => Ok(DigestAlgorithm::Sha256(sha2::Sha256::new())),
-      _ => Err(ContentDigestError::AlgorithmUnknown(name.to_string())),
+      "sha256" => Ok(DigestAlgorithm::Sha256),
+      "sha512" => Ok(DigestAlgorithm::Sha512),
+      "blake3" => Ok(DigestAlgorithm::Blake3),
+      _ if valid => Ok(DigestAlgorithm::Unknown(name.to_string())),
+      _ => Err(ContentDigestError::BadDigest(name.to_string())),
     }
   }
 }
@@ -24,49 +84,237 @@ impl std::str::FromStr for DigestAlgorithm {
 pub enum ContentDigestError {
   #[error("digest {0} does not have algorithm prefix")]
   BadDigest(String),
+  #[error("digest {0} has an invalid encoded part for its algorithm")]
+  BadEncoding(String),
   #[error("unknown algorithm: {0}")]
   AlgorithmUnknown(String),
+  #[error("registered algorithm {0} is not supported")]
+  AlgorithmUnsupported(String),
   #[error("verification failed: expected '{expected}', got '{got}'")]
   Verify { expected: String, got: String },
 }
 
-/// ContentDigest stores a digest and its DigestAlgorithm
+/// A validated content digest, of the form `algorithm:encoded`.
+///
+/// Specification is at <https://github.com/opencontainers/image-spec/blob/main/descriptor.md#digests>.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Digest {
+  algorithm: DigestAlgorithm,
+  encoded: String,
+}
+
+impl Digest {
+  /// Create a digest from an algorithm and its encoded part, validating the latter.
+  pub fn new(algorithm: DigestAlgorithm, encoded: &str) -> Result<Self, ContentDigestError> {
+    if !algorithm.validate_encoded(encoded) {
+      return Err(ContentDigestError::BadEncoding(format!("{algorithm}:{encoded}")));
+    }
+    Ok(Self {
+      algorithm,
+      encoded: encoded.to_string(),
+    })
+  }
+
+  /// Get the algorithm of this digest.
+  pub fn algorithm(&self) -> &DigestAlgorithm {
+    &self.algorithm
+  }
+
+  /// Get the encoded part of this digest (the hex string for sha256 and sha512).
+  pub fn encoded(&self) -> &str {
+    &self.encoded
+  }
+
+  /// Compute the digest of `input` with `algorithm`.
+  pub fn from_bytes(algorithm: DigestAlgorithm, input: &[u8]) -> Result<Self, ContentDigestError> {
+    let mut digester = Digester::new(algorithm)?;
+    digester.update(input);
+    Ok(digester.finalize())
+  }
+
+  /// Compute the sha256 digest of `input`.
+  pub fn sha256(input: &[u8]) -> Self {
+    let mut digester = Digester::new(DigestAlgorithm::Sha256).expect("sha256 is supported");
+    digester.update(input);
+    digester.finalize()
+  }
+
+  /// Compute the digest of all content from `reader` with `algorithm`.
+  pub fn from_reader<R: io::Read>(algorithm: DigestAlgorithm, mut reader: R) -> crate::errors::Result<Self> {
+    let mut digester = Digester::new(algorithm)?;
+    io::copy(&mut reader, &mut digester)?;
+    Ok(digester.finalize())
+  }
+
+  /// Compute the digest of all chunks from `stream` with `algorithm`.
+  pub async fn from_stream<S, B>(algorithm: DigestAlgorithm, stream: S) -> crate::errors::Result<Self>
+  where
+    S: Stream<Item = crate::errors::Result<B>>,
+    B: AsRef<[u8]>,
+  {
+    let mut digester = Digester::new(algorithm)?;
+    futures::pin_mut!(stream);
+    while let Some(chunk) = stream.try_next().await? {
+      digester.update(chunk.as_ref());
+    }
+    Ok(digester.finalize())
+  }
+
+  /// Check whether `input` hashes to this digest.
+  pub fn verify(&self, input: &[u8]) -> Result<(), ContentDigestError> {
+    let mut content_digest = ContentDigest::new(self.clone())?;
+    content_digest.update(input);
+    content_digest.verify()
+  }
+}
+
+impl fmt::Display for Digest {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}:{}", self.algorithm, self.encoded)
+  }
+}
+
+impl str::FromStr for Digest {
+  type Err = ContentDigestError;
+
+  fn from_str(digest: &str) -> Result<Self, Self::Err> {
+    let (algorithm, encoded) = digest
+      .split_once(':')
+      .ok_or_else(|| ContentDigestError::BadDigest(digest.to_string()))?;
+    let algorithm = algorithm
+      .parse()
+      .map_err(|_| ContentDigestError::BadDigest(digest.to_string()))?;
+    Digest::new(algorithm, encoded)
+  }
+}
+
+impl TryFrom<&str> for Digest {
+  type Error = ContentDigestError;
+
+  fn try_from(digest: &str) -> Result<Self, Self::Error> {
+    digest.parse()
+  }
+}
+
+impl PartialEq<str> for Digest {
+  fn eq(&self, other: &str) -> bool {
+    other.split_once(':') == Some((self.algorithm.as_str(), self.encoded.as_str()))
+  }
+}
+
+impl PartialEq<&str> for Digest {
+  fn eq(&self, other: &&str) -> bool {
+    self == *other
+  }
+}
+
+impl PartialEq<Digest> for str {
+  fn eq(&self, other: &Digest) -> bool {
+    other == self
+  }
+}
+
+impl PartialEq<Digest> for &str {
+  fn eq(&self, other: &Digest) -> bool {
+    other == *self
+  }
+}
+
+impl Serialize for Digest {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for Digest {
+  fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    s.parse().map_err(serde::de::Error::custom)
+  }
+}
+
+/// Incremental hasher producing a `Digest`.
+#[derive(Clone, Debug)]
+pub struct Digester {
+  state: DigesterState,
+}
+
+#[derive(Clone, Debug)]
+enum DigesterState {
+  Sha256(sha2::Sha256),
+  Sha512(sha2::Sha512),
+}
+
+impl Digester {
+  /// Create a hasher for `algorithm`, failing if it is not supported.
+  pub fn new(algorithm: DigestAlgorithm) -> Result<Self, ContentDigestError> {
+    let state = match algorithm {
+      DigestAlgorithm::Sha256 => DigesterState::Sha256(sha2::Sha256::new()),
+      DigestAlgorithm::Sha512 => DigesterState::Sha512(sha2::Sha512::new()),
+      DigestAlgorithm::Blake3 => return Err(ContentDigestError::AlgorithmUnsupported(algorithm.to_string())),
+      DigestAlgorithm::Unknown(name) => return Err(ContentDigestError::AlgorithmUnknown(name)),
+    };
+    Ok(Self { state })
+  }
+
+  pub fn update(&mut self, input: &[u8]) {
+    match &mut self.state {
+      DigesterState::Sha256(hash) => hash.update(input),
+      DigesterState::Sha512(hash) => hash.update(input),
+    }
+  }
+
+  pub fn finalize(self) -> Digest {
+    let (algorithm, encoded) = match self.state {
+      DigesterState::Sha256(hash) => (DigestAlgorithm::Sha256, format!("{:x}", hash.finalize())),
+      DigesterState::Sha512(hash) => (DigestAlgorithm::Sha512, format!("{:x}", hash.finalize())),
+    };
+    Digest { algorithm, encoded }
+  }
+}
+
+impl io::Write for Digester {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.update(buf);
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// ContentDigest stores an expected digest and the hasher used to verify content against it
 #[derive(Clone, Debug)]
 pub struct ContentDigest {
-  digest: String,
-  algorithm: DigestAlgorithm,
+  digest: Digest,
+  digester: Digester,
 }
 
 impl ContentDigest {
-  /// try_new attempts to parse the digest string and create a ContentDigest instance from it
+  /// Create a ContentDigest verifying content against `digest`.
   ///
-  /// Success depends on
-  /// - the string having an "algorithm:" prefix
-  /// - the algorithm being supported by DigestAlgorithm
-  pub fn try_new(digest: &str) -> std::result::Result<Self, ContentDigestError> {
-    let digest_split = digest.split(':').collect::<Vec<&str>>();
-
-    if digest_split.len() != 2 {
-      return Err(ContentDigestError::BadDigest(digest.to_string()));
-    }
+  /// Fails if the digest algorithm is not supported by `Digester`.
+  pub fn new(digest: Digest) -> std::result::Result<Self, ContentDigestError> {
+    let digester = Digester::new(digest.algorithm.clone())?;
+    Ok(ContentDigest { digest, digester })
+  }
 
-    let algorithm = std::str::FromStr::from_str(digest_split[0])?;
-    Ok(ContentDigest {
-      digest: digest.to_string(),
-      algorithm,
-    })
+  /// The digest content is verified against.
+  pub fn expected(&self) -> &Digest {
+    &self.digest
   }
 
   pub fn update(&mut self, input: &[u8]) {
-    self.algorithm.update(input)
+    self.digester.update(input)
   }
 
   pub fn verify(self) -> std::result::Result<(), ContentDigestError> {
-    let digest = self.algorithm.digest();
+    let digest = self.digester.finalize();
     if digest != self.digest {
       return Err(ContentDigestError::Verify {
-        expected: self.digest,
-        got: digest,
+        expected: self.digest.to_string(),
+        got: digest.to_string(),
       });
     }
     Ok(())
@@ -75,35 +323,25 @@ impl ContentDigest {
 
 impl std::fmt::Display for ContentDigest {
   fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-    write!(f, "{}:{}", self.algorithm, self.digest)
-  }
-}
-
-impl DigestAlgorithm {
-  fn update(&mut self, input: &[u8]) {
-    match self {
-      DigestAlgorithm::Sha256(hash) => {
-        hash.update(input);
-      }
-    }
-  }
-
-  fn digest(self) -> String {
-    let (algo, digest) = match self {
-      DigestAlgorithm::Sha256(hash) => ("sha256", hash.finalize()),
-    };
-    format!("{}:{:x}", algo, &digest)
+    write!(f, "{}", self.digest)
   }
 }
 
 #[cfg(test)]
 mod tests {
-  use sha2;
+  use std::str::FromStr;
 
   use super::*;
 
   type Fallible<T> = Result<T, crate::Error>;
 
+  impl ContentDigest {
+    /// Parse `digest` and verify content against it.
+    fn try_new(digest: &str) -> Result<Self, ContentDigestError> {
+      Self::new(digest.parse()?)
+    }
+  }
+
   #[test]
   fn try_new_succeeds_with_correct_digest() -> Fallible<()> {
     let correct_digest = "sha256:0000000000000000000000000000000000000000000000000000000000000000";
@@ -148,9 +386,9 @@ mod tests {
     let blob: &[u8] = b"somecontent";
     let different_blob: &[u8] = b"someothercontent";
 
-    let mut expected_digest = DigestAlgorithm::Sha256(sha2::Sha256::new());
+    let mut expected_digest = Digester::new(DigestAlgorithm::Sha256)?;
     expected_digest.update(different_blob);
-    let expected_digest = expected_digest.digest();
+    let expected_digest = expected_digest.finalize().to_string();
 
     let mut content_digest = ContentDigest::try_new(&expected_digest)?;
     content_digest.update(blob);
@@ -159,4 +397,82 @@ mod tests {
     }
     Ok(())
   }
+
+  #[test]
+  fn new_verifies_against_digest() -> Fallible<()> {
+    let mut content_digest = ContentDigest::new(Digest::sha256(b"someothercontent"))?;
+    content_digest.update(b"somecontent");
+    assert!(matches!(
+      content_digest.verify(),
+      Err(ContentDigestError::Verify { .. })
+    ));
+
+    let mut content_digest = ContentDigest::new(Digest::sha256(b"somecontent"))?;
+    content_digest.update(b"somecontent");
+    content_digest.verify().map_err(Into::into)
+  }
+
+  #[test]
+  fn parse_validates_encoded_part() {
+    for invalid in &[
+      "sha256:abc",
+      "sha256:D5A3477D91583E65A7ABA6F6DB7A53E2DE739BC7BF8F4A08F0DF0457B637F1FB",
+      "sha256:z5a3477d91583e65a7aba6f6db7a53e2de739bc7bf8f4a08f0df0457b637f1fb",
+      "sha512:d5a3477d91583e65a7aba6f6db7a53e2de739bc7bf8f4a08f0df0457b637f1fb",
+      "Sha256:d5a3477d91583e65a7aba6f6db7a53e2de739bc7bf8f4a08f0df0457b637f1fb",
+      "sha256+:d5a3477d91583e65a7aba6f6db7a53e2de739bc7bf8f4a08f0df0457b637f1fb",
+      "foo:bar/baz",
+    ] {
+      assert!(Digest::from_str(invalid).is_err(), "expected {invalid} to be rejected");
+    }
+  }
+
+  #[test]
+  fn parse_keeps_unknown_algorithms() -> Fallible<()> {
+    let digest = Digest::from_str("multihash+base58:QmRZxt2b1FVZPNqd8hsiykDL3TdBDeTSPX9Kv46HmX4Gx8")?;
+    assert_eq!(
+      &DigestAlgorithm::Unknown("multihash+base58".to_string()),
+      digest.algorithm()
+    );
+    assert!(matches!(
+      ContentDigest::new(digest),
+      Err(ContentDigestError::AlgorithmUnknown(_))
+    ));
+
+    let digest = Digest::from_str("blake3:d5a3477d91583e65a7aba6f6db7a53e2de739bc7bf8f4a08f0df0457b637f1fb")?;
+    assert!(matches!(
+      ContentDigest::new(digest),
+      Err(ContentDigestError::AlgorithmUnsupported(_))
+    ));
+    Ok(())
+  }
+
+  #[test]
+  fn hashing_helpers() -> Fallible<()> {
+    let expected = "sha256:d5a3477d91583e65a7aba6f6db7a53e2de739bc7bf8f4a08f0df0457b637f1fb";
+    assert_eq!(Digest::sha256(b"somecontent"), expected);
+    assert_eq!(
+      Digest::from_reader(DigestAlgorithm::Sha256, &b"somecontent"[..])?,
+      expected
+    );
+
+    let sha512 = Digest::from_bytes(DigestAlgorithm::Sha512, b"somecontent")?;
+    assert_eq!(128, sha512.encoded().len());
+    assert_eq!(sha512, Digest::from_str(&sha512.to_string())?);
+    sha512.verify(b"somecontent")?;
+
+    let chunks = futures::stream::iter(vec![Ok(b"some".to_vec()), Ok(b"content".to_vec())]);
+    let streamed = futures::executor::block_on(Digest::from_stream(DigestAlgorithm::Sha256, chunks))?;
+    assert_eq!(streamed, expected);
+    Ok(())
+  }
+
+  #[test]
+  fn serde_roundtrip() -> Fallible<()> {
+    let json = r#""sha256:d5a3477d91583e65a7aba6f6db7a53e2de739bc7bf8f4a08f0df0457b637f1fb""#;
+    let digest: Digest = serde_json::from_str(json)?;
+    assert_eq!(json, serde_json::to_string(&digest)?);
+    assert!(serde_json::from_str::<Digest>(r#""sha256:abc""#).is_err());
+    Ok(())
+  }
 }
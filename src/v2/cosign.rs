@@ -0,0 +1,224 @@
+//! Discovery and offline verification of cosign signatures.
+//!
+//! Cosign stores signatures either as an image manifest tagged `<alg>-<hex>.sig` next
+//! to the signed image, or as an OCI 1.1 referrer of it. Each layer holds a
+//! simple-signing payload, whose signature is stored in a layer annotation.
+
+use base64::prelude::*;
+use log::{debug, trace};
+use p256::{
+  ecdsa::{Signature, VerifyingKey},
+  pkcs8::DecodePublicKey,
+};
+use serde::{Deserialize, Serialize};
+use signature::Verifier;
+
+use crate::{
+  errors::Result,
+  mediatypes::MediaTypes,
+  reference::Reference,
+  v2::{
+    manifest::{Descriptor, ManifestSchema2Spec},
+    referrers::referrers_tag,
+    *,
+  },
+};
+
+/// Annotation holding the base64-encoded signature of a simple-signing payload.
+pub const COSIGN_SIGNATURE_ANNOTATION: &str = "dev.cosignproject.cosign/signature";
+
+/// Artifact type of cosign signatures stored as OCI referrers.
+const COSIGN_SIGNATURE_ARTIFACT_TYPE: &str = "application/vnd.dev.cosign.artifact.sig.v1+json";
+
+/// Value of `critical.type` in cosign image signature payloads.
+const COSIGN_SIGNATURE_TYPE: &str = "cosign container image signature";
+
+#[derive(Debug, thiserror::Error)]
+pub enum CosignError {
+  #[error("invalid public key: {0}")]
+  InvalidKey(String),
+  #[error("invalid signature encoding")]
+  InvalidSignature,
+  #[error("signature does not match the payload")]
+  Verify,
+  #[error("payload is not a cosign image signature: {0}")]
+  InvalidPayload(String),
+  #[error("payload signs {got}, expected {expected}")]
+  DigestMismatch { expected: Digest, got: Digest },
+  #[error("none of the {0} signatures found could be verified")]
+  NoValidSignature(usize),
+}
+
+/// ECDSA P-256 public key, as used by `cosign generate-key-pair`.
+#[derive(Clone, Debug)]
+pub struct CosignPublicKey {
+  key: VerifyingKey,
+}
+
+impl CosignPublicKey {
+  /// Parse a PEM-encoded (SPKI) public key, e.g. a `cosign.pub` file.
+  pub fn from_pem(pem: &str) -> std::result::Result<Self, CosignError> {
+    let key = VerifyingKey::from_public_key_pem(pem).map_err(|e| CosignError::InvalidKey(e.to_string()))?;
+    Ok(Self { key })
+  }
+}
+
+impl From<VerifyingKey> for CosignPublicKey {
+  fn from(key: VerifyingKey) -> Self {
+    Self { key }
+  }
+}
+
+/// Simple-signing payload of a cosign signature.
+///
+/// Specification is at <https://github.com/containers/image/blob/main/docs/containers-signature.5.md>.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SimpleSigning {
+  pub critical: Critical,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub optional: Option<serde_json::Value>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Critical {
+  pub identity: Identity,
+  pub image: Image,
+  #[serde(rename = "type")]
+  pub kind: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Identity {
+  #[serde(rename = "docker-reference")]
+  pub docker_reference: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Image {
+  #[serde(rename = "docker-manifest-digest")]
+  pub docker_manifest_digest: Digest,
+}
+
+/// A cosign signature, as found in a registry.
+#[derive(Clone, Debug)]
+pub struct CosignSignature {
+  /// Digest of the signature manifest holding this signature.
+  pub manifest: Digest,
+  /// Descriptor of the payload layer.
+  pub layer: Descriptor,
+  /// Raw simple-signing payload.
+  pub payload: Vec<u8>,
+  /// Base64-encoded signature of the payload.
+  pub signature: String,
+}
+
+impl CosignSignature {
+  /// Verify this signature with `key`, checking that it signs the image `digest`.
+  pub fn verify(&self, key: &CosignPublicKey, digest: &Digest) -> std::result::Result<SimpleSigning, CosignError> {
+    let raw = BASE64_STANDARD
+      .decode(self.signature.trim())
+      .map_err(|_| CosignError::InvalidSignature)?;
+    // Cosign emits ASN.1 DER signatures, other signers may use the fixed-size encoding.
+    let signature = Signature::from_der(&raw)
+      .or_else(|_| Signature::from_slice(&raw))
+      .map_err(|_| CosignError::InvalidSignature)?;
+    key
+      .key
+      .verify(&self.payload, &signature)
+      .map_err(|_| CosignError::Verify)?;
+
+    let payload: SimpleSigning =
+      serde_json::from_slice(&self.payload).map_err(|e| CosignError::InvalidPayload(e.to_string()))?;
+    if payload.critical.kind != COSIGN_SIGNATURE_TYPE {
+      return Err(CosignError::InvalidPayload(format!(
+        "unexpected type {:?}",
+        payload.critical.kind
+      )));
+    }
+    if &payload.critical.image.docker_manifest_digest != digest {
+      return Err(CosignError::DigestMismatch {
+        expected: digest.clone(),
+        got: payload.critical.image.docker_manifest_digest,
+      });
+    }
+    Ok(payload)
+  }
+}
+
+impl Client {
+  /// Find the cosign signatures of the image `digest` in the repository of `reference`.
+  ///
+  /// Signatures are looked up under the `<alg>-<hex>.sig` tag and among the OCI referrers
+  /// of the image, falling back to the tag alone when the registry cannot list referrers.
+  /// The client must be configured for the registry of `reference`.
+  pub async fn get_cosign_signatures(&self, reference: &Reference, digest: &Digest) -> Result<Vec<CosignSignature>> {
+    let name = reference.repository();
+
+    let mut manifests = vec![format!("{}.sig", referrers_tag(digest))];
+    // The referrers API is optional, failing to query it must not hide the tag.
+    match self
+      .get_referrers(&name, digest, Some(COSIGN_SIGNATURE_ARTIFACT_TYPE))
+      .await
+    {
+      Ok(referrers) => manifests.extend(referrers.index.manifests.iter().map(|m| m.digest.to_string())),
+      Err(e) => debug!("Failed to get the referrers of {digest}, only checking the signature tag: {e}"),
+    }
+
+    let mut signatures = Vec::new();
+    for manifest_ref in manifests {
+      let accepted = [MediaTypes::OciImageManifest, MediaTypes::ManifestV2S2];
+      let Some((_, raw)) = self.get_raw_manifest(&name, &manifest_ref, &accepted).await? else {
+        trace!("No cosign signature manifest {manifest_ref}");
+        continue;
+      };
+      let manifest_digest = Digest::sha256(&raw);
+      let manifest: ManifestSchema2Spec = serde_json::from_slice(&raw)?;
+
+      for layer in manifest.layers() {
+        let Some(signature) = layer.annotation(COSIGN_SIGNATURE_ANNOTATION) else {
+          continue;
+        };
+        if layer.media_type != MediaTypes::CosignSimpleSigning.to_string() {
+          debug!("Skipping cosign layer {} of type {}", layer.digest, layer.media_type);
+          continue;
+        }
+        let (payload, _) = self.get_blob_from_descriptor(&name, layer).await?;
+        signatures.push(CosignSignature {
+          manifest: manifest_digest.clone(),
+          layer: layer.clone(),
+          payload,
+          signature: signature.to_string(),
+        });
+      }
+    }
+
+    Ok(signatures)
+  }
+
+  /// Verify the cosign signatures of the image `digest` against `key`.
+  ///
+  /// Returns the payloads of the valid signatures, or an error if none is valid.
+  pub async fn verify_cosign_signatures(
+    &self,
+    reference: &Reference,
+    digest: &Digest,
+    key: &CosignPublicKey,
+  ) -> Result<Vec<SimpleSigning>> {
+    let signatures = self.get_cosign_signatures(reference, digest).await?;
+    let verified = signatures
+      .iter()
+      .filter_map(|s| match s.verify(key, digest) {
+        Ok(payload) => Some(payload),
+        Err(e) => {
+          debug!("Ignoring cosign signature in {}: {e}", s.manifest);
+          None
+        }
+      })
+      .collect::<Vec<_>>();
+
+    if verified.is_empty() {
+      return Err(CosignError::NoValidSignature(signatures.len()).into());
+    }
+    Ok(verified)
+  }
+}
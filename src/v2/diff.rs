@@ -0,0 +1,315 @@
+//! Differences between two images, e.g. two tags of the same repository.
+//!
+//! Manifests and configs are compared without fetching anything else. File-level
+//! changes are computed by `Client::diff_files`, which only reads the tar headers
+//! of the layers of both images.
+
+use std::{
+  collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+  path::{Path, PathBuf},
+};
+
+use crate::{
+  errors::Result,
+  v2::{
+    files::{FileEntry, FileIndex, FileKind},
+    manifest::{Descriptor, ManifestList, ManifestSchema2, Platform},
+    *,
+  },
+};
+
+/// Differences between two images.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImageDiff {
+  pub layers: LayerDiff,
+  pub config: Vec<ConfigChange>,
+}
+
+/// Layers of two images, compared by digest and by `diff_id`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LayerDiff {
+  /// Layers present in both images, in the order of the new image.
+  pub shared: Vec<Descriptor>,
+  /// Layers only present in the new image.
+  pub added: Vec<Descriptor>,
+  /// Layers only present in the old image.
+  pub removed: Vec<Descriptor>,
+  /// Uncompressed layer digests present in both images.
+  ///
+  /// A layer recompressed differently has a new digest but the same `diff_id`.
+  pub shared_diff_ids: Vec<Digest>,
+  pub added_diff_ids: Vec<Digest>,
+  pub removed_diff_ids: Vec<Digest>,
+}
+
+/// A change in the runtime configuration of an image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigChange {
+  Env {
+    name: String,
+    old: Option<String>,
+    new: Option<String>,
+  },
+  Label {
+    name: String,
+    old: Option<String>,
+    new: Option<String>,
+  },
+  ExposedPort {
+    port: String,
+    exposed: bool,
+  },
+  Entrypoint {
+    old: Option<Vec<String>>,
+    new: Option<Vec<String>>,
+  },
+  Cmd {
+    old: Option<Vec<String>>,
+    new: Option<Vec<String>>,
+  },
+  User {
+    old: Option<String>,
+    new: Option<String>,
+  },
+}
+
+/// Platforms of two manifest lists or image indexes.
+///
+/// Platforms are matched on OS, architecture, variant and OS version; attestation
+/// manifests are ignored.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PlatformDiff {
+  /// Images of platforms only present in the new index.
+  pub added: Vec<Descriptor>,
+  /// Images of platforms only present in the old index.
+  pub removed: Vec<Descriptor>,
+  /// Images of platforms present in both indexes, with a different digest, as `(old, new)`.
+  pub changed: Vec<(Descriptor, Descriptor)>,
+  /// Images of platforms present in both indexes, with the same digest.
+  pub unchanged: Vec<Descriptor>,
+}
+
+/// A change to a path of the image filesystem.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FileChange {
+  Added(PathBuf),
+  Modified(PathBuf),
+  Deleted(PathBuf),
+}
+
+impl FileChange {
+  /// The changed path, relative to the root of the image filesystem.
+  pub fn path(&self) -> &Path {
+    match self {
+      FileChange::Added(p) | FileChange::Modified(p) | FileChange::Deleted(p) => p,
+    }
+  }
+}
+
+impl ManifestSchema2 {
+  /// Compare this (old) image to `new`.
+  pub fn diff(&self, new: &ManifestSchema2) -> ImageDiff {
+    ImageDiff {
+      layers: diff_layers(self, new),
+      config: diff_config(self, new),
+    }
+  }
+}
+
+impl ManifestList {
+  /// Compare the platforms of this (old) index to those of `new`.
+  pub fn diff_platforms(&self, new: &ManifestList) -> PlatformDiff {
+    let key = |d: &Descriptor| {
+      d.platform.as_ref().map(|p: &Platform| {
+        (
+          p.os.clone(),
+          p.architecture.clone(),
+          p.variant.clone(),
+          p.os_version.clone(),
+        )
+      })
+    };
+    let old: Vec<_> = self.images().filter(|d| d.platform.is_some()).collect();
+    let new: Vec<_> = new.images().filter(|d| d.platform.is_some()).collect();
+
+    let mut diff = PlatformDiff::default();
+    for n in &new {
+      match old.iter().find(|o| key(o) == key(n)) {
+        Some(o) if o.digest == n.digest => diff.unchanged.push((*n).clone()),
+        Some(o) => diff.changed.push(((*o).clone(), (*n).clone())),
+        None => diff.added.push((*n).clone()),
+      }
+    }
+    diff.removed = old
+      .into_iter()
+      .filter(|o| !new.iter().any(|n| key(n) == key(o)))
+      .cloned()
+      .collect();
+    diff
+  }
+}
+
+fn diff_layers(old: &ManifestSchema2, new: &ManifestSchema2) -> LayerDiff {
+  let old_layers = old.get_layers();
+  let new_layers = new.get_layers();
+  let old_digests: HashSet<_> = old_layers.iter().map(|l| &l.digest).collect();
+  let new_digests: HashSet<_> = new_layers.iter().map(|l| &l.digest).collect();
+  let old_diff_ids: HashSet<_> = old.diff_ids().iter().collect();
+  let new_diff_ids: HashSet<_> = new.diff_ids().iter().collect();
+
+  let (shared, added) = new_layers
+    .iter()
+    .cloned()
+    .partition(|l| old_digests.contains(&l.digest));
+  let (shared_diff_ids, added_diff_ids) = new.diff_ids().iter().cloned().partition(|d| old_diff_ids.contains(d));
+  LayerDiff {
+    shared,
+    added,
+    removed: old_layers
+      .iter()
+      .filter(|l| !new_digests.contains(&l.digest))
+      .cloned()
+      .collect(),
+    shared_diff_ids,
+    added_diff_ids,
+    removed_diff_ids: old
+      .diff_ids()
+      .iter()
+      .filter(|d| !new_diff_ids.contains(d))
+      .cloned()
+      .collect(),
+  }
+}
+
+fn diff_config(old: &ManifestSchema2, new: &ManifestSchema2) -> Vec<ConfigChange> {
+  let mut changes = Vec::new();
+
+  let env = |m: &ManifestSchema2| -> BTreeMap<String, String> {
+    m.env()
+      .unwrap_or_default()
+      .iter()
+      .map(|e| match e.split_once('=') {
+        Some((name, value)) => (name.to_string(), value.to_string()),
+        None => (e.clone(), String::new()),
+      })
+      .collect()
+  };
+  for (name, old, new) in diff_maps(env(old), env(new)) {
+    changes.push(ConfigChange::Env { name, old, new });
+  }
+
+  let labels = |m: &ManifestSchema2| m.labels().unwrap_or_default().into_iter().collect();
+  for (name, old, new) in diff_maps(labels(old), labels(new)) {
+    changes.push(ConfigChange::Label { name, old, new });
+  }
+
+  let old_ports: BTreeSet<_> = old.exposed_ports().into_iter().collect();
+  let new_ports: BTreeSet<_> = new.exposed_ports().into_iter().collect();
+  for port in old_ports.symmetric_difference(&new_ports) {
+    changes.push(ConfigChange::ExposedPort {
+      port: port.to_string(),
+      exposed: new_ports.contains(port),
+    });
+  }
+
+  if old.entrypoint() != new.entrypoint() {
+    changes.push(ConfigChange::Entrypoint {
+      old: old.entrypoint().map(<[String]>::to_vec),
+      new: new.entrypoint().map(<[String]>::to_vec),
+    });
+  }
+  if old.cmd() != new.cmd() {
+    changes.push(ConfigChange::Cmd {
+      old: old.cmd().map(<[String]>::to_vec),
+      new: new.cmd().map(<[String]>::to_vec),
+    });
+  }
+  // An empty user is the same as no user: both run as root.
+  let user = |m: &ManifestSchema2| m.user().filter(|u| !u.is_empty()).map(str::to_string);
+  if user(old) != user(new) {
+    changes.push(ConfigChange::User {
+      old: user(old),
+      new: user(new),
+    });
+  }
+
+  changes
+}
+
+/// Changed entries of two maps, as `(key, old, new)` sorted by key.
+fn diff_maps(
+  old: BTreeMap<String, String>,
+  mut new: BTreeMap<String, String>,
+) -> Vec<(String, Option<String>, Option<String>)> {
+  let mut changes = Vec::new();
+  for (key, old_value) in old {
+    match new.remove(&key) {
+      Some(new_value) if new_value == old_value => {}
+      new_value => changes.push((key, Some(old_value), new_value)),
+    }
+  }
+  changes.extend(new.into_iter().map(|(key, value)| (key, None, Some(value))));
+  changes.sort();
+  changes
+}
+
+impl FileIndex {
+  /// Compare this (old) image filesystem to `new`, see `Client::diff_files`.
+  pub fn diff(&self, new: &FileIndex) -> Vec<FileChange> {
+    let mut changes = Vec::new();
+    for old_entry in self.iter() {
+      match new.stat(&old_entry.path) {
+        None => changes.push(FileChange::Deleted(old_entry.path.clone())),
+        Some(new_entry) if self.is_modified(old_entry, new, new_entry) => {
+          changes.push(FileChange::Modified(old_entry.path.clone()))
+        }
+        Some(_) => {}
+      }
+    }
+    changes.extend(
+      new
+        .iter()
+        .filter(|e| self.stat(&e.path).is_none())
+        .map(|e| FileChange::Added(e.path.clone())),
+    );
+    changes.sort_by(|a, b| a.path().cmp(b.path()));
+    changes
+  }
+
+  fn is_modified(&self, old_entry: &FileEntry, new: &FileIndex, new_entry: &FileEntry) -> bool {
+    let metadata = |e: &FileEntry| (e.kind, e.mode, e.uid, e.gid);
+    if metadata(old_entry) != metadata(new_entry) {
+      return true;
+    }
+    // Directories are rewritten by every layer adding to them, only their metadata matters.
+    if old_entry.kind == FileKind::Directory {
+      return false;
+    }
+    old_entry.size != new_entry.size
+      || old_entry.mtime != new_entry.mtime
+      || old_entry.link_name != new_entry.link_name
+      || self.layers()[old_entry.layer].digest != new.layers()[new_entry.layer].digest
+  }
+}
+
+impl Client {
+  /// Compute the file changes between two images of repository `name`, from their layers.
+  ///
+  /// `old` and `new` are the layers of each image, base image first, as returned by
+  /// `Manifest::layers`. The filesystems of both images are indexed from the tar headers
+  /// of their layers and compared by path; see `Client::get_file_index`.
+  ///
+  /// Every layer of both images is streamed in full, including the base layers they share,
+  /// which are only streamed once: whiteouts and symlinks of the differing layers apply to
+  /// the whole filesystem below them. The cost is about that of pulling both images.
+  ///
+  /// Directories are modified if their type, mode or ownership changed. Other entries are
+  /// also modified if their header changed, or if they come from a different layer blob: a
+  /// file rewritten with the same content by a new layer is thus reported as modified.
+  pub async fn diff_files(&self, name: &str, old: &[Descriptor], new: &[Descriptor]) -> Result<Vec<FileChange>> {
+    let mut read = HashMap::new();
+    let old = self.index_layers(name, old.to_vec(), &mut read).await?;
+    let new = self.index_layers(name, new.to_vec(), &mut read).await?;
+    Ok(old.diff(&new))
+  }
+}
@@ -0,0 +1,335 @@
+//! Merged view of the filesystem of an image, from the headers of its layers.
+//!
+//! `Client::get_file_index` streams the layers once to index their tar headers, applying
+//! whiteouts as `render` would, but writes nothing to disk. Single files are then read by
+//! `Client::extract_file`, which only streams the topmost layer providing them.
+
+use std::{
+  collections::{BTreeMap, HashMap},
+  io::{self, Read},
+  ops::Bound,
+  path::{Component, Path, PathBuf},
+};
+
+use tar::EntryType;
+
+use crate::{
+  errors::Result,
+  mediatypes::Compression,
+  render::{self, WhiteoutEntry},
+  v2::{
+    manifest::{Descriptor, Manifest},
+    *,
+  },
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum FileError {
+  #[error("no such file or directory: {}", _0.display())]
+  NotFound(PathBuf),
+  #[error("not a regular file: {}", _0.display())]
+  NotAFile(PathBuf),
+  #[error("too many levels of symbolic links: {}", _0.display())]
+  SymlinkLoop(PathBuf),
+  #[error("file {} not found in layer {layer}", path.display())]
+  MissingFromLayer { path: PathBuf, layer: Digest },
+}
+
+/// Type of an entry of the image filesystem.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileKind {
+  File,
+  Directory,
+  Symlink,
+  /// A hard link to `link_name`.
+  HardLink,
+  /// Devices, FIFOs and other special files.
+  Other,
+}
+
+/// An entry of the image filesystem, as recorded in the topmost layer providing it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileEntry {
+  /// Path relative to the root of the image filesystem.
+  pub path: PathBuf,
+  pub kind: FileKind,
+  pub size: u64,
+  pub mode: u32,
+  pub uid: u64,
+  pub gid: u64,
+  /// Modification time, in seconds since the epoch.
+  pub mtime: u64,
+  /// Target of symlinks and hard links.
+  pub link_name: Option<PathBuf>,
+  /// Position of the layer providing the entry, in `FileIndex::layers`.
+  pub layer: usize,
+  /// Path of the entry in the layer tarball, which differs from `path` when a lower layer
+  /// made one of its parents a symlink.
+  layer_path: PathBuf,
+}
+
+/// Merged index of the filesystem of an image, where upper layers win.
+///
+/// Directories without an entry in any layer, but with descendants, are indexed too.
+#[derive(Clone, Debug, Default)]
+pub struct FileIndex {
+  layers: Vec<Descriptor>,
+  entries: BTreeMap<PathBuf, FileEntry>,
+}
+
+impl FileIndex {
+  /// The layers of the image, lower layers first.
+  pub fn layers(&self) -> &[Descriptor] {
+    &self.layers
+  }
+
+  /// Iterate over all entries, sorted by path.
+  pub fn iter(&self) -> impl Iterator<Item = &FileEntry> {
+    self.entries.values()
+  }
+
+  /// Get the entry of `path`, without following symlinks.
+  pub fn stat<P: AsRef<Path>>(&self, path: P) -> Option<&FileEntry> {
+    self.entries.get(&render::normalize_path(path.as_ref()))
+  }
+
+  /// Get the entry of `path`, following symlinks in all its components.
+  pub fn resolve<P: AsRef<Path>>(&self, path: P) -> std::result::Result<&FileEntry, FileError> {
+    let path = self.resolve_path(path.as_ref(), true)?;
+    self.entries.get(&path).ok_or(FileError::NotFound(path))
+  }
+
+  /// List the entries of directory `dir`, following symlinks, or `None` if it is not a directory.
+  pub fn list<P: AsRef<Path>>(&self, dir: P) -> Option<Vec<&FileEntry>> {
+    let dir = self.resolve_path(dir.as_ref(), true).ok()?;
+    if !dir.as_os_str().is_empty() && self.entries.get(&dir)?.kind != FileKind::Directory {
+      return None;
+    }
+    let children = self
+      .entries
+      .range::<Path, _>((Bound::Excluded(dir.as_path()), Bound::Unbounded))
+      .take_while(|(path, _)| path.starts_with(&dir))
+      .filter(|(path, _)| path.parent() == Some(dir.as_path()))
+      .map(|(_, entry)| entry);
+    Some(children.collect())
+  }
+
+  /// Resolve the symlinks of `path` within the image, as a chroot would.
+  ///
+  /// The last component is only followed if `follow` is set.
+  fn resolve_path(&self, path: &Path, follow: bool) -> std::result::Result<PathBuf, FileError> {
+    let read_link = |candidate: &Path| {
+      Ok(match self.entries.get(candidate) {
+        Some(FileEntry {
+          kind: FileKind::Symlink,
+          link_name: Some(target),
+          ..
+        }) => Some(target.clone()),
+        _ => None,
+      })
+    };
+    render::resolve_symlinks(path, follow, read_link, || FileError::SymlinkLoop(path.to_path_buf()))
+  }
+
+  /// Apply a layer on top of the index.
+  fn apply_layer(&mut self, layer: usize, entries: &[LayerEntry]) {
+    // Whiteouts only hide lower layers, wherever they come in the archive.
+    for entry in entries {
+      match entry {
+        LayerEntry::Whiteout(path) => self.remove_tree(&self.resolve_parents(path, false), true),
+        LayerEntry::Opaque(dir) => self.remove_tree(&self.resolve_parents(dir, true), false),
+        LayerEntry::File(_) => {}
+      }
+    }
+    for entry in entries {
+      let LayerEntry::File(entry) = entry else {
+        continue;
+      };
+      // Entries go where `render` writes them, through the symlinks of lower layers.
+      let entry = FileEntry {
+        path: self.resolve_parents(&entry.path, false),
+        layer,
+        ..entry.clone()
+      };
+      // Something else replacing a directory replaces its content too.
+      if entry.kind != FileKind::Directory {
+        self.remove_tree(&entry.path, false);
+      }
+      for parent in entry.path.ancestors().skip(1) {
+        if parent.as_os_str().is_empty() || self.entries.contains_key(parent) {
+          break;
+        }
+        self.entries.insert(
+          parent.to_path_buf(),
+          FileEntry {
+            path: parent.to_path_buf(),
+            kind: FileKind::Directory,
+            size: 0,
+            mode: 0o755,
+            uid: 0,
+            gid: 0,
+            mtime: 0,
+            link_name: None,
+            layer,
+            layer_path: parent.to_path_buf(),
+          },
+        );
+      }
+      self.entries.insert(entry.path.clone(), entry);
+    }
+  }
+
+  /// Resolve `path` as `resolve_path`, keeping it as is if it has a symlink loop.
+  fn resolve_parents(&self, path: &Path, follow: bool) -> PathBuf {
+    self.resolve_path(path, follow).unwrap_or_else(|_| path.to_path_buf())
+  }
+
+  /// Remove the descendants of `path`, and `path` itself if `include_self`.
+  fn remove_tree(&mut self, path: &Path, include_self: bool) {
+    let descendants: Vec<_> = self
+      .entries
+      .range::<Path, _>((Bound::Excluded(path), Bound::Unbounded))
+      .take_while(|(p, _)| p.starts_with(path))
+      .map(|(p, _)| p.clone())
+      .collect();
+    for p in descendants {
+      self.entries.remove(&p);
+    }
+    if include_self {
+      self.entries.remove(path);
+    }
+  }
+}
+
+/// An entry of a layer tarball, with whiteouts resolved to the path they hide.
+#[derive(Clone, Debug)]
+pub(crate) enum LayerEntry {
+  File(FileEntry),
+  Whiteout(PathBuf),
+  Opaque(PathBuf),
+}
+
+impl Client {
+  /// Index the filesystem of the image `manifest` from repository `name`.
+  ///
+  /// Every layer is streamed once, but only the tar headers are kept.
+  pub async fn get_file_index(&self, name: &str, manifest: &Manifest) -> Result<FileIndex> {
+    let layers = pull::pull_descriptors(manifest)?;
+    self.index_layers(name, layers, &mut HashMap::new()).await
+  }
+
+  /// Index the filesystem made of `layers`, lower layers first.
+  ///
+  /// The headers of the layers already in `read` are reused, the others are added to it.
+  pub(crate) async fn index_layers(
+    &self,
+    name: &str,
+    layers: Vec<Descriptor>,
+    read: &mut HashMap<Digest, Vec<LayerEntry>>,
+  ) -> Result<FileIndex> {
+    let mut index = FileIndex::default();
+    for (i, layer) in layers.iter().enumerate() {
+      if !read.contains_key(&layer.digest) {
+        let compression = manifest::parse_media_type(&layer.media_type).layer_compression();
+        let entries = self
+          .read_blob_blocking(name, layer, move |reader| read_layer_headers(reader, compression))
+          .await?;
+        read.insert(layer.digest.clone(), entries);
+      }
+      index.apply_layer(i, &read[&layer.digest]);
+    }
+    index.layers = layers;
+    Ok(index)
+  }
+
+  /// Read the content of the regular file `path` of an indexed image of repository `name`.
+  ///
+  /// Symlinks and hard links are followed within the image. Only the topmost layer
+  /// providing the file is streamed.
+  pub async fn extract_file<P: AsRef<Path>>(&self, name: &str, index: &FileIndex, path: P) -> Result<Vec<u8>> {
+    let mut entry = index.resolve(path.as_ref())?;
+    if entry.kind == FileKind::HardLink {
+      let target = entry.link_name.as_deref().unwrap_or(Path::new(""));
+      entry = index.resolve(target)?;
+    }
+    if entry.kind != FileKind::File {
+      return Err(FileError::NotAFile(entry.path.clone()).into());
+    }
+
+    let layer = &index.layers[entry.layer];
+    let compression = manifest::parse_media_type(&layer.media_type).layer_compression();
+    let file_path = entry.layer_path.clone();
+    let content = self
+      .read_blob_blocking(name, layer, move |reader| {
+        read_layer_file(reader, compression, &file_path)
+      })
+      .await?;
+    content.ok_or_else(|| {
+      FileError::MissingFromLayer {
+        path: entry.path.clone(),
+        layer: layer.digest.clone(),
+      }
+      .into()
+    })
+  }
+}
+
+fn read_layer_headers(reader: &mut dyn Read, compression: Compression) -> io::Result<Vec<LayerEntry>> {
+  let mut entries = Vec::new();
+  let mut archive = tar::Archive::new(render::decompress(reader, compression)?);
+  for entry in archive.entries()? {
+    let entry = entry?;
+    let path = render::normalize_path(&entry.path()?);
+    // Skip the root itself, and paths trying to get out of it as `render` does.
+    if path.file_name().is_none() || path.components().any(|c| c == Component::ParentDir) {
+      continue;
+    }
+    if let Some(whiteout) = render::parse_whiteout(&path) {
+      entries.push(match whiteout {
+        WhiteoutEntry::Remove(hidden) => LayerEntry::Whiteout(hidden),
+        WhiteoutEntry::Opaque(dir) => LayerEntry::Opaque(dir),
+      });
+      continue;
+    }
+
+    let header = entry.header();
+    let kind = match header.entry_type() {
+      EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => FileKind::File,
+      EntryType::Directory => FileKind::Directory,
+      EntryType::Symlink => FileKind::Symlink,
+      EntryType::Link => FileKind::HardLink,
+      _ => FileKind::Other,
+    };
+    entries.push(LayerEntry::File(FileEntry {
+      kind,
+      size: entry.size(),
+      mode: header.mode()?,
+      uid: header.uid()?,
+      gid: header.gid()?,
+      mtime: header.mtime()?,
+      link_name: entry.link_name()?.map(|l| l.into_owned()),
+      layer: 0,
+      layer_path: path.clone(),
+      path,
+    }));
+  }
+  Ok(entries)
+}
+
+/// Read the content of the file `path` from a layer, if it is there.
+///
+/// A path may appear several times in a tarball: the last entry wins, as in the index.
+fn read_layer_file(reader: &mut dyn Read, compression: Compression, path: &Path) -> io::Result<Option<Vec<u8>>> {
+  let mut archive = tar::Archive::new(render::decompress(reader, compression)?);
+  let mut content = None;
+  for entry in archive.entries()? {
+    let mut entry = entry?;
+    if render::normalize_path(&entry.path()?) == path && entry.header().entry_type() != EntryType::Link {
+      // The size comes from the archive, it is not trusted to preallocate.
+      let mut file = Vec::new();
+      entry.read_to_end(&mut file)?;
+      content = Some(file);
+    }
+  }
+  Ok(content)
+}
@@ -0,0 +1,334 @@
+//! Builders for image manifests and indexes.
+
+use std::{collections::BTreeMap, str::FromStr};
+
+use crate::{
+  errors::Result,
+  mediatypes::MediaTypes,
+  v2::{
+    Digest,
+    manifest::{Descriptor, ManifestList, ManifestSchema2Spec},
+  },
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ManifestBuildError {
+  #[error("media type {0} is not supported by this builder")]
+  UnsupportedMediaType(MediaTypes),
+  #[error("manifest has no config descriptor")]
+  MissingConfig,
+  #[error("descriptor {0} has an empty media type")]
+  MissingMediaType(Digest),
+  #[error("artifactType is required when the config is the empty descriptor")]
+  MissingArtifactType,
+  #[error("manifest list entry {0} has no platform")]
+  MissingPlatform(Digest),
+  #[error("{field} is not supported by media type {media_type}")]
+  UnsupportedField {
+    field: &'static str,
+    media_type: MediaTypes,
+  },
+  #[error("descriptor {digest} has media type {media_type}, which is not allowed in a {field}")]
+  InvalidDescriptor {
+    field: &'static str,
+    digest: Digest,
+    media_type: String,
+  },
+}
+
+/// A manifest serialized for a push, along with its digest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncodedManifest {
+  pub media_type: String,
+  pub bytes: Vec<u8>,
+  pub digest: Digest,
+}
+
+impl EncodedManifest {
+  fn new(media_type: &str, bytes: Vec<u8>) -> Self {
+    Self {
+      media_type: media_type.to_string(),
+      digest: Digest::sha256(&bytes),
+      bytes,
+    }
+  }
+
+  /// Get a `Descriptor` for this manifest, e.g. to reference it from an index.
+  pub fn descriptor(&self) -> Descriptor {
+    Descriptor::new(&self.media_type, self.digest.clone(), self.bytes.len() as u64)
+  }
+}
+
+/// Builder for a Docker schema 2 or OCI image manifest.
+#[derive(Debug)]
+pub struct ManifestBuilder {
+  media_type: MediaTypes,
+  artifact_type: Option<String>,
+  config: Option<Descriptor>,
+  layers: Vec<Descriptor>,
+  subject: Option<Descriptor>,
+  annotations: BTreeMap<String, String>,
+}
+
+impl ManifestBuilder {
+  /// Set the media type of the manifest, `MediaTypes::OciImageManifest` by default.
+  pub fn media_type(mut self, media_type: MediaTypes) -> Self {
+    self.media_type = media_type;
+    self
+  }
+
+  /// Set the artifact type of the manifest (OCI only).
+  pub fn artifact_type(mut self, artifact_type: &str) -> Self {
+    self.artifact_type = Some(artifact_type.to_string());
+    self
+  }
+
+  /// Set the config descriptor.
+  pub fn config(mut self, config: Descriptor) -> Self {
+    self.config = Some(config);
+    self
+  }
+
+  /// Append a layer descriptor; layers are ordered starting with the base image first.
+  pub fn layer(mut self, layer: Descriptor) -> Self {
+    self.layers.push(layer);
+    self
+  }
+
+  /// Append multiple layer descriptors.
+  pub fn layers<I: IntoIterator<Item = Descriptor>>(mut self, layers: I) -> Self {
+    self.layers.extend(layers);
+    self
+  }
+
+  /// Set the manifest this one refers to (OCI only).
+  pub fn subject(mut self, subject: Descriptor) -> Self {
+    self.subject = Some(subject);
+    self
+  }
+
+  /// Add an annotation (OCI only).
+  pub fn annotation(mut self, key: &str, value: &str) -> Self {
+    self.annotations.insert(key.to_string(), value.to_string());
+    self
+  }
+
+  /// Validate the manifest and return it.
+  pub fn build(self) -> Result<ManifestSchema2Spec> {
+    let config = self.config.ok_or(ManifestBuildError::MissingConfig)?;
+    for descriptor in std::iter::once(&config).chain(&self.layers).chain(&self.subject) {
+      check_media_type(descriptor)?;
+    }
+
+    match self.media_type {
+      MediaTypes::ManifestV2S2 => {
+        reject_oci_fields(&self.media_type, &self.artifact_type, &self.subject, &self.annotations)?;
+        let config_type = parse_media_type(&config.media_type);
+        if !matches!(config_type, MediaTypes::ContainerConfigV1 | MediaTypes::PluginConfigV1) {
+          return Err(invalid_descriptor("config", &config).into());
+        }
+        if let Some(layer) = self.layers.iter().find(|l| {
+          !matches!(
+            parse_media_type(&l.media_type),
+            MediaTypes::ImageLayerTgz | MediaTypes::ImageLayerForeignTgz
+          )
+        }) {
+          return Err(invalid_descriptor("layer", layer).into());
+        }
+      }
+      MediaTypes::OciImageManifest => {
+        // Artifacts may use any media type for their config and layers, but not manifests.
+        let is_manifest = |d: &Descriptor| {
+          let media_type = parse_media_type(&d.media_type);
+          media_type.is_manifest() || media_type.is_index()
+        };
+        if is_manifest(&config) {
+          return Err(invalid_descriptor("config", &config).into());
+        }
+        if let Some(layer) = self.layers.iter().find(|l| is_manifest(l)) {
+          return Err(invalid_descriptor("layer", layer).into());
+        }
+        if parse_media_type(&config.media_type) == MediaTypes::OciEmpty && self.artifact_type.is_none() {
+          return Err(ManifestBuildError::MissingArtifactType.into());
+        }
+      }
+      other => return Err(ManifestBuildError::UnsupportedMediaType(other).into()),
+    }
+
+    let mut manifest = ManifestSchema2Spec::new(&self.media_type, config, self.layers);
+    manifest.artifact_type = self.artifact_type;
+    manifest.subject = self.subject;
+    manifest.annotations = Some(self.annotations).filter(|a| !a.is_empty());
+    Ok(manifest)
+  }
+}
+
+/// Builder for a Docker manifest list or OCI image index.
+#[derive(Debug)]
+pub struct IndexBuilder {
+  media_type: MediaTypes,
+  artifact_type: Option<String>,
+  manifests: Vec<Descriptor>,
+  subject: Option<Descriptor>,
+  annotations: BTreeMap<String, String>,
+}
+
+impl IndexBuilder {
+  /// Set the media type of the index, `MediaTypes::OciImageIndexV1` by default.
+  pub fn media_type(mut self, media_type: MediaTypes) -> Self {
+    self.media_type = media_type;
+    self
+  }
+
+  /// Set the artifact type of the index (OCI only).
+  pub fn artifact_type(mut self, artifact_type: &str) -> Self {
+    self.artifact_type = Some(artifact_type.to_string());
+    self
+  }
+
+  /// Append a manifest descriptor.
+  pub fn manifest(mut self, manifest: Descriptor) -> Self {
+    self.manifests.push(manifest);
+    self
+  }
+
+  /// Append multiple manifest descriptors.
+  pub fn manifests<I: IntoIterator<Item = Descriptor>>(mut self, manifests: I) -> Self {
+    self.manifests.extend(manifests);
+    self
+  }
+
+  /// Set the manifest this index refers to (OCI only).
+  pub fn subject(mut self, subject: Descriptor) -> Self {
+    self.subject = Some(subject);
+    self
+  }
+
+  /// Add an annotation (OCI only).
+  pub fn annotation(mut self, key: &str, value: &str) -> Self {
+    self.annotations.insert(key.to_string(), value.to_string());
+    self
+  }
+
+  /// Validate the index and return it.
+  pub fn build(self) -> Result<ManifestList> {
+    for descriptor in self.manifests.iter().chain(&self.subject) {
+      check_media_type(descriptor)?;
+    }
+
+    match self.media_type {
+      MediaTypes::ManifestList => {
+        reject_oci_fields(&self.media_type, &self.artifact_type, &self.subject, &self.annotations)?;
+        for manifest in &self.manifests {
+          if !parse_media_type(&manifest.media_type).is_manifest() {
+            return Err(invalid_descriptor("manifest list", manifest).into());
+          }
+          if manifest.platform.is_none() {
+            return Err(ManifestBuildError::MissingPlatform(manifest.digest.clone()).into());
+          }
+        }
+      }
+      MediaTypes::OciImageIndexV1 => {
+        for manifest in &self.manifests {
+          let media_type = parse_media_type(&manifest.media_type);
+          if !media_type.is_manifest() && !media_type.is_index() {
+            return Err(invalid_descriptor("image index", manifest).into());
+          }
+        }
+      }
+      other => return Err(ManifestBuildError::UnsupportedMediaType(other).into()),
+    }
+
+    Ok(ManifestList {
+      schema_version: 2,
+      media_type: self.media_type.to_string(),
+      artifact_type: self.artifact_type,
+      manifests: self.manifests,
+      subject: self.subject,
+      annotations: Some(self.annotations).filter(|a| !a.is_empty()),
+    })
+  }
+}
+
+impl ManifestSchema2Spec {
+  /// Start building an image manifest.
+  pub fn builder() -> ManifestBuilder {
+    ManifestBuilder {
+      media_type: MediaTypes::OciImageManifest,
+      artifact_type: None,
+      config: None,
+      layers: Vec::new(),
+      subject: None,
+      annotations: BTreeMap::new(),
+    }
+  }
+
+  /// Serialize this manifest to compact JSON and compute its digest.
+  ///
+  /// Fields are emitted in a fixed order and annotations sorted by key, so encoding
+  /// the same manifest always yields the same bytes.
+  pub fn encode(&self) -> Result<EncodedManifest> {
+    Ok(EncodedManifest::new(&self.media_type, serde_json::to_vec(self)?))
+  }
+}
+
+impl ManifestList {
+  /// Start building a manifest list or image index.
+  pub fn builder() -> IndexBuilder {
+    IndexBuilder {
+      media_type: MediaTypes::OciImageIndexV1,
+      artifact_type: None,
+      manifests: Vec::new(),
+      subject: None,
+      annotations: BTreeMap::new(),
+    }
+  }
+
+  /// Serialize this manifest list to compact JSON and compute its digest.
+  ///
+  /// See `ManifestSchema2Spec::encode`.
+  pub fn encode(&self) -> Result<EncodedManifest> {
+    Ok(EncodedManifest::new(&self.media_type, serde_json::to_vec(self)?))
+  }
+}
+
+pub(crate) fn parse_media_type(media_type: &str) -> MediaTypes {
+  MediaTypes::from_str(media_type).expect("parsing falls back to MediaTypes::Other")
+}
+
+fn check_media_type(descriptor: &Descriptor) -> std::result::Result<(), ManifestBuildError> {
+  match descriptor.media_type.is_empty() {
+    true => Err(ManifestBuildError::MissingMediaType(descriptor.digest.clone())),
+    false => Ok(()),
+  }
+}
+
+fn invalid_descriptor(field: &'static str, descriptor: &Descriptor) -> ManifestBuildError {
+  ManifestBuildError::InvalidDescriptor {
+    field,
+    digest: descriptor.digest.clone(),
+    media_type: descriptor.media_type.clone(),
+  }
+}
+
+// Docker manifests and manifest lists do not define these OCI fields.
+fn reject_oci_fields(
+  media_type: &MediaTypes,
+  artifact_type: &Option<String>,
+  subject: &Option<Descriptor>,
+  annotations: &BTreeMap<String, String>,
+) -> std::result::Result<(), ManifestBuildError> {
+  let field = if artifact_type.is_some() {
+    "artifactType"
+  } else if subject.is_some() {
+    "subject"
+  } else if !annotations.is_empty() {
+    "annotations"
+  } else {
+    return Ok(());
+  };
+  Err(ManifestBuildError::UnsupportedField {
+    field,
+    media_type: media_type.clone(),
+  })
+}
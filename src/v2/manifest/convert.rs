@@ -0,0 +1,365 @@
+//! Conversions between manifest formats.
+
+use std::io::Read;
+
+use libflate::gzip;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{
+  errors::Result,
+  mediatypes::MediaTypes,
+  v2::{
+    Digest, DigestAlgorithm,
+    manifest::{Descriptor, ManifestList, ManifestSchema1Signed, ManifestSchema2Spec, builder::parse_media_type},
+  },
+};
+
+/// Keys of a `v1Compatibility` entry which do not belong into an image config.
+const V1_ONLY_KEYS: &[&str] = &["id", "parent", "Size", "parent_id", "layer_id", "throwaway"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConversionError {
+  #[error("cannot convert to media type {0}")]
+  UnsupportedTarget(MediaTypes),
+  #[error("manifest has no history")]
+  EmptyHistory,
+  #[error("manifest has a different number of layers and history entries")]
+  HistoryMismatch,
+  #[error("expected {expected} layer blobs, got {got}")]
+  LayerCount { expected: usize, got: usize },
+  #[error("invalid v1Compatibility entry: {0}")]
+  InvalidV1Compatibility(String),
+}
+
+/// A manifest produced by a conversion, along with the config blob it references.
+///
+/// The config blob is not known to the registry yet and has to be pushed
+/// alongside the manifest.
+#[derive(Debug)]
+pub struct ConvertedManifest {
+  pub manifest: ManifestSchema2Spec,
+  pub config: Vec<u8>,
+}
+
+/// Information which could not be carried over by a conversion.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConversionLoss {
+  /// Annotations were dropped, from the manifest itself (`None`) or from the descriptor with the given digest.
+  Annotations(Option<Digest>),
+  /// The artifact type was dropped, from the manifest itself (`None`) or from the descriptor with the given digest.
+  ArtifactType(Option<Digest>),
+  /// The `subject` of the manifest was dropped.
+  Subject,
+  /// A media type without counterpart in the target format was kept as is.
+  MediaType { digest: Digest, media_type: String },
+}
+
+/// Report of the information lost by a Docker ⇄ OCI conversion.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConversionReport {
+  pub losses: Vec<ConversionLoss>,
+  /// Entries of a converted index whose media type was mapped, while their digest and
+  /// size still describe the original manifest.
+  ///
+  /// Each of these manifests has to be converted and pushed, and its entry re-pointed
+  /// to the result, before the index is valid.
+  pub unconverted: Vec<Descriptor>,
+}
+
+impl ConversionReport {
+  /// Whether the conversion carried over all information.
+  pub fn is_lossless(&self) -> bool {
+    self.losses.is_empty()
+  }
+
+  /// Whether the converted manifest can be used as is, i.e. no index entry has to be re-pointed.
+  pub fn is_complete(&self) -> bool {
+    self.unconverted.is_empty()
+  }
+}
+
+/// Target format of a Docker ⇄ OCI conversion.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+  Docker,
+  Oci,
+}
+
+impl Format {
+  /// Map a media type to its counterpart in this format.
+  ///
+  /// Returns `None` if the media type has no counterpart; OCI manifests may reference
+  /// arbitrary (artifact) media types, while Docker ones are restricted to Docker types.
+  fn media_type(self, media_type: &MediaTypes) -> Option<MediaTypes> {
+    use MediaTypes::*;
+
+    let mapped = match (self, media_type) {
+      (Format::Oci, ManifestV2S2) => OciImageManifest,
+      (Format::Oci, ManifestList) => OciImageIndexV1,
+      (Format::Oci, ContainerConfigV1) => OciImageConfig,
+      (Format::Oci, ImageLayerTgz) => OciImageLayerTgz,
+      (Format::Oci, ImageLayerForeignTgz) => OciImageLayerNondistributableTgz,
+      (Format::Oci, ManifestV2S1 | ManifestV2S1Signed | PluginConfigV1) => return None,
+      (Format::Docker, OciImageManifest) => ManifestV2S2,
+      (Format::Docker, OciImageIndexV1) => ManifestList,
+      (Format::Docker, OciImageConfig) => ContainerConfigV1,
+      (Format::Docker, OciImageLayerTgz) => ImageLayerTgz,
+      (Format::Docker, OciImageLayerNondistributableTgz) => ImageLayerForeignTgz,
+      (
+        Format::Docker,
+        ManifestV2S1 | ManifestV2S1Signed | ManifestV2S2 | ManifestList | ImageLayerTgz | ImageLayerForeignTgz
+        | ContainerConfigV1 | PluginConfigV1,
+      ) => media_type.clone(),
+      (Format::Docker, _) => return None,
+      (Format::Oci, other) => other.clone(),
+    };
+    Some(mapped)
+  }
+
+  fn descriptor(self, descriptor: &Descriptor, report: &mut ConversionReport) -> Descriptor {
+    let mut descriptor = descriptor.clone();
+    match self.media_type(&parse_media_type(&descriptor.media_type)) {
+      Some(mapped) => descriptor.media_type = mapped.to_string(),
+      None => report.losses.push(ConversionLoss::MediaType {
+        digest: descriptor.digest.clone(),
+        media_type: descriptor.media_type.clone(),
+      }),
+    }
+    if self == Format::Docker {
+      if descriptor.annotations.take().is_some() {
+        report
+          .losses
+          .push(ConversionLoss::Annotations(Some(descriptor.digest.clone())));
+      }
+      if descriptor.artifact_type.take().is_some() {
+        report
+          .losses
+          .push(ConversionLoss::ArtifactType(Some(descriptor.digest.clone())));
+      }
+    }
+    descriptor
+  }
+
+  /// Drop the manifest-level fields which Docker manifests do not define.
+  fn strip<T>(
+    self,
+    artifact_type: &mut Option<String>,
+    subject: &mut Option<Descriptor>,
+    annotations: &mut Option<T>,
+    report: &mut ConversionReport,
+  ) {
+    if self == Format::Oci {
+      return;
+    }
+    if artifact_type.take().is_some() {
+      report.losses.push(ConversionLoss::ArtifactType(None));
+    }
+    if subject.take().is_some() {
+      report.losses.push(ConversionLoss::Subject);
+    }
+    if annotations.take().is_some() {
+      report.losses.push(ConversionLoss::Annotations(None));
+    }
+  }
+
+  fn manifest(self, manifest: &ManifestSchema2Spec) -> (ManifestSchema2Spec, ConversionReport) {
+    let mut report = ConversionReport::default();
+    let mut converted = manifest.clone();
+    converted.media_type = match self {
+      Format::Docker => MediaTypes::ManifestV2S2,
+      Format::Oci => MediaTypes::OciImageManifest,
+    }
+    .to_string();
+    converted.config = self.descriptor(&manifest.config, &mut report);
+    converted.layers = manifest
+      .layers
+      .iter()
+      .map(|l| self.descriptor(l, &mut report))
+      .collect();
+    self.strip(
+      &mut converted.artifact_type,
+      &mut converted.subject,
+      &mut converted.annotations,
+      &mut report,
+    );
+    (converted, report)
+  }
+
+  fn list(self, list: &ManifestList) -> (ManifestList, ConversionReport) {
+    let mut report = ConversionReport::default();
+    let mut converted = list.clone();
+    converted.media_type = match self {
+      Format::Docker => MediaTypes::ManifestList,
+      Format::Oci => MediaTypes::OciImageIndexV1,
+    }
+    .to_string();
+    converted.manifests = list.manifests.iter().map(|m| self.descriptor(m, &mut report)).collect();
+    report.unconverted = converted
+      .manifests
+      .iter()
+      .zip(&list.manifests)
+      .filter(|(converted, original)| converted.media_type != original.media_type)
+      .map(|(converted, _)| converted.clone())
+      .collect();
+    self.strip(
+      &mut converted.artifact_type,
+      &mut converted.subject,
+      &mut converted.annotations,
+      &mut report,
+    );
+    (converted, report)
+  }
+}
+
+impl ManifestSchema2Spec {
+  /// Convert this manifest to an OCI image manifest.
+  ///
+  /// Docker media types of the config and layers are mapped to their OCI counterparts,
+  /// foreign layers becoming non-distributable ones. Digests are left untouched, as
+  /// the referenced blobs do not change.
+  pub fn to_oci(&self) -> (ManifestSchema2Spec, ConversionReport) {
+    Format::Oci.manifest(self)
+  }
+
+  /// Convert this manifest to a Docker schema 2 manifest.
+  ///
+  /// OCI media types of the config and layers are mapped to their Docker counterparts.
+  /// Media types without counterpart (e.g. zstd layers) are kept as is, while annotations,
+  /// `artifactType` and `subject` are dropped; both are recorded in the returned report.
+  pub fn to_docker(&self) -> (ManifestSchema2Spec, ConversionReport) {
+    Format::Docker.manifest(self)
+  }
+}
+
+impl ManifestList {
+  /// Convert this manifest list to an OCI image index.
+  ///
+  /// The media types of the referenced manifests are mapped as well, but the manifests
+  /// themselves have to be converted separately, which changes their digests. The entries
+  /// to re-point once done are listed in `ConversionReport::unconverted`.
+  pub fn to_oci(&self) -> (ManifestList, ConversionReport) {
+    Format::Oci.list(self)
+  }
+
+  /// Convert this OCI image index to a Docker manifest list.
+  ///
+  /// See `ManifestSchema2Spec::to_docker` for the information which cannot be carried over,
+  /// and `to_oci` for the entries to re-point.
+  pub fn to_docker(&self) -> (ManifestList, ConversionReport) {
+    Format::Docker.list(self)
+  }
+}
+
+/// History entry of an image config.
+#[derive(Debug, Serialize)]
+struct ConfigHistory {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  created: Option<Value>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  author: Option<Value>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  created_by: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  comment: Option<Value>,
+  #[serde(skip_serializing_if = "std::ops::Not::not")]
+  empty_layer: bool,
+}
+
+impl ManifestSchema1Signed {
+  /// Convert this manifest to a Docker schema 2 or OCI image manifest.
+  ///
+  /// `layers` are the layer blobs, in the order given by `get_layers_digests()`
+  /// (base image first), and are checked against their digests. An image config is
+  /// synthesized from the `v1Compatibility` history: layers marked as `throwaway` are
+  /// recorded as `empty_layer` history entries and dropped from the manifest, while
+  /// the `diff_ids` of the remaining layers are computed from their uncompressed content.
+  ///
+  /// `target` must be either `MediaTypes::ManifestV2S2` or `MediaTypes::OciImageManifest`.
+  pub fn convert(&self, target: MediaTypes, layers: &[Vec<u8>]) -> Result<ConvertedManifest> {
+    let (config_type, layer_type) = match target {
+      MediaTypes::ManifestV2S2 => (MediaTypes::ContainerConfigV1, MediaTypes::ImageLayerTgz),
+      MediaTypes::OciImageManifest => (MediaTypes::OciImageConfig, MediaTypes::OciImageLayerTgz),
+      other => return Err(ConversionError::UnsupportedTarget(other).into()),
+    };
+
+    let entries = self.layers_history().ok_or(ConversionError::HistoryMismatch)?;
+    if entries.len() != layers.len() {
+      return Err(
+        ConversionError::LayerCount {
+          expected: entries.len(),
+          got: layers.len(),
+        }
+        .into(),
+      );
+    }
+
+    let mut descriptors = Vec::new();
+    let mut diff_ids = Vec::new();
+    let mut history = Vec::new();
+    let mut config = None;
+
+    for ((digest, v1_compat), blob) in entries.into_iter().zip(layers) {
+      digest.verify(blob)?;
+
+      let v1: Map<String, Value> =
+        serde_json::from_str(v1_compat).map_err(|e| ConversionError::InvalidV1Compatibility(e.to_string()))?;
+      let empty_layer = v1.get("throwaway").and_then(Value::as_bool).unwrap_or(false);
+      let created_by = v1
+        .get("container_config")
+        .and_then(|c| c.get("Cmd"))
+        .and_then(Value::as_array)
+        .map(|cmd| cmd.iter().filter_map(Value::as_str).collect::<Vec<_>>().join(" "));
+
+      history.push(ConfigHistory {
+        created: v1.get("created").cloned(),
+        author: v1.get("author").cloned(),
+        created_by,
+        comment: v1.get("comment").cloned(),
+        empty_layer,
+      });
+
+      if !empty_layer {
+        diff_ids.push(diff_id(blob)?.to_string());
+        descriptors.push(Descriptor::new(
+          &layer_type.to_string(),
+          digest.clone(),
+          blob.len() as u64,
+        ));
+      }
+
+      // The top-most entry carries the configuration of the resulting image.
+      config = Some(v1);
+    }
+
+    let mut config = config.ok_or(ConversionError::EmptyHistory)?;
+    for key in V1_ONLY_KEYS {
+      config.remove(*key);
+    }
+    config.insert(
+      "rootfs".to_string(),
+      serde_json::json!({ "type": "layers", "diff_ids": diff_ids }),
+    );
+    config.insert("history".to_string(), serde_json::to_value(history)?);
+
+    let config = serde_json::to_vec(&config)?;
+    let config_descriptor = Descriptor::new(&config_type.to_string(), Digest::sha256(&config), config.len() as u64);
+
+    Ok(ConvertedManifest {
+      manifest: ManifestSchema2Spec::new(&target, config_descriptor, descriptors),
+      config,
+    })
+  }
+}
+
+/// Compute the digest of the uncompressed content of a layer blob.
+///
+/// The compression is detected from the magic bytes of the blob.
+pub(crate) fn diff_id(blob: &[u8]) -> Result<Digest> {
+  let reader: Box<dyn Read + '_> = match blob {
+    [0x1f, 0x8b, ..] => Box::new(gzip::Decoder::new(blob)?),
+    [0x28, 0xb5, 0x2f, 0xfd, ..] => Box::new(zstd::Decoder::new(blob)?),
+    _ => Box::new(blob),
+  };
+  Digest::from_reader(DigestAlgorithm::Sha256, reader)
+}
@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::v2::{Digest, manifest::Platform};
+
+/// Annotation set by BuildKit on index entries which are not images.
+const ANNOTATION_REFERENCE_TYPE: &str = "vnd.docker.reference.type";
+/// Annotation set by BuildKit on attestation manifests, pointing to the attested image.
+const ANNOTATION_REFERENCE_DIGEST: &str = "vnd.docker.reference.digest";
+const REFERENCE_TYPE_ATTESTATION: &str = "attestation-manifest";
+
+/// Content descriptor, pointing to a blob or manifest by digest.
+///
+/// This is the common shape shared by layers and configs of image manifests and
+/// by the entries of manifest lists / image indexes.
+///
+/// Specification is at <https://github.com/opencontainers/image-spec/blob/main/descriptor.md>.
+///
+/// Schema 1 manifests do not record blob sizes; descriptors derived from them
+/// report a `size` of 0, which is treated as "unknown" when fetching blobs.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Descriptor {
+  #[serde(rename = "mediaType")]
+  pub media_type: String,
+  pub digest: Digest,
+  #[serde(default)]
+  pub size: u64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub urls: Option<Vec<String>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub annotations: Option<BTreeMap<String, String>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub platform: Option<Platform>,
+  #[serde(rename = "artifactType", skip_serializing_if = "Option::is_none")]
+  pub artifact_type: Option<String>,
+}
+
+impl Descriptor {
+  /// Create a descriptor from its required fields.
+  pub fn new(media_type: &str, digest: Digest, size: u64) -> Self {
+    Self {
+      media_type: media_type.to_string(),
+      digest,
+      size,
+      urls: None,
+      annotations: None,
+      platform: None,
+      artifact_type: None,
+    }
+  }
+
+  /// Get the architecture of the platform this descriptor targets, if any.
+  pub fn architecture(&self) -> Option<String> {
+    self.platform.as_ref().map(|p| p.architecture.to_owned())
+  }
+
+  /// Get the value of an annotation, if present.
+  pub fn annotation(&self, key: &str) -> Option<&str> {
+    self.annotations.as_ref()?.get(key).map(String::as_str)
+  }
+
+  /// Whether this describes a BuildKit attestation manifest, rather than an image.
+  pub fn is_attestation(&self) -> bool {
+    self.annotation(ANNOTATION_REFERENCE_TYPE) == Some(REFERENCE_TYPE_ATTESTATION)
+  }
+
+  /// Get the digest of the image an attestation manifest refers to.
+  pub fn reference_digest(&self) -> Option<Digest> {
+    self.annotation(ANNOTATION_REFERENCE_DIGEST)?.parse().ok()
+  }
+}
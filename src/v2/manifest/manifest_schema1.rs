@@ -1,8 +1,15 @@
 use std::collections::HashMap;
 
+use base64::prelude::*;
 use serde::{Deserialize, Serialize};
+use sha2::{Sha256, Sha384, Sha512};
+use signature::Verifier;
 
-use crate::v2::manifest::Layer;
+use crate::{
+  errors::Result,
+  mediatypes::MediaTypes,
+  v2::{Digest, manifest::Descriptor},
+};
 
 /// Manifest version 2 schema 1, signed.
 ///
@@ -18,17 +25,76 @@ pub struct ManifestSchema1Signed {
   fs_layers: Vec<S1Layer>,
   history: Vec<V1Compat>,
   signatures: Vec<Signature>,
+  /// Raw bytes of the manifest, as fetched from the registry.
+  #[serde(skip)]
+  raw: Vec<u8>,
 }
 
+/// libtrust-style JWS signature, in JSON serialization with a detached payload.
 #[derive(Debug, Default, Deserialize, Serialize)]
 struct Signature {
-  // TODO(lucab): switch to jsonwebtokens crate
-  // https://github.com/Keats/rust-jwt/pull/23
-  header: serde_json::Value,
+  header: JwsHeader,
   signature: String,
   protected: String,
 }
 
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct JwsHeader {
+  jwk: Option<Jwk>,
+  alg: String,
+}
+
+/// Protected JWS header, describing how to rebuild the signed payload.
+#[derive(Debug, Deserialize)]
+struct ProtectedHeader {
+  #[serde(rename = "formatLength")]
+  format_length: usize,
+  #[serde(rename = "formatTail")]
+  format_tail: String,
+}
+
+/// JSON Web Key embedded in a schema 1 manifest signature.
+///
+/// Only EC (P-256, P-384, P-521) and RSA keys are supported for verification.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Jwk {
+  pub kty: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub kid: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub crv: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub x: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub y: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub n: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub e: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SignatureError {
+  #[error("manifest has no signatures")]
+  NoSignatures,
+  #[error("raw manifest bytes are not available")]
+  MissingRawManifest,
+  #[error("invalid protected header: {0}")]
+  InvalidProtectedHeader(String),
+  #[error("signed payload does not match the manifest content")]
+  PayloadMismatch,
+  #[error("malformed signature: {0}")]
+  MalformedSignature(String),
+  #[error("signature has no embedded JWK (x5c chains are not supported)")]
+  MissingKey,
+  #[error("invalid JWK: {0}")]
+  InvalidKey(String),
+  #[error("unsupported signature algorithm {alg} for key type {kty}")]
+  UnsupportedAlgorithm { alg: String, kty: String },
+  #[error("signature verification failed for key {0:?}")]
+  Invalid(Option<String>),
+}
+
 /// Compatibility entry for version 1 manifest interoperability.
 #[derive(Debug, Deserialize, Serialize)]
 struct V1Compat {
@@ -39,28 +105,120 @@ struct V1Compat {
 #[derive(Debug, Deserialize, Serialize)]
 struct S1Layer {
   #[serde(rename = "blobSum")]
-  blob_sum: String,
+  blob_sum: Digest,
 }
 
 impl ManifestSchema1Signed {
+  /// Parse a signed manifest from its raw bytes, retaining them for signature verification.
+  pub fn from_bytes(raw: Vec<u8>) -> Result<Self> {
+    let manifest = serde_json::from_slice::<ManifestSchema1Signed>(&raw)?;
+    Ok(Self { raw, ..manifest })
+  }
+
+  /// Reconstruct the signed payload, i.e. the manifest without its signatures.
+  ///
+  /// The payload is rebuilt from the raw bytes using the `formatLength` and `formatTail`
+  /// of the protected headers, which must agree across all signatures.
+  pub fn payload(&self) -> std::result::Result<Vec<u8>, SignatureError> {
+    if self.raw.is_empty() {
+      return Err(SignatureError::MissingRawManifest);
+    }
+    let mut payload: Option<Vec<u8>> = None;
+    for signature in &self.signatures {
+      let protected: ProtectedHeader = serde_json::from_slice(&decode_b64url(
+        &signature.protected,
+        SignatureError::InvalidProtectedHeader,
+      )?)
+      .map_err(|e| SignatureError::InvalidProtectedHeader(e.to_string()))?;
+      let head = self
+        .raw
+        .get(..protected.format_length)
+        .ok_or_else(|| SignatureError::InvalidProtectedHeader("formatLength out of range".to_string()))?;
+      let candidate = [
+        head,
+        &decode_b64url(&protected.format_tail, SignatureError::InvalidProtectedHeader)?,
+      ]
+      .concat();
+      match payload {
+        Some(ref p) if p != &candidate => return Err(SignatureError::PayloadMismatch),
+        Some(_) => {}
+        None => payload = Some(candidate),
+      }
+    }
+    payload.ok_or(SignatureError::NoSignatures)
+  }
+
+  /// Compute the canonical digest of this manifest, i.e. the digest of the signed payload.
+  ///
+  /// This is what registries report as `Docker-Content-Digest` for schema 1 manifests.
+  pub fn canonical_digest(&self) -> Result<Digest> {
+    Ok(Digest::sha256(&self.payload()?))
+  }
+
+  /// Verify all signatures of this manifest against their embedded keys.
+  ///
+  /// On success, returns the keys which signed the manifest; checking whether those are
+  /// trusted is up to the caller. The manifest must have been created with `from_bytes`
+  /// (as done by `Client::get_manifest`), since verification needs the raw bytes.
+  pub fn verify(&self) -> std::result::Result<Vec<Jwk>, SignatureError> {
+    let payload = self.payload()?;
+
+    // The payload is what was signed, so it must describe the same manifest we parsed.
+    let mut content: serde_json::Value =
+      serde_json::from_slice(&self.raw).map_err(|_| SignatureError::PayloadMismatch)?;
+    if let Some(obj) = content.as_object_mut() {
+      obj.remove("signatures");
+    }
+    let signed: serde_json::Value = serde_json::from_slice(&payload).map_err(|_| SignatureError::PayloadMismatch)?;
+    if content != signed {
+      return Err(SignatureError::PayloadMismatch);
+    }
+
+    let encoded_payload = BASE64_URL_SAFE_NO_PAD.encode(&payload);
+    self
+      .signatures
+      .iter()
+      .map(|signature| {
+        let signing_input = format!("{}.{}", signature.protected, encoded_payload);
+        signature.verify(signing_input.as_bytes())
+      })
+      .collect()
+  }
+
+  /// Pair each layer with its `v1Compatibility` history entry, base image first.
+  ///
+  /// Returns `None` if the number of layers and history entries differ.
+  pub(crate) fn layers_history(&self) -> Option<Vec<(&Digest, &str)>> {
+    if self.fs_layers.len() != self.history.len() {
+      return None;
+    }
+    let pairs = self
+      .fs_layers
+      .iter()
+      .zip(self.history.iter())
+      .rev()
+      .map(|(l, h)| (&l.blob_sum, h.v1_compat.as_str()))
+      .collect();
+    Some(pairs)
+  }
+
   /// List digests of all layers referenced by this manifest.
   ///
   /// The returned layers list is ordered starting with the base image first.
-  pub fn get_layers_digests(&self) -> Vec<String> {
+  pub fn get_layers_digests(&self) -> Vec<Digest> {
     self.fs_layers.iter().rev().map(|l| l.blob_sum.clone()).collect()
   }
 
   /// List of all layers referenced by this manifest.
   ///
+  /// Schema 1 does not record layer sizes, so the returned descriptors have a `size` of 0.
   /// The returned layers list is ordered starting with the base image first.
-  pub fn get_layers(&self) -> Vec<Layer> {
+  pub fn get_layers(&self) -> Vec<Descriptor> {
     self
       .fs_layers
       .iter()
-      .map(|l| Layer {
-        media_type: "application/vnd.docker.image.rootfs.diff.tar.gzip".to_string(),
-        digest: l.blob_sum.clone(),
-      })
+      .rev()
+      .map(|l| Descriptor::new(&MediaTypes::ImageLayerTgz.to_string(), l.blob_sum.clone(), 0))
       .collect()
   }
 
@@ -80,3 +238,91 @@ impl ManifestSchema1Signed {
     )
   }
 }
+
+impl Signature {
+  /// Verify this signature over `signing_input`, returning the key which produced it.
+  fn verify(&self, signing_input: &[u8]) -> std::result::Result<Jwk, SignatureError> {
+    let jwk = self.header.jwk.as_ref().ok_or(SignatureError::MissingKey)?;
+    let signature = decode_b64url(&self.signature, SignatureError::MalformedSignature)?;
+    let invalid = |_| SignatureError::Invalid(jwk.kid.clone());
+
+    macro_rules! verify_ecdsa {
+      ($curve:ident) => {{
+        let point = $curve::EncodedPoint::from_affine_coordinates(
+          jwk
+            .coordinate(jwk.x.as_deref(), $curve::FieldBytes::default().len())?
+            .as_slice()
+            .into(),
+          jwk
+            .coordinate(jwk.y.as_deref(), $curve::FieldBytes::default().len())?
+            .as_slice()
+            .into(),
+          false,
+        );
+        let key = $curve::ecdsa::VerifyingKey::from_encoded_point(&point)
+          .map_err(|e| SignatureError::InvalidKey(e.to_string()))?;
+        let signature = $curve::ecdsa::Signature::from_slice(&signature).map_err(invalid)?;
+        key.verify(signing_input, &signature).map_err(invalid)
+      }};
+    }
+
+    match (self.header.alg.as_str(), jwk.kty.as_str(), jwk.crv.as_deref()) {
+      ("ES256", "EC", Some("P-256")) => verify_ecdsa!(p256)?,
+      ("ES384", "EC", Some("P-384")) => verify_ecdsa!(p384)?,
+      ("ES512", "EC", Some("P-521")) => verify_ecdsa!(p521)?,
+      ("RS256", "RSA", _) => jwk.verify_rsa::<Sha256>(signing_input, &signature)?,
+      ("RS384", "RSA", _) => jwk.verify_rsa::<Sha384>(signing_input, &signature)?,
+      ("RS512", "RSA", _) => jwk.verify_rsa::<Sha512>(signing_input, &signature)?,
+      (alg, kty, _) => {
+        return Err(SignatureError::UnsupportedAlgorithm {
+          alg: alg.to_string(),
+          kty: kty.to_string(),
+        });
+      }
+    };
+
+    Ok(jwk.clone())
+  }
+}
+
+impl Jwk {
+  // Decode an EC coordinate, checking it has the field size of the curve.
+  fn coordinate(&self, value: Option<&str>, len: usize) -> std::result::Result<Vec<u8>, SignatureError> {
+    let value = value.ok_or_else(|| SignatureError::InvalidKey("missing EC coordinate".into()))?;
+    let bytes = decode_b64url(value, SignatureError::InvalidKey)?;
+    if bytes.len() != len {
+      return Err(SignatureError::InvalidKey(format!(
+        "EC coordinate has {} bytes, expected {len}",
+        bytes.len()
+      )));
+    }
+    Ok(bytes)
+  }
+
+  fn verify_rsa<D>(&self, signing_input: &[u8], signature: &[u8]) -> std::result::Result<(), SignatureError>
+  where
+    D: sha2::Digest + rsa::pkcs8::AssociatedOid,
+  {
+    let component = |value: Option<&str>| -> std::result::Result<rsa::BigUint, SignatureError> {
+      let value = value.ok_or_else(|| SignatureError::InvalidKey("missing RSA component".into()))?;
+      let bytes = decode_b64url(value, SignatureError::InvalidKey)?;
+      Ok(rsa::BigUint::from_bytes_be(&bytes))
+    };
+    let key = rsa::RsaPublicKey::new(component(self.n.as_deref())?, component(self.e.as_deref())?)
+      .map_err(|e| SignatureError::InvalidKey(e.to_string()))?;
+    let invalid = |_| SignatureError::Invalid(self.kid.clone());
+    let signature = rsa::pkcs1v15::Signature::try_from(signature).map_err(invalid)?;
+    rsa::pkcs1v15::VerifyingKey::<D>::new(key)
+      .verify(signing_input, &signature)
+      .map_err(invalid)
+  }
+}
+
+// Decode base64url, tolerating padding as emitted by some libtrust versions.
+//
+// Failures are reported with the `error` variant of the field being decoded.
+fn decode_b64url(input: &str, error: fn(String) -> SignatureError) -> std::result::Result<Vec<u8>, SignatureError> {
+  BASE64_URL_SAFE_NO_PAD
+    .decode(input.trim_end_matches('='))
+    .map_err(|e| error(e.to_string()))
+}
@@ -1,40 +1,43 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use log::trace;
-use reqwest::Method;
 use serde::{Deserialize, Serialize};
 
-pub use crate::v2::ApiErrors;
-use crate::{errors::Result, v2::manifest::Layer};
+use crate::{
+  errors::Result,
+  mediatypes::MediaTypes,
+  v2::{Digest, manifest::Descriptor},
+};
 
 /// Manifest version 2 schema 2.
 ///
 /// Specification is at <https://docs.docker.com/registry/spec/manifest-v2-2/>.
-#[derive(Debug, Default, Deserialize, Serialize)]
+///
+/// OCI image manifests share this representation; the fields only defined by the
+/// OCI image spec (`subject`, `artifactType`, `annotations`) are optional.
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ManifestSchema2Spec {
   #[serde(rename = "schemaVersion")]
-  schema_version: u16,
+  pub(crate) schema_version: u16,
   #[serde(rename = "mediaType")]
-  media_type: String,
-  config: Config,
-  layers: Vec<S2Layer>,
+  pub(crate) media_type: String,
+  #[serde(rename = "artifactType", skip_serializing_if = "Option::is_none")]
+  pub(crate) artifact_type: Option<String>,
+  pub(crate) config: Descriptor,
+  pub(crate) layers: Vec<Descriptor>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub(crate) subject: Option<Descriptor>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub(crate) annotations: Option<BTreeMap<String, String>>,
 }
 
 /// Super-type for combining a ManifestSchema2 with a ConfigBlob.
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct ManifestSchema2 {
   pub manifest_spec: ManifestSchema2Spec,
   pub config_blob: ConfigBlob,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize, Clone)]
-pub struct Config {
-  #[serde(rename = "mediaType")]
-  pub media_type: String,
-  pub size: u64,
-  pub digest: String,
-}
-
 /// Partial representation of a container image (application/vnd.docker.container.image.v1+json).
 ///
 /// The remaining fields according to [the image spec v1][image-spec-v1] are not covered.
@@ -43,80 +46,128 @@ pub struct Config {
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct ConfigBlob {
   architecture: String,
+  #[serde(default)]
   config: InnerConfigBlob,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  rootfs: Option<RootFs>,
+  /// Raw bytes of the config blob, as fetched from the registry.
+  #[serde(skip)]
+  raw: Vec<u8>,
 }
 
 #[derive(Debug, Default, Deserialize, Serialize)]
 struct InnerConfigBlob {
   #[serde(rename = "Labels")]
   labels: Option<HashMap<String, String>>,
+  #[serde(rename = "Env", skip_serializing_if = "Option::is_none")]
+  env: Option<Vec<String>>,
+  #[serde(rename = "Entrypoint", skip_serializing_if = "Option::is_none")]
+  entrypoint: Option<Vec<String>>,
+  #[serde(rename = "Cmd", skip_serializing_if = "Option::is_none")]
+  cmd: Option<Vec<String>>,
+  #[serde(rename = "User", skip_serializing_if = "Option::is_none")]
+  user: Option<String>,
+  #[serde(rename = "ExposedPorts", skip_serializing_if = "Option::is_none")]
+  exposed_ports: Option<BTreeMap<String, serde_json::Value>>,
 }
 
 #[derive(Debug, Default, Deserialize, Serialize)]
-struct S2Layer {
-  #[serde(rename = "mediaType")]
-  media_type: String,
-  size: u64,
-  digest: String,
-  urls: Option<Vec<String>>,
+struct RootFs {
+  #[serde(rename = "type")]
+  kind: String,
+  diff_ids: Vec<Digest>,
 }
 
 /// Manifest List.
-#[derive(Debug, Default, Deserialize, Serialize)]
+///
+/// OCI image indexes share this representation, see `ManifestSchema2Spec`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct ManifestList {
   #[serde(rename = "schemaVersion")]
-  schema_version: u16,
-  #[serde(rename = "mediaType")]
-  media_type: String,
-  pub manifests: Vec<ManifestObj>,
-}
-
-/// Manifest object.
-#[derive(Debug, Default, Deserialize, Serialize)]
-pub struct ManifestObj {
+  pub(crate) schema_version: u16,
   #[serde(rename = "mediaType")]
-  media_type: String,
-  size: u64,
-  pub digest: String,
-  pub platform: Platform,
+  pub(crate) media_type: String,
+  #[serde(rename = "artifactType", skip_serializing_if = "Option::is_none")]
+  pub(crate) artifact_type: Option<String>,
+  pub manifests: Vec<Descriptor>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub(crate) subject: Option<Descriptor>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub(crate) annotations: Option<BTreeMap<String, String>>,
 }
 
 /// Platform-related manifest entries.
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Platform {
   pub architecture: String,
   pub os: String,
-  #[serde(rename = "os.version")]
+  #[serde(rename = "os.version", skip_serializing_if = "Option::is_none")]
   pub os_version: Option<String>,
-  #[serde(rename = "os.features")]
+  #[serde(rename = "os.features", skip_serializing_if = "Option::is_none")]
   pub os_features: Option<Vec<String>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
   pub variant: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
   pub features: Option<Vec<String>>,
 }
 
 impl ManifestSchema2Spec {
-  /// Get `Config` object referenced by this manifest.
-  pub fn config(&self) -> &Config {
+  pub(crate) fn new(media_type: &MediaTypes, config: Descriptor, layers: Vec<Descriptor>) -> Self {
+    Self {
+      schema_version: 2,
+      media_type: media_type.to_string(),
+      artifact_type: None,
+      config,
+      layers,
+      subject: None,
+      annotations: None,
+    }
+  }
+
+  /// Get the media type of this manifest.
+  pub fn media_type(&self) -> &str {
+    &self.media_type
+  }
+
+  /// Get the config `Descriptor` referenced by this manifest.
+  pub fn config(&self) -> &Descriptor {
     &self.config
   }
 
-  /// Fetch the config blob for this manifest
-  pub(crate) async fn fetch_config_blob(self, client: crate::v2::Client, repo: String) -> Result<ManifestSchema2> {
-    let url = {
-      let ep = format!("{}/v2/{}/blobs/{}", client.base_url.clone(), repo, self.config.digest);
-      reqwest::Url::parse(&ep)?
-    };
+  /// Get the layer `Descriptor`s referenced by this manifest.
+  ///
+  /// The returned layers list is ordered starting with the base image first.
+  pub fn layers(&self) -> &[Descriptor] {
+    &self.layers
+  }
+
+  /// Get the artifact type of this manifest, if any.
+  pub fn artifact_type(&self) -> Option<&str> {
+    self.artifact_type.as_deref()
+  }
 
-    let r = client.build_reqwest(Method::GET, url.clone()).send().await?;
+  /// Get the `Descriptor` of the manifest this one refers to, if any.
+  pub fn subject(&self) -> Option<&Descriptor> {
+    self.subject.as_ref()
+  }
 
-    let status = r.status();
-    trace!("GET {:?}: {}", url, &status);
+  /// Get the annotations of this manifest, if any.
+  pub fn annotations(&self) -> Option<&BTreeMap<String, String>> {
+    self.annotations.as_ref()
+  }
 
-    if !status.is_success() {
-      return Err(ApiErrors::from(r).await);
-    }
+  /// Fetch the config blob for this manifest
+  ///
+  /// The blob is verified against the digest and size declared by the config descriptor.
+  pub(crate) async fn fetch_config_blob(self, client: crate::v2::Client, repo: String) -> Result<ManifestSchema2> {
+    trace!("Fetching config blob {}", self.config.digest);
+    let raw = client
+      .get_blob_response_from_descriptor(&repo, &self.config)
+      .await?
+      .bytes()
+      .await?;
 
-    let config_blob = r.json::<ConfigBlob>().await?;
+    let config_blob = ConfigBlob::from_bytes(raw)?;
 
     Ok(ManifestSchema2 {
       manifest_spec: self,
@@ -125,27 +176,40 @@ impl ManifestSchema2Spec {
   }
 }
 
+impl ConfigBlob {
+  /// Parse a config blob from its raw bytes, retaining them.
+  pub fn from_bytes(raw: Vec<u8>) -> Result<Self> {
+    let config_blob = serde_json::from_slice::<ConfigBlob>(&raw)?;
+    Ok(Self { raw, ..config_blob })
+  }
+
+  /// Get the raw bytes of the config blob.
+  ///
+  /// This is empty unless the blob was fetched from a registry or created with `from_bytes`.
+  /// The sha256 of these bytes is the image ID.
+  pub fn raw(&self) -> &[u8] {
+    &self.raw
+  }
+}
+
 impl ManifestSchema2 {
   /// List digests of all layers referenced by this manifest.
   ///
   /// The returned layers list is ordered starting with the base image first.
-  pub fn get_layer_digests(&self) -> Vec<String> {
+  pub fn get_layer_digests(&self) -> Vec<Digest> {
     self.manifest_spec.layers.iter().map(|l| l.digest.clone()).collect()
   }
 
   /// List of all layers referenced by this manifest.
   ///
   /// The returned layers list is ordered starting with the base image first.
-  pub fn get_layers(&self) -> Vec<Layer> {
-    self
-      .manifest_spec
-      .layers
-      .iter()
-      .map(|l| Layer {
-        media_type: l.media_type.clone(),
-        digest: l.digest.clone(),
-      })
-      .collect()
+  pub fn get_layers(&self) -> Vec<Descriptor> {
+    self.manifest_spec.layers.clone()
+  }
+
+  /// Get the raw bytes of the config blob, verified against the config descriptor.
+  pub fn config_bytes(&self) -> &[u8] {
+    self.config_blob.raw()
   }
 
   /// Get the architecture from the config
@@ -157,40 +221,109 @@ impl ManifestSchema2 {
   pub fn labels(&self) -> Option<HashMap<String, String>> {
     self.config_blob.config.labels.to_owned()
   }
-}
 
-impl ManifestObj {
-  /// Get the architecture of the manifest object
-  pub fn architecture(&self) -> String {
-    self.platform.architecture.to_owned()
+  /// Get the environment variables, as `KEY=value` strings, from the config.
+  pub fn env(&self) -> Option<&[String]> {
+    self.config_blob.config.env.as_deref()
+  }
+
+  /// Get the entrypoint, if any, from the config.
+  pub fn entrypoint(&self) -> Option<&[String]> {
+    self.config_blob.config.entrypoint.as_deref()
+  }
+
+  /// Get the default command, if any, from the config.
+  pub fn cmd(&self) -> Option<&[String]> {
+    self.config_blob.config.cmd.as_deref()
+  }
+
+  /// Get the user the container runs as, if any, from the config.
+  pub fn user(&self) -> Option<&str> {
+    self.config_blob.config.user.as_deref()
+  }
+
+  /// Get the exposed ports (e.g. `80/tcp`) from the config.
+  pub fn exposed_ports(&self) -> Vec<&str> {
+    self
+      .config_blob
+      .config
+      .exposed_ports
+      .iter()
+      .flat_map(|ports| ports.keys().map(String::as_str))
+      .collect()
   }
 
-  /// Returns the sha digest of the manifest object
-  pub fn digest(&self) -> String {
-    self.digest.to_owned()
+  /// Get the digests of the uncompressed layers (`rootfs.diff_ids`) from the config.
+  ///
+  /// The returned list is ordered starting with the base image first, like the layers.
+  pub fn diff_ids(&self) -> &[Digest] {
+    self.config_blob.rootfs.as_ref().map_or(&[], |r| r.diff_ids.as_slice())
   }
 }
 
 impl ManifestList {
+  /// Get the media type of this manifest list.
+  pub fn media_type(&self) -> &str {
+    &self.media_type
+  }
+
+  /// Get the artifact type of this index, if any.
+  pub fn artifact_type(&self) -> Option<&str> {
+    self.artifact_type.as_deref()
+  }
+
+  /// Get the `Descriptor` of the manifest this index refers to, if any.
+  pub fn subject(&self) -> Option<&Descriptor> {
+    self.subject.as_ref()
+  }
+
+  /// Get the annotations of this manifest list, if any.
+  pub fn annotations(&self) -> Option<&BTreeMap<String, String>> {
+    self.annotations.as_ref()
+  }
+
   /// Get architecture of all the manifests
+  ///
+  /// Entries without a platform (allowed by OCI image indexes) and attestation
+  /// manifests are skipped.
   pub fn architectures(&self) -> Vec<String> {
-    self.manifests.iter().map(|mo| mo.architecture()).collect()
+    self.images().filter_map(|mo| mo.architecture()).collect()
   }
 
   /// Get the digest for all the manifest images in the ManifestList
-  pub fn get_digests(&self) -> Vec<String> {
-    self.manifests.iter().map(|mo| mo.digest()).collect()
+  pub fn get_digests(&self) -> Vec<Digest> {
+    self.images().map(|mo| mo.digest.clone()).collect()
   }
 
-  /// Get `Layer` structs all the manifest images in the ManifestList
-  pub fn get_layers(&self) -> Vec<Layer> {
-    self
-      .manifests
-      .iter()
-      .map(|mo| Layer {
-        media_type: mo.media_type.clone(),
-        digest: mo.digest.clone(),
+  /// Get the `Descriptor`s of all the manifest images in the ManifestList
+  pub fn get_descriptors(&self) -> Vec<Descriptor> {
+    self.images().cloned().collect()
+  }
+
+  /// Get the `Descriptor`s of all the manifest images in the ManifestList
+  #[deprecated(note = "use `get_descriptors`")]
+  pub fn get_layers(&self) -> Vec<Descriptor> {
+    self.get_descriptors()
+  }
+
+  /// Iterate over the entries which are images, i.e. not attestation manifests.
+  pub fn images(&self) -> impl Iterator<Item = &Descriptor> {
+    self.manifests.iter().filter(|mo| !mo.is_attestation())
+  }
+
+  /// Iterate over the attestation manifests attached to images of this index by BuildKit.
+  pub fn attestations(&self) -> impl Iterator<Item = &Descriptor> {
+    self.manifests.iter().filter(|mo| mo.is_attestation())
+  }
+
+  /// Find the image for a platform, ignoring attestation manifests.
+  ///
+  /// If `variant` is `None`, the first image matching `os` and `architecture` is returned.
+  pub fn get_platform(&self, os: &str, architecture: &str, variant: Option<&str>) -> Option<&Descriptor> {
+    self.images().find(|mo| {
+      mo.platform.as_ref().is_some_and(|p| {
+        p.os == os && p.architecture == architecture && variant.is_none_or(|v| p.variant.as_deref() == Some(v))
       })
-      .collect()
+    })
   }
 }
@@ -1,6 +1,6 @@
 use std::{iter::FromIterator, str::FromStr};
 
-use log::{debug, trace};
+use log::{debug, trace, warn};
 use reqwest::{self, StatusCode, Url, header};
 
 use crate::{
@@ -9,13 +9,29 @@ use crate::{
   v2::*,
 };
 
+mod builder;
+pub(crate) use self::builder::parse_media_type;
+pub use self::builder::{EncodedManifest, IndexBuilder, ManifestBuildError, ManifestBuilder};
+
+mod convert;
+pub use self::convert::{ConversionError, ConversionLoss, ConversionReport, ConvertedManifest};
+
+mod descriptor;
+pub use self::descriptor::Descriptor;
+
+/// Former name of `Descriptor`, which layers, configs and manifest list entries share.
+#[deprecated(note = "use `Descriptor`")]
+pub type Layer = Descriptor;
+
+/// Former type of the entries of a `ManifestList`, now `Descriptor`s.
+#[deprecated(note = "use `Descriptor`")]
+pub type ManifestObj = Descriptor;
+
 mod manifest_schema1;
 pub use self::manifest_schema1::*;
 
 mod manifest_schema2;
-pub use self::manifest_schema2::{
-  ConfigBlob, ManifestList, ManifestObj, ManifestSchema2, ManifestSchema2Spec, Platform,
-};
+pub use self::manifest_schema2::{ConfigBlob, ManifestList, ManifestSchema2, ManifestSchema2Spec, Platform};
 
 impl Client {
   /// Fetch an image manifest.
@@ -33,13 +49,37 @@ impl Client {
   ///
   /// The name and reference parameters identify the image.
   /// The reference may be either a tag or digest.
-  pub async fn get_manifest_and_ref(&self, name: &str, reference: &str) -> Result<(Manifest, Option<String>)> {
+  pub async fn get_manifest_and_ref(&self, name: &str, reference: &str) -> Result<(Manifest, Option<Digest>)> {
+    // Manifests fetched by digest are immutable, so they can be served from the cache.
+    let digest = reference.parse::<Digest>().ok();
+    if let (Some(cache), Some(digest)) = (&self.cache, &digest) {
+      // The cache is only an optimization, the registry is asked on any cache failure.
+      let cached_digest = digest.clone();
+      let cached = cache
+        .blocking(move |cache| cache.read(&cached_digest))
+        .await
+        .unwrap_or_else(|e| {
+          debug!("Failed to read cached manifest {digest}: {e}");
+          None
+        });
+      if let Some(body) = cached {
+        if digest.verify(&body).is_ok() {
+          let media_type = sniff_media_type(&body)?;
+          trace!("Using cached manifest {digest} with media-type {media_type:?}");
+          return self.parse_manifest(name, media_type, body, Some(digest.clone())).await;
+        }
+        debug!("Cached manifest {digest} is corrupted, removing it");
+        let cached_digest = digest.clone();
+        if let Err(e) = cache.blocking(move |cache| cache.remove(&cached_digest)).await {
+          debug!("Failed to remove cached manifest {digest}: {e}");
+        }
+      }
+    }
+
     let url = self.build_url(name, reference)?;
 
     let accept_headers = build_accept_headers(&self.accepted_types);
 
-    let client_spare0 = self.clone();
-
     let res = self
       .build_reqwest(Method::GET, url.clone())
       .headers(accept_headers)
@@ -55,36 +95,56 @@ impl Client {
     }
 
     let headers = res.headers();
-    let content_digest = match headers.get("docker-content-digest") {
-      Some(content_digest_value) => Some(content_digest_value.to_str()?.to_string()),
-      None => {
-        debug!("cannot find manifestref in headers");
-        None
-      }
-    };
+    let content_digest = content_digest_from_headers(headers);
 
     let header_content_type = headers.get(header::CONTENT_TYPE);
     let media_type = evaluate_media_type(header_content_type, &url)?;
 
     trace!("content-type: {header_content_type:?}, media-type: {media_type:?}");
 
+    let body = res.bytes().await?.to_vec();
+    // Schema 1 manifests are addressed by the digest of their payload, they are not cached.
+    if let (Some(cache), Some(digest)) = (&self.cache, &digest) {
+      if digest.verify(&body).is_ok() {
+        let (cached_digest, cached_body) = (digest.clone(), body.clone());
+        if let Err(e) = cache
+          .blocking(move |cache| cache.insert(&cached_digest, &cached_body))
+          .await
+        {
+          debug!("Failed to cache manifest {digest}: {e}");
+        }
+      }
+    }
+    self.parse_manifest(name, media_type, body, content_digest).await
+  }
+
+  async fn parse_manifest(
+    &self,
+    name: &str,
+    media_type: MediaTypes,
+    body: Vec<u8>,
+    content_digest: Option<Digest>,
+  ) -> Result<(Manifest, Option<Digest>)> {
     match media_type {
-      mediatypes::MediaTypes::ManifestV2S1Signed => Ok((
-        res.json::<ManifestSchema1Signed>().await.map(Manifest::S1Signed)?,
-        content_digest,
-      )),
+      mediatypes::MediaTypes::ManifestV2S1Signed => {
+        let m = ManifestSchema1Signed::from_bytes(body)?;
+        // Registries may omit the header; the digest of a signed manifest is that of its payload.
+        let content_digest = content_digest.or_else(|| m.canonical_digest().ok());
+        Ok((Manifest::S1Signed(m), content_digest))
+      }
       mediatypes::MediaTypes::ManifestV2S2 | mediatypes::MediaTypes::OciImageManifest => {
-        let m = res.json::<ManifestSchema2Spec>().await?;
+        let m = serde_json::from_slice::<ManifestSchema2Spec>(&body)?;
         Ok((
-          m.fetch_config_blob(client_spare0, name.to_string())
+          m.fetch_config_blob(self.clone(), name.to_string())
             .await
             .map(Manifest::S2)?,
           content_digest,
         ))
       }
-      mediatypes::MediaTypes::ManifestList | mediatypes::MediaTypes::OciImageIndexV1 => {
-        Ok((res.json::<ManifestList>().await.map(Manifest::ML)?, content_digest))
-      }
+      mediatypes::MediaTypes::ManifestList | mediatypes::MediaTypes::OciImageIndexV1 => Ok((
+        serde_json::from_slice::<ManifestList>(&body).map(Manifest::ML)?,
+        content_digest,
+      )),
       unsupported => Err(Error::UnsupportedMediaType(unsupported)),
     }
   }
@@ -95,7 +155,7 @@ impl Client {
   }
 
   /// Fetch content digest for a particular tag.
-  pub async fn get_manifestref(&self, name: &str, reference: &str) -> Result<Option<String>> {
+  pub async fn get_manifestref(&self, name: &str, reference: &str) -> Result<Option<Digest>> {
     let url = self.build_url(name, reference)?;
 
     let accept_headers = build_accept_headers(&self.accepted_types);
@@ -115,13 +175,7 @@ impl Client {
     }
 
     let headers = res.headers();
-    let content_digest = match headers.get("docker-content-digest") {
-      Some(content_digest_value) => Some(content_digest_value.to_str()?.to_string()),
-      None => {
-        debug!("cannot find manifestref in headers");
-        None
-      }
-    };
+    let content_digest = content_digest_from_headers(headers);
     Ok(content_digest)
   }
 
@@ -174,6 +228,109 @@ impl Client {
       _ => Err(ApiErrors::from(r).await),
     }
   }
+
+  /// Fetch the raw bytes of a manifest along with their media type, if it exists.
+  ///
+  /// Only `accepted` media types are requested. If `reference` is a digest, the
+  /// bytes are verified against it.
+  pub(crate) async fn get_raw_manifest(
+    &self,
+    name: &str,
+    reference: &str,
+    accepted: &[MediaTypes],
+  ) -> Result<Option<(mediatypes::MediaTypes, Vec<u8>)>> {
+    let url = self.build_url(name, reference)?;
+    let accept_headers = build_accept_headers(&accepted.iter().map(|m| (m.clone(), None)).collect::<Vec<_>>());
+
+    let res = self
+      .build_reqwest(Method::GET, url.clone())
+      .headers(accept_headers)
+      .send()
+      .await?;
+
+    let status = res.status();
+    trace!("GET '{}' status: {:?}", res.url(), status);
+
+    match status {
+      StatusCode::OK => {}
+      StatusCode::NOT_FOUND => return Ok(None),
+      _ => return Err(ApiErrors::from(res).await),
+    }
+
+    let media_type = evaluate_media_type(res.headers().get(header::CONTENT_TYPE), &url)?;
+    let raw = res.bytes().await?.to_vec();
+    if let Ok(digest) = Digest::from_str(reference) {
+      digest.verify(&raw)?;
+    }
+    Ok(Some((media_type, raw)))
+  }
+
+  /// Upload a manifest and tag it with `reference`, which may be either a tag or its digest.
+  ///
+  /// Returns the digest of the uploaded manifest.
+  pub async fn put_manifest(&self, name: &str, reference: &str, manifest: &EncodedManifest) -> Result<Digest> {
+    self
+      .put_manifest_response(name, reference, manifest)
+      .await
+      .map(|(digest, _)| digest)
+  }
+
+  // Upload a manifest, also returning whether the registry processed its `subject`.
+  pub(crate) async fn put_manifest_response(
+    &self,
+    name: &str,
+    reference: &str,
+    manifest: &EncodedManifest,
+  ) -> Result<(Digest, bool)> {
+    let url = self.build_url(name, reference)?;
+
+    let res = self
+      .build_reqwest(Method::PUT, url)
+      .header(header::CONTENT_TYPE, manifest.media_type.as_str())
+      .body(manifest.bytes.clone())
+      .send()
+      .await?;
+
+    let status = res.status();
+    trace!("PUT '{}' status: {:?}", res.url(), status);
+
+    match status {
+      StatusCode::OK | StatusCode::CREATED => {}
+      s if s.is_server_error() => return Err(Error::Server { status }),
+      _ => return Err(ApiErrors::from(res).await),
+    }
+
+    if let Some(digest) = content_digest_from_headers(res.headers()) {
+      if digest != manifest.digest {
+        return Err(
+          ContentDigestError::Verify {
+            expected: manifest.digest.to_string(),
+            got: digest.to_string(),
+          }
+          .into(),
+        );
+      }
+    }
+    let subject_processed = res.headers().contains_key("OCI-Subject");
+    Ok((manifest.digest.clone(), subject_processed))
+  }
+}
+
+// Parse the `Docker-Content-Digest` header, if present.
+//
+// The header is informational only, a malformed one is ignored.
+fn content_digest_from_headers(headers: &header::HeaderMap) -> Option<Digest> {
+  let Some(content_digest_value) = headers.get("docker-content-digest") else {
+    debug!("cannot find manifestref in headers");
+    return None;
+  };
+  match content_digest_value.to_str().map(str::parse::<Digest>) {
+    Ok(Ok(digest)) => Some(digest),
+    _ => {
+      warn!("ignoring malformed Docker-Content-Digest header: {content_digest_value:?}");
+      None
+    }
+  }
 }
 
 fn to_mimes(v: &[&str]) -> Vec<mime::Mime> {
@@ -193,9 +350,11 @@ fn evaluate_media_type(
   content_type: Option<&reqwest::header::HeaderValue>,
   url: &Url,
 ) -> Result<mediatypes::MediaTypes> {
+  // Drop parameters such as `charset`, they are not part of the media type.
   let header_content_type = content_type
     .map(|hv| hv.to_str())
-    .map(std::result::Result::unwrap_or_default);
+    .map(std::result::Result::unwrap_or_default)
+    .map(|ct| ct.split(';').next().unwrap_or_default().trim());
 
   let is_pulp_based = url.path().starts_with("/pulp/docker/v2");
 
@@ -226,6 +385,24 @@ fn evaluate_media_type(
   }
 }
 
+/// Infer the media type of a manifest from its content, for manifests read from the cache.
+fn sniff_media_type(body: &[u8]) -> Result<mediatypes::MediaTypes> {
+  #[derive(serde::Deserialize)]
+  struct Probe {
+    #[serde(rename = "mediaType")]
+    media_type: Option<String>,
+    manifests: Option<serde::de::IgnoredAny>,
+  }
+
+  let probe = serde_json::from_slice::<Probe>(body)?;
+  Ok(match (probe.media_type, probe.manifests) {
+    (Some(media_type), _) => parse_media_type(&media_type),
+    // The media type is optional in OCI manifests and indexes.
+    (None, Some(_)) => MediaTypes::OciImageIndexV1,
+    (None, None) => MediaTypes::OciImageManifest,
+  })
+}
+
 fn build_accept_headers(accepted_types: &[(MediaTypes, Option<f64>)]) -> header::HeaderMap {
   let accepted_types_string = accepted_types
     .iter()
@@ -249,13 +426,8 @@ fn build_accept_headers(accepted_types: &[(MediaTypes, Option<f64>)]) -> header:
   )])
 }
 
-#[derive(Debug)]
-pub struct Layer {
-  pub media_type: String,
-  pub digest: String,
-}
-
 /// Umbrella type for common actions on the different manifest schema types
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum Manifest {
   S1Signed(manifest_schema1::ManifestSchema1Signed),
@@ -273,6 +445,10 @@ pub enum ManifestError {
   LayerDigestsUnsupported(String),
   #[error("manifest {0} does not support the 'architecture' method")]
   ArchitectureNotSupported(String),
+  #[error("no image for platform {0} in manifest list")]
+  PlatformNotFound(String),
+  #[error("no image in manifest list")]
+  NoImage,
 }
 
 impl Manifest {
@@ -291,7 +467,7 @@ impl Manifest {
   /// the individual image to get the layers.
   ///
   /// The returned layers list for non ManifestList images is ordered starting with the base image first.
-  pub fn layers_digests(&self, architecture: Option<&str>) -> Result<Vec<String>> {
+  pub fn layers_digests(&self, architecture: Option<&str>) -> Result<Vec<Digest>> {
     match (self, self.architectures(), architecture) {
       (Manifest::S1Signed(m), _, None) => Ok(m.get_layers_digests()),
       (Manifest::S2(m), _, None) => Ok(m.get_layer_digests()),
@@ -314,22 +490,22 @@ impl Manifest {
     }
   }
 
-  /// List of all layers referenced by this manifest, if available.
-  /// For ManifestList, returns the digests of all the manifest list images.
+  /// List of all layer `Descriptor`s referenced by this manifest, if available.
+  /// For ManifestList, returns the descriptors of all the manifest list images.
   ///
-  /// As manifest list images only contain digests of the
-  /// images contained in the manifest, the `layers_digests`
-  /// function returns the digests of all the images
+  /// As manifest list images only contain descriptors of the
+  /// images contained in the manifest, the `layers`
+  /// function returns the descriptors of all the images
   /// contained in the ManifestList instead of individual
   /// layers of the manifests.
   /// The layers of a specific image from manifest list can
-  /// be obtained using the `Layer` struct of the image from the
+  /// be obtained using the `Descriptor` of the image from the
   /// manifest list and getting its manifest and manifestref
   /// (get_manifest_and_ref()) and using this manifest of
   /// the individual image to get the layers.
   ///
   /// The returned layers list for non ManifestList images is ordered starting with the base image first.
-  pub fn layers(&self, architecture: Option<&str>) -> Result<Vec<Layer>> {
+  pub fn layers(&self, architecture: Option<&str>) -> Result<Vec<Descriptor>> {
     match (self, self.architectures(), architecture) {
       (Manifest::S1Signed(m), _, None) => Ok(m.get_layers()),
       (Manifest::S2(m), _, None) => Ok(m.get_layers()),
@@ -347,11 +523,19 @@ impl Manifest {
         }
         Ok(m.get_layers())
       }
-      (Manifest::ML(m), _, _) => Ok(m.get_layers()),
+      (Manifest::ML(m), _, _) => Ok(m.get_descriptors()),
       _ => Err(ManifestError::LayerDigestsUnsupported(format!("{self:?}")).into()),
     }
   }
 
+  /// The config `Descriptor` of the image, if the manifest type has one.
+  pub fn config(&self) -> Option<&Descriptor> {
+    match self {
+      Manifest::S2(m) => Some(m.manifest_spec.config()),
+      Manifest::S1Signed(_) | Manifest::ML(_) => None,
+    }
+  }
+
   /// The architectures of the image the manifest points to, if available.
   pub fn architectures(&self) -> Result<Vec<String>> {
     match self {
@@ -49,11 +49,32 @@ pub mod manifest;
 
 mod tags;
 
-mod blobs;
+mod referrers;
+pub use self::referrers::Referrers;
+
+mod artifacts;
+pub use self::artifacts::{ANNOTATION_TITLE, Artifact, ArtifactError};
+
+pub mod attestations;
+
+pub mod cosign;
+
+pub mod diff;
+
+pub mod size;
+
+pub mod pull;
+
+pub mod files;
+
+pub(crate) mod blobs;
+
+mod cache;
+pub use self::cache::BlobCache;
 
 mod content_digest;
 pub(crate) use self::content_digest::ContentDigest;
-pub use self::content_digest::ContentDigestError;
+pub use self::content_digest::{ContentDigestError, Digest, DigestAlgorithm, Digester};
 
 /// A Client to make outgoing API requests to a registry.
 #[derive(Clone, Debug)]
@@ -64,6 +85,7 @@ pub struct Client {
   auth: Option<auth::Auth>,
   client: reqwest::Client,
   accepted_types: Vec<(MediaTypes, Option<f64>)>,
+  cache: Option<BlobCache>,
 }
 
 impl Client {
@@ -0,0 +1,296 @@
+//! Concurrent download of the layers of an image.
+//!
+//! `Client::pull_layers` keeps the layers in memory, ready for `render::unpack_layers`,
+//! while `Client::pull_layers_to` writes them to a directory. Both report their progress
+//! as `PullEvent`s and return the layers in manifest order, whatever order they complete in.
+//!
+//! `Client::pull_and_unpack` instead streams the layers one after the other into a
+//! directory, without holding them in memory or on disk.
+
+use std::path::{Path, PathBuf};
+
+use futures::prelude::*;
+use log::debug;
+
+use crate::{
+  errors::{Error, Result},
+  render::{self, LayerBlob, LayerReader, UnpackOptions},
+  v2::{
+    blobs,
+    manifest::{Descriptor, Manifest, ManifestError, Platform},
+    *,
+  },
+};
+
+/// Options of `Client::pull_layers` and `Client::pull_layers_to`.
+#[derive(Clone, Debug)]
+pub struct PullOptions {
+  concurrency: usize,
+  retries: usize,
+}
+
+impl Default for PullOptions {
+  fn default() -> Self {
+    Self {
+      concurrency: 3,
+      retries: 2,
+    }
+  }
+}
+
+impl PullOptions {
+  /// Set the maximum number of layers downloaded at the same time, 3 by default.
+  pub fn concurrency(mut self, concurrency: usize) -> Self {
+    self.concurrency = concurrency.max(1);
+    self
+  }
+
+  /// Set how many times the download of a layer is retried after a transient failure
+  /// (connection or server error, corrupted content), 2 by default.
+  pub fn retries(mut self, retries: usize) -> Self {
+    self.retries = retries;
+    self
+  }
+}
+
+/// Progress of the download of a layer.
+///
+/// `index` is the position of the layer in the manifest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PullEvent {
+  /// The download started, `total` is the size of the layer if known.
+  Started {
+    index: usize,
+    digest: Digest,
+    total: Option<u64>,
+  },
+  /// A chunk was received, `received` bytes of the layer so far.
+  Received {
+    index: usize,
+    digest: Digest,
+    received: u64,
+    total: Option<u64>,
+  },
+  /// The layer was completely downloaded and matches its digest.
+  Verified { index: usize, digest: Digest },
+  /// The download failed and is started over, for the `attempt`th time.
+  Retried {
+    index: usize,
+    digest: Digest,
+    attempt: usize,
+    error: String,
+  },
+  /// The download failed for good, which fails the whole pull.
+  Failed {
+    index: usize,
+    digest: Digest,
+    error: String,
+  },
+}
+
+impl Client {
+  /// Download the layers of the image `manifest` from repository `name`, concurrently.
+  ///
+  /// Layers are returned in manifest order, with their `diff_id` when the image config
+  /// lists them. `progress` is called with the `PullEvent`s of all the layers as they
+  /// happen. The first layer failing after its retries fails the pull.
+  pub async fn pull_layers<F>(
+    &self,
+    name: &str,
+    manifest: &Manifest,
+    options: &PullOptions,
+    progress: F,
+  ) -> Result<Vec<LayerBlob>>
+  where
+    F: Fn(PullEvent) + Sync,
+  {
+    let diff_ids = match manifest {
+      Manifest::S2(m) => m.diff_ids(),
+      _ => &[],
+    };
+    let layers = pull_descriptors(manifest)?;
+    stream::iter(layers.into_iter().enumerate())
+      .map(|(index, layer)| {
+        let progress = &progress;
+        async move {
+          let bytes = self
+            .pull_layer(name, index, &layer, options, progress, |stream| stream.try_concat())
+            .await?;
+          let blob = LayerBlob::new(bytes, Some(layer.media_type));
+          Ok(match diff_ids.get(index) {
+            Some(diff_id) => blob.diff_id(diff_id.clone()),
+            None => blob,
+          })
+        }
+      })
+      .buffered(options.concurrency)
+      .try_collect()
+      .await
+  }
+
+  /// Download the layers of the image `manifest` from repository `name` into `dir`, concurrently.
+  ///
+  /// Each layer is written atomically to `<dir>/<encoded digest>`, see `download_blob_to`.
+  /// The paths of the layers are returned in manifest order. See `pull_layers`.
+  pub async fn pull_layers_to<F>(
+    &self,
+    name: &str,
+    manifest: &Manifest,
+    dir: &Path,
+    options: &PullOptions,
+    progress: F,
+  ) -> Result<Vec<PathBuf>>
+  where
+    F: Fn(PullEvent) + Sync,
+  {
+    let layers = pull_descriptors(manifest)?;
+    stream::iter(layers.into_iter().enumerate())
+      .map(|(index, layer)| {
+        let progress = &progress;
+        async move {
+          let path = dir.join(layer.digest.encoded());
+          self
+            .pull_layer(name, index, &layer, options, progress, |stream| {
+              blobs::write_blob_stream(stream, &path)
+            })
+            .await?;
+          Ok(path)
+        }
+      })
+      .buffered(options.concurrency)
+      .try_collect()
+      .await
+  }
+
+  /// Pull the image `reference` of repository `name` and unpack its filesystem in `target`.
+  ///
+  /// If `reference` is a manifest list or image index, the image for `platform` is
+  /// unpacked, or the first one of the list if no platform is given. Layers are streamed
+  /// from the registry (or the `BlobCache`), decompressed and extracted on the fly, in
+  /// order; each one is verified against its digest and, when the image config lists
+  /// it, its `diff_id`. `target` must be an existing absolute directory; layers are
+  /// unpacked according to `options`.
+  pub async fn pull_and_unpack(
+    &self,
+    name: &str,
+    reference: &str,
+    target: &Path,
+    platform: Option<&Platform>,
+    options: &UnpackOptions,
+  ) -> Result<()> {
+    let manifest = match self.get_manifest(name, reference).await? {
+      Manifest::ML(list) => {
+        let image = match platform {
+          Some(p) => list
+            .get_platform(&p.os, &p.architecture, p.variant.as_deref())
+            .ok_or_else(|| ManifestError::PlatformNotFound(format!("{}/{}", p.os, p.architecture)))?,
+          None => list.images().next().ok_or(ManifestError::NoImage)?,
+        };
+        self.get_manifest(name, &image.digest.to_string()).await?
+      }
+      manifest => manifest,
+    };
+    let diff_ids = match &manifest {
+      Manifest::S2(m) => m.diff_ids(),
+      _ => &[],
+    };
+
+    for (index, layer) in manifest.layers(None)?.into_iter().enumerate() {
+      debug!("Unpacking layer {} into {}", layer.digest, target.display());
+      let resp = self.get_blob_response_from_descriptor(name, &layer).await?;
+      let layer = LayerReader {
+        reader: resp.reader(),
+        media_type: Some(layer.media_type),
+        diff_id: diff_ids.get(index).cloned(),
+      };
+      render::unpack_layer_async_with(layer, target, options).await?;
+    }
+    Ok(())
+  }
+
+  /// Download a layer with `consume`, which reads the whole blob stream, retrying on transient errors.
+  async fn pull_layer<'a, T, C, Fut>(
+    &self,
+    name: &str,
+    index: usize,
+    layer: &'a Descriptor,
+    options: &PullOptions,
+    progress: &'a (dyn Fn(PullEvent) + Sync),
+    consume: C,
+  ) -> Result<T>
+  where
+    C: Fn(stream::BoxStream<'a, Result<Vec<u8>>>) -> Fut,
+    Fut: Future<Output = Result<T>>,
+  {
+    let digest = &layer.digest;
+    let mut attempt = 0;
+    loop {
+      let res = match self.get_blob_response_from_descriptor(name, layer).await {
+        Ok(resp) => {
+          let total = resp.size();
+          progress(PullEvent::Started {
+            index,
+            digest: digest.clone(),
+            total,
+          });
+          let mut received = 0;
+          let stream = resp.stream().inspect_ok(move |chunk| {
+            received += chunk.len() as u64;
+            progress(PullEvent::Received {
+              index,
+              digest: digest.clone(),
+              received,
+              total,
+            });
+          });
+          consume(stream.boxed()).await
+        }
+        Err(e) => Err(e),
+      };
+
+      match res {
+        Ok(value) => {
+          progress(PullEvent::Verified {
+            index,
+            digest: digest.clone(),
+          });
+          return Ok(value);
+        }
+        Err(e) if attempt < options.retries && is_transient(&e) => {
+          attempt += 1;
+          debug!("Download of layer {digest} failed, retrying: {e}");
+          progress(PullEvent::Retried {
+            index,
+            digest: digest.clone(),
+            attempt,
+            error: e.to_string(),
+          });
+        }
+        Err(e) => {
+          progress(PullEvent::Failed {
+            index,
+            digest: digest.clone(),
+            error: e.to_string(),
+          });
+          return Err(e);
+        }
+      }
+    }
+  }
+}
+
+/// Layers of an image manifest; manifest lists have to be resolved to an image first.
+pub(crate) fn pull_descriptors(manifest: &Manifest) -> Result<Vec<Descriptor>> {
+  match manifest {
+    Manifest::ML(_) => Err(ManifestError::LayerDigestsUnsupported(format!("{manifest:?}")).into()),
+    _ => manifest.layers(None),
+  }
+}
+
+/// Whether a failed download may succeed when started over.
+fn is_transient(e: &Error) -> bool {
+  matches!(
+    e,
+    Error::Reqwest(_) | Error::Server { .. } | Error::BlobSize { .. } | Error::ContentDigestParse(_)
+  )
+}
@@ -0,0 +1,158 @@
+use log::trace;
+use reqwest::{self, StatusCode, Url, header};
+
+use crate::{
+  errors::Result,
+  mediatypes::MediaTypes,
+  v2::{
+    manifest::{Descriptor, ManifestList},
+    tags::parse_link,
+    *,
+  },
+};
+
+/// Header listing the filters applied by the registry to a referrers response.
+const FILTERS_APPLIED_HEADER: &str = "OCI-Filters-Applied";
+
+/// Manifests referring to a subject manifest, as returned by `Client::get_referrers`.
+#[derive(Debug)]
+pub struct Referrers {
+  /// OCI image index whose `manifests` are the descriptors of the referrers.
+  pub index: ManifestList,
+  /// Whether the registry applied the `artifactType` filter itself.
+  ///
+  /// Referrers are filtered locally otherwise, so `index` only ever contains
+  /// matching descriptors.
+  pub filter_applied: bool,
+  /// Whether referrers were discovered through the referrers tag schema,
+  /// because the registry does not support the referrers API.
+  pub tag_fallback: bool,
+}
+
+impl Client {
+  /// List the manifests referring to the manifest `digest` of image `name`.
+  ///
+  /// This uses the OCI 1.1 referrers API, following `Link` headers for paginated
+  /// results. If the registry does not support it, the `<alg>-<ref>` referrers tag
+  /// is looked up instead, and a missing tag yields an empty index.
+  pub async fn get_referrers(&self, name: &str, digest: &Digest, artifact_type: Option<&str>) -> Result<Referrers> {
+    let base_url = format!("{}/v2/{}/referrers/{}", self.base_url, name, digest);
+    let mut url = Url::parse(&base_url)?;
+    if let Some(artifact_type) = artifact_type {
+      url.query_pairs_mut().append_pair("artifactType", artifact_type);
+    }
+
+    let mut index: Option<ManifestList> = None;
+    let mut filter_applied;
+    loop {
+      let resp = self
+        .build_reqwest(Method::GET, url.clone())
+        .header(header::ACCEPT, MediaTypes::OciImageIndexV1.to_string())
+        .send()
+        .await?;
+
+      let status = resp.status();
+      trace!("GET '{}' status: {:?}", resp.url(), status);
+
+      match status {
+        StatusCode::OK => {}
+        StatusCode::NOT_FOUND if index.is_none() => {
+          return self.get_referrers_from_tag(name, digest, artifact_type).await;
+        }
+        _ => return Err(ApiErrors::from(resp).await),
+      }
+
+      filter_applied = resp
+        .headers()
+        .get(FILTERS_APPLIED_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|h| h.split(',').any(|f| f.trim() == "artifactType"));
+      let next = parse_link(resp.headers().get(header::LINK));
+      trace!("next_page {next:?}");
+
+      let page = resp.json::<ManifestList>().await?;
+      match index {
+        Some(ref mut index) => index.manifests.extend(page.manifests),
+        None => index = Some(page),
+      }
+
+      match next {
+        Some(query) => url = Url::parse(&format!("{base_url}?{query}"))?,
+        None => break,
+      }
+    }
+
+    let mut index = index.unwrap_or_default();
+    filter_referrers(&mut index, artifact_type, filter_applied);
+    Ok(Referrers {
+      index,
+      filter_applied,
+      tag_fallback: false,
+    })
+  }
+
+  async fn get_referrers_from_tag(
+    &self,
+    name: &str,
+    digest: &Digest,
+    artifact_type: Option<&str>,
+  ) -> Result<Referrers> {
+    let url = {
+      let ep = format!("{}/v2/{}/manifests/{}", self.base_url, name, referrers_tag(digest));
+      Url::parse(&ep)?
+    };
+
+    let resp = self
+      .build_reqwest(Method::GET, url)
+      .header(header::ACCEPT, MediaTypes::OciImageIndexV1.to_string())
+      .send()
+      .await?;
+
+    let status = resp.status();
+    trace!("GET '{}' status: {:?}", resp.url(), status);
+
+    let mut index = match status {
+      StatusCode::OK => resp.json::<ManifestList>().await?,
+      StatusCode::NOT_FOUND => ManifestList::builder().build()?,
+      _ => return Err(ApiErrors::from(resp).await),
+    };
+    filter_referrers(&mut index, artifact_type, false);
+    Ok(Referrers {
+      index,
+      filter_applied: false,
+      tag_fallback: true,
+    })
+  }
+}
+
+/// Tag under which referrers of `digest` are listed by registries without referrers API.
+///
+/// Format is described at <https://github.com/opencontainers/distribution-spec/blob/main/spec.md#referrers-tag-schema>.
+pub(crate) fn referrers_tag(digest: &Digest) -> String {
+  let algorithm: String = digest.algorithm().as_str().chars().take(32).collect();
+  let encoded: String = digest.encoded().chars().take(64).collect();
+  format!("{algorithm}-{encoded}")
+}
+
+fn filter_referrers(index: &mut ManifestList, artifact_type: Option<&str>, filter_applied: bool) {
+  if let (Some(artifact_type), false) = (artifact_type, filter_applied) {
+    index
+      .manifests
+      .retain(|m| m.artifact_type.as_deref() == Some(artifact_type));
+  }
+}
+
+impl Client {
+  /// Add `referrer` to the referrers tag of `subject`, for registries without referrers API.
+  pub(crate) async fn add_referrer_to_tag(&self, name: &str, subject: &Digest, referrer: Descriptor) -> Result<()> {
+    let mut index = self.get_referrers_from_tag(name, subject, None).await?.index;
+    if index.manifests.iter().any(|m| m.digest == referrer.digest) {
+      return Ok(());
+    }
+    index.manifests.push(referrer);
+    self
+      .put_manifest(name, &referrers_tag(subject), &index.encode()?)
+      .await
+      .map(|_| ())
+  }
+}
@@ -0,0 +1,183 @@
+//! Size accounting of images and repositories, without pulling them.
+//!
+//! Compressed sizes come from descriptors. Uncompressed sizes require streaming each
+//! layer through its decompressor, so they are only computed on request.
+
+use std::{
+  collections::{BTreeMap, HashSet},
+  io,
+};
+
+use futures::prelude::*;
+use log::trace;
+use reqwest::{Method, StatusCode, header};
+
+use crate::{
+  errors::{Error, Result},
+  render,
+  v2::{
+    manifest::{Descriptor, Manifest},
+    *,
+  },
+};
+
+/// Size of one or more images, counting each blob once.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImageSize {
+  /// Number of image manifests accounted for, excluding manifest lists.
+  pub images: usize,
+  /// Number of unique blobs (configs and layers).
+  pub blobs: usize,
+  /// Total size of the unique blobs, as stored in the registry.
+  pub compressed: u64,
+  /// Total size of the unique blobs once decompressed, if requested.
+  pub uncompressed: Option<u64>,
+}
+
+/// Blobs accounted for so far, with their compressed and uncompressed sizes.
+#[derive(Debug, Default)]
+struct Blobs {
+  images: HashSet<Digest>,
+  anonymous_images: usize,
+  blobs: BTreeMap<Digest, (u64, Option<u64>)>,
+}
+
+impl Blobs {
+  fn size(&self) -> ImageSize {
+    ImageSize {
+      images: self.images.len() + self.anonymous_images,
+      blobs: self.blobs.len(),
+      compressed: self.blobs.values().map(|(c, _)| c).sum(),
+      uncompressed: self.blobs.values().map(|(_, u)| *u).sum(),
+    }
+  }
+}
+
+impl Client {
+  /// Compute the size of the image `manifest` from repository `name`.
+  ///
+  /// For a manifest list or image index, the images of all its platforms are fetched
+  /// and accounted for, attestation manifests excluded; blobs shared between platforms
+  /// are counted once. If `uncompressed` is set, every layer is downloaded and
+  /// decompressed to measure its uncompressed size.
+  ///
+  /// Only configs and layers are accounted for, not the manifests and indexes themselves,
+  /// which usually weigh a few kilobytes.
+  pub async fn get_image_size(&self, name: &str, manifest: &Manifest, uncompressed: bool) -> Result<ImageSize> {
+    let mut blobs = Blobs::default();
+    self
+      .account_manifest(name, manifest, None, uncompressed, &mut blobs)
+      .await?;
+    Ok(blobs.size())
+  }
+
+  /// Compute the storage footprint of all the tags of repository `name`.
+  ///
+  /// Images and blobs shared between tags are counted once. See `get_image_size`.
+  pub async fn get_repository_size(&self, name: &str, uncompressed: bool) -> Result<ImageSize> {
+    let tags = self.get_tags(name, None).try_collect::<Vec<_>>().await?;
+    let mut blobs = Blobs::default();
+    for tag in tags {
+      let (manifest, digest) = self.get_manifest_and_ref(name, &tag).await?;
+      trace!("Accounting for tag {tag} ({digest:?})");
+      self
+        .account_manifest(name, &manifest, digest, uncompressed, &mut blobs)
+        .await?;
+    }
+    Ok(blobs.size())
+  }
+
+  async fn account_manifest(
+    &self,
+    name: &str,
+    manifest: &Manifest,
+    digest: Option<Digest>,
+    uncompressed: bool,
+    blobs: &mut Blobs,
+  ) -> Result<()> {
+    let Manifest::ML(list) = manifest else {
+      return self.account_image(name, manifest, digest, uncompressed, blobs).await;
+    };
+    for image in list.images() {
+      if !blobs.images.contains(&image.digest) {
+        let manifest = self.get_manifest(name, &image.digest.to_string()).await?;
+        self
+          .account_image(name, &manifest, Some(image.digest.clone()), uncompressed, blobs)
+          .await?;
+      }
+    }
+    Ok(())
+  }
+
+  async fn account_image(
+    &self,
+    name: &str,
+    manifest: &Manifest,
+    digest: Option<Digest>,
+    uncompressed: bool,
+    blobs: &mut Blobs,
+  ) -> Result<()> {
+    // Nested indexes are not followed.
+    if let Manifest::ML(_) = manifest {
+      return Ok(());
+    }
+    match digest {
+      Some(digest) => {
+        if !blobs.images.insert(digest) {
+          return Ok(());
+        }
+      }
+      None => blobs.anonymous_images += 1,
+    }
+    // Configs are stored uncompressed; schema 1 manifests embed theirs.
+    if let Some(config) = manifest.config() {
+      blobs.blobs.insert(
+        config.digest.clone(),
+        (config.size, uncompressed.then_some(config.size)),
+      );
+    }
+    for layer in manifest.layers(None)? {
+      if blobs.blobs.contains_key(&layer.digest) {
+        continue;
+      }
+      let size = match layer.size {
+        0 => self.get_blob_size(name, &layer.digest).await?,
+        size => size,
+      };
+      let layer_uncompressed = match uncompressed {
+        true => Some(self.get_uncompressed_size(name, &layer).await?),
+        false => None,
+      };
+      blobs.blobs.insert(layer.digest, (size, layer_uncompressed));
+    }
+    Ok(())
+  }
+
+  /// Get the size of a blob from the registry, for descriptors without one.
+  async fn get_blob_size(&self, name: &str, digest: &Digest) -> Result<u64> {
+    let url = {
+      let ep = format!("{}/v2/{}/blobs/{}", self.base_url, name, digest);
+      reqwest::Url::parse(&ep)?
+    };
+    let resp = self.build_reqwest(Method::HEAD, url).send().await?;
+    trace!("HEAD {} status: {}", resp.url(), resp.status());
+
+    blobs::check_status(resp, StatusCode::OK)
+      .await?
+      .headers()
+      .get(header::CONTENT_LENGTH)
+      .and_then(|h| h.to_str().ok())
+      .and_then(|h| h.parse().ok())
+      .ok_or(Error::MissingHeader("Content-Length"))
+  }
+
+  /// Get the size of a layer once decompressed, by streaming it through its decompressor.
+  async fn get_uncompressed_size(&self, name: &str, layer: &Descriptor) -> Result<u64> {
+    let compression = manifest::parse_media_type(&layer.media_type).layer_compression();
+    self
+      .read_blob_blocking(name, layer, move |reader| {
+        io::copy(&mut render::decompress(reader, compression)?, &mut io::sink())
+      })
+      .await
+  }
+}
@@ -92,7 +92,7 @@ impl Client {
 /// Parse a `Link` header.
 ///
 /// Format is described at https://docs.docker.com/registry/spec/api/#listing-image-tags#pagination.
-fn parse_link(hdr: Option<&header::HeaderValue>) -> Option<String> {
+pub(crate) fn parse_link(hdr: Option<&header::HeaderValue>) -> Option<String> {
   // TODO(lucab): this a brittle string-matching parser. Investigate
   // whether there is a a common library to do this, in the future.
 
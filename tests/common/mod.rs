@@ -0,0 +1,45 @@
+//! Helpers shared by the test targets.
+
+use std::io::Write;
+
+/// An uncompressed layer with the given files, or directories for paths ending with `/`.
+pub fn layer_tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+  let mut builder = tar::Builder::new(Vec::new());
+  for (path, content) in files {
+    if path.ends_with('/') {
+      append(&mut builder, tar::EntryType::Directory, path, content, "", 0o755);
+    } else {
+      append(&mut builder, tar::EntryType::Regular, path, content, "", 0o644);
+    }
+  }
+  builder.into_inner().unwrap()
+}
+
+/// Append an entry of the given type to a layer being built, a link to `link` when it is set.
+pub fn append(
+  builder: &mut tar::Builder<Vec<u8>>,
+  entry_type: tar::EntryType,
+  path: &str,
+  content: &[u8],
+  link: &str,
+  mode: u32,
+) {
+  let mut header = tar::Header::new_gnu();
+  header.set_entry_type(entry_type);
+  header.set_size(content.len() as u64);
+  header.set_mode(mode);
+  header.set_uid(0);
+  header.set_gid(0);
+  header.set_mtime(0);
+  if link.is_empty() {
+    builder.append_data(&mut header, path, content).unwrap();
+  } else {
+    builder.append_link(&mut header, path, link).unwrap();
+  }
+}
+
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+  let mut encoder = libflate::gzip::Encoder::new(Vec::new()).unwrap();
+  encoder.write_all(data).unwrap();
+  encoder.finish().into_result().unwrap()
+}
@@ -24,7 +24,7 @@ fn deserialize_manifest_v2s2_config() -> Result<docker_registry::v2::manifest::M
   let config_blob = {
     let f = fs::File::open(format!(
       "tests/fixtures/quay.io_v2_openshift-release-dev_ocp-release_manifests_4.1.0-rc.9/{}",
-      &manifest_spec.config().digest.replace(":", "_")
+      &manifest_spec.config().digest.to_string().replace(":", "_")
     ))
     .expect("Missing fixture");
     serde_json::from_reader::<_, docker_registry::v2::manifest::ConfigBlob>(f)?
@@ -121,3 +121,637 @@ fn test_labels_manifest_v2s1_signed() {
   assert_eq!(expected_labels_0, labels_0);
   assert_eq!(None, manif.get_labels(1));
 }
+
+#[test]
+fn test_manifest_v2s1_layers_base_first() {
+  let f = fs::File::open("tests/fixtures/manifest_v2_s1.json").expect("Missing fixture");
+  let bufrd = io::BufReader::new(f);
+  let manif: docker_registry::v2::manifest::ManifestSchema1Signed = serde_json::from_reader(bufrd).unwrap();
+
+  let digests: Vec<_> = manif.get_layers().into_iter().map(|l| l.digest).collect();
+  assert_eq!(manif.get_layers_digests(), digests);
+  assert_eq!(
+    "sha256:cc8567d70002e957612902a8e985ea129d831ebe04057d88fb644857caa45d11",
+    digests[1].to_string()
+  );
+}
+
+#[test]
+fn test_manifest_descriptors() -> Result<(), Box<dyn std::error::Error>> {
+  let manifest = deserialize_manifest_v2s2_config()?;
+
+  let config = manifest.config().expect("missing config descriptor");
+  assert_eq!("application/vnd.docker.container.image.v1+json", config.media_type);
+
+  let layers = manifest.layers(None)?;
+  assert_eq!(5, layers.len());
+  assert!(layers.iter().all(|l| l.size > 0));
+
+  let f = fs::File::open("tests/fixtures/manifest_list_v2.json").expect("Missing fixture");
+  let list: docker_registry::v2::manifest::ManifestList = serde_json::from_reader(f)?;
+  let descriptors = docker_registry::v2::manifest::Manifest::ML(list).layers(None)?;
+  assert_eq!(7143, descriptors[0].size);
+  assert_eq!(Some("ppc64le".to_string()), descriptors[0].architecture());
+
+  Ok(())
+}
+
+#[test]
+fn test_deserialize_oci_index_without_platform() -> Result<(), Box<dyn std::error::Error>> {
+  let index = r#"{
+    "schemaVersion": 2,
+    "mediaType": "application/vnd.oci.image.index.v1+json",
+    "manifests": [
+      {
+        "mediaType": "application/vnd.oci.image.manifest.v1+json",
+        "size": 7143,
+        "digest": "sha256:e692418e4cbaf90ca69d05a66403747baa33ee08806650b51fab815ad7fc331f",
+        "annotations": { "org.opencontainers.image.ref.name": "v1" }
+      }
+    ]
+  }"#;
+  let list: docker_registry::v2::manifest::ManifestList = serde_json::from_str(index)?;
+
+  assert!(list.architectures().is_empty());
+  assert_eq!(
+    Some("v1"),
+    list.manifests[0].annotation("org.opencontainers.image.ref.name")
+  );
+
+  Ok(())
+}
+
+#[test]
+fn test_verify_etcd_manifest_signature() -> Result<(), Box<dyn std::error::Error>> {
+  let raw = fs::read("tests/fixtures/quayio_coreos_etcd_latest.json")?;
+  let manif = docker_registry::v2::manifest::ManifestSchema1Signed::from_bytes(raw.clone())?;
+
+  let keys = manif.verify()?;
+  assert_eq!(1, keys.len());
+  assert_eq!("RSA", keys[0].kty);
+  assert_eq!(
+    "sha256:63fbaf3879c76b54cfd9d791d39bb09cef1aca4c44f41b42e5e255e624f9870b",
+    manif.canonical_digest()?.to_string()
+  );
+
+  // Tampering with the signed content must be detected.
+  let tampered = String::from_utf8(raw.clone())?.replacen("amd64", "arm64", 1);
+  let manif = docker_registry::v2::manifest::ManifestSchema1Signed::from_bytes(tampered.into_bytes())?;
+  assert!(manif.verify().is_err());
+
+  // So must a signature which is not even base64url.
+  let malformed = String::from_utf8(raw)?.replacen(r#""signature": ""#, r#""signature": "!"#, 1);
+  let manif = docker_registry::v2::manifest::ManifestSchema1Signed::from_bytes(malformed.into_bytes())?;
+  assert!(matches!(
+    manif.verify(),
+    Err(docker_registry::v2::manifest::SignatureError::MalformedSignature(_))
+  ));
+
+  Ok(())
+}
+
+#[test]
+fn test_verify_manifest_signature_p256() -> Result<(), Box<dyn std::error::Error>> {
+  use base64::prelude::*;
+  use p256::ecdsa::{Signature, SigningKey, signature::Signer};
+
+  let payload = r#"{
+   "schemaVersion": 1,
+   "name": "test/p256",
+   "tag": "latest",
+   "architecture": "amd64",
+   "fsLayers": [
+      {
+         "blobSum": "sha256:a3ed95caeb02ffe68cdd9fd84406680ae93d633cb16422d00e8a7c22955b46d4"
+      }
+   ],
+   "history": [
+      {
+         "v1Compatibility": "{\"id\":\"1\"}"
+      }
+   ]
+}"#;
+  let format_length = payload.len() - 2;
+  let protected = BASE64_URL_SAFE_NO_PAD.encode(format!(
+    r#"{{"formatLength":{},"formatTail":"{}","time":"2026-01-01T00:00:00Z"}}"#,
+    format_length,
+    BASE64_URL_SAFE_NO_PAD.encode(&payload[format_length..])
+  ));
+
+  let key = SigningKey::from_slice(&[7u8; 32])?;
+  let signing_input = format!("{}.{}", protected, BASE64_URL_SAFE_NO_PAD.encode(payload));
+  let signature: Signature = key.sign(signing_input.as_bytes());
+  let point = key.verifying_key().to_encoded_point(false);
+
+  let raw = format!(
+    r#"{},
+   "signatures": [
+      {{
+         "header": {{
+            "jwk": {{ "kty": "EC", "crv": "P-256", "kid": "test", "x": "{}", "y": "{}" }},
+            "alg": "ES256"
+         }},
+         "signature": "{}",
+         "protected": "{}"
+      }}
+   ]
+}}"#,
+    &payload[..format_length],
+    BASE64_URL_SAFE_NO_PAD.encode(point.x().unwrap()),
+    BASE64_URL_SAFE_NO_PAD.encode(point.y().unwrap()),
+    BASE64_URL_SAFE_NO_PAD.encode(signature.to_bytes()),
+    protected,
+  );
+
+  let manif = docker_registry::v2::manifest::ManifestSchema1Signed::from_bytes(raw.into_bytes())?;
+  let keys = manif.verify()?;
+  assert_eq!(Some("test".to_string()), keys[0].kid);
+  assert_eq!(
+    docker_registry::v2::Digest::sha256(payload.as_bytes()),
+    manif.canonical_digest()?
+  );
+
+  Ok(())
+}
+
+#[test]
+fn test_verify_manifest_signature_without_raw_bytes() {
+  let f = fs::File::open("tests/fixtures/quayio_coreos_etcd_latest.json").expect("Missing fixture");
+  let manif: docker_registry::v2::manifest::ManifestSchema1Signed = serde_json::from_reader(f).unwrap();
+  assert!(matches!(
+    manif.verify(),
+    Err(docker_registry::v2::manifest::SignatureError::MissingRawManifest)
+  ));
+}
+
+#[test]
+fn test_convert_manifest_v2s1() -> Result<(), Box<dyn std::error::Error>> {
+  use std::io::Write;
+
+  use docker_registry::{
+    mediatypes::MediaTypes,
+    v2::{Digest, manifest::ManifestSchema1Signed},
+  };
+
+  let base = b"base layer content".to_vec();
+  let mut encoder = libflate::gzip::Encoder::new(Vec::new())?;
+  encoder.write_all(b"top layer content")?;
+  let top = encoder.finish().into_result()?;
+  let throwaway = b"empty layer".to_vec();
+
+  let manifest = serde_json::json!({
+    "schemaVersion": 1,
+    "name": "test/convert",
+    "tag": "latest",
+    "architecture": "amd64",
+    "fsLayers": [
+      { "blobSum": Digest::sha256(&top).to_string() },
+      { "blobSum": Digest::sha256(&throwaway).to_string() },
+      { "blobSum": Digest::sha256(&base).to_string() },
+    ],
+    "history": [
+      { "v1Compatibility": r##"{"id":"3","parent":"2","architecture":"amd64","os":"linux","config":{"Env":["A=1"]},"container_config":{"Cmd":["/bin/sh","-c","touch /top"]},"created":"2020-01-03T00:00:00Z"}"## },
+      { "v1Compatibility": r##"{"id":"2","parent":"1","throwaway":true,"container_config":{"Cmd":["/bin/sh","-c","#(nop) ENV A=1"]},"created":"2020-01-02T00:00:00Z"}"## },
+      { "v1Compatibility": r##"{"id":"1","author":"me","created":"2020-01-01T00:00:00Z"}"## },
+    ],
+    "signatures": [],
+  });
+  let manifest: ManifestSchema1Signed = serde_json::from_value(manifest)?;
+  let layers = vec![base.clone(), throwaway.clone(), top.clone()];
+
+  let converted = manifest.convert(MediaTypes::OciImageManifest, &layers)?;
+  assert_eq!(
+    "application/vnd.oci.image.manifest.v1+json",
+    converted.manifest.media_type()
+  );
+  assert_eq!(
+    vec![Digest::sha256(&base), Digest::sha256(&top)],
+    converted
+      .manifest
+      .layers()
+      .iter()
+      .map(|l| l.digest.clone())
+      .collect::<Vec<_>>()
+  );
+  assert_eq!(top.len() as u64, converted.manifest.layers()[1].size);
+
+  let config = converted.manifest.config();
+  assert_eq!("application/vnd.oci.image.config.v1+json", config.media_type);
+  assert_eq!(Digest::sha256(&converted.config), config.digest);
+
+  let config: serde_json::Value = serde_json::from_slice(&converted.config)?;
+  assert_eq!(None, config.get("id"));
+  assert_eq!(Some("amd64"), config["architecture"].as_str());
+  assert_eq!(
+    serde_json::json!([
+      Digest::sha256(&base).to_string(),
+      Digest::sha256(b"top layer content").to_string()
+    ]),
+    config["rootfs"]["diff_ids"]
+  );
+  assert_eq!(3, config["history"].as_array().unwrap().len());
+  assert_eq!(serde_json::json!(true), config["history"][1]["empty_layer"]);
+  assert_eq!("/bin/sh -c touch /top", config["history"][2]["created_by"]);
+
+  // Layer blobs must match the manifest.
+  let layers = vec![base, top, throwaway];
+  assert!(manifest.convert(MediaTypes::ManifestV2S2, &layers).is_err());
+  assert!(manifest.convert(MediaTypes::ManifestList, &[]).is_err());
+
+  Ok(())
+}
+
+#[test]
+fn test_convert_manifest_v2s2_to_oci_and_back() -> Result<(), Box<dyn std::error::Error>> {
+  use docker_registry::v2::manifest::ManifestSchema2Spec;
+
+  let f = fs::File::open("tests/fixtures/manifest_v2_s2.json")?;
+  let manifest: ManifestSchema2Spec = serde_json::from_reader(f)?;
+
+  let (oci, report) = manifest.to_oci();
+  assert!(report.is_lossless());
+  assert_eq!("application/vnd.oci.image.manifest.v1+json", oci.media_type());
+  assert_eq!("application/vnd.oci.image.config.v1+json", oci.config().media_type);
+  assert!(
+    oci
+      .layers()
+      .iter()
+      .all(|l| l.media_type == "application/vnd.oci.image.layer.v1.tar+gzip")
+  );
+
+  let (docker, report) = oci.to_docker();
+  assert!(report.is_lossless());
+  assert_eq!(serde_json::to_value(&manifest)?, serde_json::to_value(&docker)?);
+
+  Ok(())
+}
+
+#[test]
+fn test_convert_oci_manifest_to_docker_report() -> Result<(), Box<dyn std::error::Error>> {
+  use docker_registry::v2::{
+    Digest,
+    manifest::{ConversionLoss, ManifestList, ManifestSchema2Spec},
+  };
+
+  let manifest = r#"{
+    "schemaVersion": 2,
+    "mediaType": "application/vnd.oci.image.manifest.v1+json",
+    "config": {
+      "mediaType": "application/vnd.oci.image.config.v1+json",
+      "size": 2297,
+      "digest": "sha256:7324f32f94760ec1dc237858203ea520fc4e6dfbd0bc018f392e54b1392ac722"
+    },
+    "layers": [
+      {
+        "mediaType": "application/vnd.oci.image.layer.v1.tar+zstd",
+        "size": 28028344,
+        "digest": "sha256:b2afc8f0dccbc5496c814ae03ac3fff7e86393abd18b2d2910a9c489bfe64311"
+      },
+      {
+        "mediaType": "application/vnd.oci.image.layer.nondistributable.v1.tar+gzip",
+        "size": 1024,
+        "digest": "sha256:e692418e4cbaf90ca69d05a66403747baa33ee08806650b51fab815ad7fc331f",
+        "urls": ["https://example.com/layer"],
+        "annotations": { "com.example.layer": "foreign" }
+      }
+    ],
+    "annotations": { "org.opencontainers.image.created": "2024-01-01T00:00:00Z" }
+  }"#;
+  let manifest: ManifestSchema2Spec = serde_json::from_str(manifest)?;
+
+  let (docker, report) = manifest.to_docker();
+  assert_eq!(
+    "application/vnd.docker.distribution.manifest.v2+json",
+    docker.media_type()
+  );
+  assert_eq!(
+    "application/vnd.docker.image.rootfs.foreign.diff.tar.gzip",
+    docker.layers()[1].media_type
+  );
+  assert!(docker.layers()[1].urls.is_some());
+  assert!(docker.layers()[1].annotations.is_none());
+  assert!(docker.annotations().is_none());
+
+  let zstd_layer = Digest::try_from("sha256:b2afc8f0dccbc5496c814ae03ac3fff7e86393abd18b2d2910a9c489bfe64311")?;
+  let foreign_layer = Digest::try_from("sha256:e692418e4cbaf90ca69d05a66403747baa33ee08806650b51fab815ad7fc331f")?;
+  assert_eq!(
+    vec![
+      ConversionLoss::MediaType {
+        digest: zstd_layer,
+        media_type: "application/vnd.oci.image.layer.v1.tar+zstd".to_string(),
+      },
+      ConversionLoss::Annotations(Some(foreign_layer)),
+      ConversionLoss::Annotations(None),
+    ],
+    report.losses
+  );
+
+  let f = fs::File::open("tests/fixtures/manifest_list_v2.json")?;
+  let list: ManifestList = serde_json::from_reader(f)?;
+  let (index, report) = list.to_oci();
+  assert!(report.is_lossless());
+  assert_eq!("application/vnd.oci.image.index.v1+json", index.media_type());
+  assert_eq!(list.manifests.len(), index.manifests.len());
+  // The entries of the fixture have a media type unknown to the conversion.
+  assert!(report.is_complete());
+
+  let list: ManifestList = serde_json::from_value(serde_json::json!({
+    "schemaVersion": 2,
+    "mediaType": "application/vnd.docker.distribution.manifest.list.v2+json",
+    "manifests": [{
+      "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+      "size": 7143,
+      "digest": "sha256:e692418e4cbaf90ca69d05a66403747baa33ee08806650b51fab815ad7fc331f",
+      "platform": { "architecture": "amd64", "os": "linux" }
+    }]
+  }))?;
+  let (index, report) = list.to_oci();
+  // The Docker manifest is still referenced by its digest, it has to be converted too.
+  assert!(!report.is_complete());
+  assert_eq!(index.manifests, report.unconverted);
+  assert_eq!(
+    "application/vnd.oci.image.manifest.v1+json",
+    report.unconverted[0].media_type
+  );
+
+  Ok(())
+}
+
+#[test]
+fn test_manifest_builder() -> Result<(), Box<dyn std::error::Error>> {
+  use docker_registry::{
+    mediatypes::MediaTypes,
+    v2::{
+      Digest,
+      manifest::{Descriptor, ManifestList, ManifestSchema2Spec},
+    },
+  };
+
+  let config = Descriptor::new(&MediaTypes::OciImageConfig.to_string(), Digest::sha256(b"{}"), 2);
+  let layer = Descriptor::new(&MediaTypes::OciImageLayerTgz.to_string(), Digest::sha256(b"layer"), 5);
+
+  let manifest = ManifestSchema2Spec::builder()
+    .config(config.clone())
+    .layer(layer.clone())
+    .annotation("org.opencontainers.image.title", "test")
+    .annotation("org.opencontainers.image.created", "2024-01-01T00:00:00Z")
+    .build()?;
+  let encoded = manifest.encode()?;
+  assert_eq!(Digest::sha256(&encoded.bytes), encoded.digest);
+  assert_eq!("application/vnd.oci.image.manifest.v1+json", encoded.media_type);
+  assert!(
+    String::from_utf8(encoded.bytes.clone())?
+      .starts_with(r#"{"schemaVersion":2,"mediaType":"application/vnd.oci.image.manifest.v1+json","config":"#)
+  );
+
+  // Encoding is stable across a round-trip.
+  let parsed: ManifestSchema2Spec = serde_json::from_slice(&encoded.bytes)?;
+  assert_eq!(encoded, parsed.encode()?);
+
+  let mut entry = encoded.descriptor();
+  assert_eq!(encoded.bytes.len() as u64, entry.size);
+  let index = ManifestList::builder().manifest(entry.clone()).build()?;
+  assert_eq!("application/vnd.oci.image.index.v1+json", index.media_type());
+
+  // Docker manifest lists require a platform for every entry.
+  assert!(
+    ManifestList::builder()
+      .media_type(MediaTypes::ManifestList)
+      .manifest(entry.clone())
+      .build()
+      .is_err()
+  );
+  entry.media_type = MediaTypes::ManifestV2S2.to_string();
+  entry.platform = Some(docker_registry::v2::manifest::Platform {
+    architecture: "amd64".to_string(),
+    os: "linux".to_string(),
+    ..Default::default()
+  });
+  ManifestList::builder()
+    .media_type(MediaTypes::ManifestList)
+    .manifest(entry)
+    .build()?;
+
+  // Docker manifests only accept Docker media types and no OCI-only fields.
+  assert!(
+    ManifestSchema2Spec::builder()
+      .media_type(MediaTypes::ManifestV2S2)
+      .config(config.clone())
+      .layer(layer.clone())
+      .build()
+      .is_err()
+  );
+  assert!(ManifestSchema2Spec::builder().layer(layer.clone()).build().is_err());
+
+  // Manifests and indexes are not blobs, nor are blobs manifests.
+  assert!(
+    ManifestSchema2Spec::builder()
+      .config(config.clone())
+      .layer(encoded.descriptor())
+      .build()
+      .is_err()
+  );
+  assert!(
+    ManifestSchema2Spec::builder()
+      .config(index.encode()?.descriptor())
+      .build()
+      .is_err()
+  );
+  assert!(ManifestList::builder().manifest(layer.clone()).build().is_err());
+
+  // Artifacts with an empty config must declare their type.
+  let empty = Descriptor::new(&MediaTypes::OciEmpty.to_string(), Digest::sha256(b"{}"), 2);
+  assert!(
+    ManifestSchema2Spec::builder()
+      .config(empty.clone())
+      .layer(layer.clone())
+      .build()
+      .is_err()
+  );
+  let artifact = ManifestSchema2Spec::builder()
+    .artifact_type("application/vnd.example+type")
+    .config(empty)
+    .layer(layer)
+    .build()?;
+  assert_eq!(Some("application/vnd.example+type"), artifact.artifact_type());
+
+  Ok(())
+}
+
+#[test]
+fn test_buildx_index_attestations() -> Result<(), Box<dyn std::error::Error>> {
+  let f = fs::File::open("tests/fixtures/manifest_buildx_index.json")?;
+  let index: docker_registry::v2::manifest::ManifestList = serde_json::from_reader(f)?;
+
+  assert_eq!(vec!["amd64", "arm64"], index.architectures());
+  assert_eq!(2, index.get_digests().len());
+
+  let arm64 = index.get_platform("linux", "arm64", None).expect("missing arm64 image");
+  assert_eq!(Some(arm64), index.get_platform("linux", "arm64", Some("v8")));
+  assert_eq!(None, index.get_platform("linux", "arm64", Some("v7")));
+  assert_eq!(None, index.get_platform("unknown", "unknown", None));
+
+  let attestations = index.attestations().collect::<Vec<_>>();
+  assert_eq!(2, attestations.len());
+  assert_eq!(Some(arm64.digest.clone()), attestations[1].reference_digest());
+
+  Ok(())
+}
+
+#[test]
+fn test_image_diff() -> Result<(), Box<dyn std::error::Error>> {
+  use docker_registry::{
+    mediatypes::MediaTypes,
+    v2::{
+      Digest,
+      diff::ConfigChange,
+      manifest::{ConfigBlob, Descriptor, ManifestSchema2, ManifestSchema2Spec},
+    },
+  };
+
+  let image = |config: serde_json::Value, layers: &[&[u8]]| -> Result<ManifestSchema2, Box<dyn std::error::Error>> {
+    let raw = serde_json::to_vec(&config)?;
+    let manifest_spec = ManifestSchema2Spec::builder()
+      .config(Descriptor::new(
+        &MediaTypes::OciImageConfig.to_string(),
+        Digest::sha256(&raw),
+        raw.len() as u64,
+      ))
+      .layers(layers.iter().map(|l| {
+        Descriptor::new(
+          &MediaTypes::OciImageLayerTgz.to_string(),
+          Digest::sha256(l),
+          l.len() as u64,
+        )
+      }))
+      .build()?;
+    Ok(ManifestSchema2 {
+      manifest_spec,
+      config_blob: ConfigBlob::from_bytes(raw)?,
+    })
+  };
+  let diff_id = |s: &[u8]| Digest::sha256(s).to_string();
+
+  let old = image(
+    serde_json::json!({
+      "architecture": "amd64",
+      "os": "linux",
+      "config": {
+        "Env": ["PATH=/usr/bin", "VERSION=1.2"],
+        "Entrypoint": ["/app"],
+        "Labels": { "maintainer": "me", "version": "1.2" },
+        "ExposedPorts": { "80/tcp": {} },
+      },
+      "rootfs": { "type": "layers", "diff_ids": [diff_id(b"base"), diff_id(b"app-1.2")] },
+    }),
+    &[b"base.gz", b"app-1.2.gz"],
+  )?;
+  let new = image(
+    serde_json::json!({
+      "architecture": "amd64",
+      "os": "linux",
+      "config": {
+        "Env": ["PATH=/usr/bin", "VERSION=1.3", "DEBUG=0"],
+        "Entrypoint": ["/app"],
+        "User": "app",
+        "Labels": { "version": "1.3" },
+        "ExposedPorts": { "443/tcp": {} },
+      },
+      "rootfs": { "type": "layers", "diff_ids": [diff_id(b"base"), diff_id(b"app-1.3")] },
+    }),
+    &[b"base.gz", b"app-1.3.gz"],
+  )?;
+  assert_eq!([Digest::sha256(b"base"), Digest::sha256(b"app-1.3")], new.diff_ids());
+
+  let diff = old.diff(&new);
+  assert_eq!(
+    vec![Digest::sha256(b"base.gz")],
+    diff.layers.shared.iter().map(|l| l.digest.clone()).collect::<Vec<_>>()
+  );
+  assert_eq!(Digest::sha256(b"app-1.3.gz"), diff.layers.added[0].digest);
+  assert_eq!(Digest::sha256(b"app-1.2.gz"), diff.layers.removed[0].digest);
+  assert_eq!(vec![Digest::sha256(b"base")], diff.layers.shared_diff_ids);
+  assert_eq!(vec![Digest::sha256(b"app-1.3")], diff.layers.added_diff_ids);
+  assert_eq!(vec![Digest::sha256(b"app-1.2")], diff.layers.removed_diff_ids);
+
+  let some = |s: &str| Some(s.to_string());
+  assert_eq!(
+    vec![
+      ConfigChange::Env {
+        name: "DEBUG".to_string(),
+        old: None,
+        new: some("0"),
+      },
+      ConfigChange::Env {
+        name: "VERSION".to_string(),
+        old: some("1.2"),
+        new: some("1.3"),
+      },
+      ConfigChange::Label {
+        name: "maintainer".to_string(),
+        old: some("me"),
+        new: None,
+      },
+      ConfigChange::Label {
+        name: "version".to_string(),
+        old: some("1.2"),
+        new: some("1.3"),
+      },
+      ConfigChange::ExposedPort {
+        port: "443/tcp".to_string(),
+        exposed: true,
+      },
+      ConfigChange::ExposedPort {
+        port: "80/tcp".to_string(),
+        exposed: false,
+      },
+      ConfigChange::User {
+        old: None,
+        new: some("app")
+      },
+    ],
+    diff.config
+  );
+
+  let diff = new.diff(&new);
+  assert_eq!(2, diff.layers.shared.len());
+  assert!(diff.layers.added.is_empty() && diff.layers.removed.is_empty() && diff.config.is_empty());
+
+  Ok(())
+}
+
+#[test]
+fn test_index_diff_platforms() -> Result<(), Box<dyn std::error::Error>> {
+  use docker_registry::v2::{Digest, manifest::ManifestList};
+
+  let f = fs::File::open("tests/fixtures/manifest_buildx_index.json")?;
+  let old: ManifestList = serde_json::from_reader(f)?;
+  let amd64 = old
+    .get_platform("linux", "amd64", None)
+    .expect("missing amd64 image")
+    .clone();
+  let mut arm64 = old
+    .get_platform("linux", "arm64", None)
+    .expect("missing arm64 image")
+    .clone();
+  arm64.digest = Digest::sha256(b"rebuilt");
+  let mut riscv64 = amd64.clone();
+  riscv64.platform.as_mut().unwrap().architecture = "riscv64".to_string();
+
+  let new = ManifestList::builder()
+    .manifests([arm64.clone(), riscv64.clone()])
+    .manifests(old.attestations().cloned())
+    .build()?;
+  let diff = old.diff_platforms(&new);
+  assert_eq!(vec![riscv64], diff.added);
+  assert_eq!(vec![amd64], diff.removed);
+  assert_eq!(1, diff.changed.len());
+  assert_eq!(arm64, diff.changed[0].1);
+  assert!(diff.unchanged.is_empty());
+
+  let diff = old.diff_platforms(&old);
+  assert_eq!(2, diff.unchanged.len());
+  assert!(diff.added.is_empty() && diff.removed.is_empty() && diff.changed.is_empty());
+
+  Ok(())
+}
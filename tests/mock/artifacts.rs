@@ -0,0 +1,178 @@
+use std::fs;
+
+use docker_registry::{
+  mediatypes::MediaTypes,
+  v2::{Artifact, Digest, manifest::Descriptor},
+};
+use mockito::Matcher;
+
+use super::client;
+
+static ARTIFACT_TYPE: &str = "application/vnd.example.policy";
+static FILE_TYPE: &str = "application/vnd.example.policy.rego";
+
+#[tokio::test]
+async fn test_push_artifact() {
+  let name = "repo";
+  let dir = tempfile::tempdir().unwrap();
+  let file = dir.path().join("policy.rego");
+  fs::write(&file, b"package example").unwrap();
+  let subject = Descriptor::new(&MediaTypes::OciImageManifest.to_string(), Digest::sha256(b"subject"), 7);
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+
+  let head_mock = server
+    .mock("HEAD", Matcher::Regex(format!("^/v2/{name}/blobs/sha256:")))
+    .with_status(404)
+    .expect(2)
+    .create();
+  let upload_mock = server
+    .mock("POST", format!("/v2/{name}/blobs/uploads/").as_str())
+    .with_status(202)
+    .with_header("Location", &format!("/v2/{name}/blobs/uploads/some-uuid?state=abc"))
+    .expect(2)
+    .create();
+  let file_mock = server
+    .mock("PUT", format!("/v2/{name}/blobs/uploads/some-uuid").as_str())
+    .match_query(Matcher::AllOf(vec![
+      Matcher::UrlEncoded("state".into(), "abc".into()),
+      Matcher::UrlEncoded("digest".into(), Digest::sha256(b"package example").to_string()),
+    ]))
+    .match_body("package example")
+    .with_status(201)
+    .create();
+  let config_mock = server
+    .mock("PUT", format!("/v2/{name}/blobs/uploads/some-uuid").as_str())
+    .match_query(Matcher::UrlEncoded("digest".into(), Digest::sha256(b"{}").to_string()))
+    .match_body("{}")
+    .with_status(201)
+    .create();
+  let manifest_mock = server
+    .mock("PUT", format!("/v2/{name}/manifests/v1").as_str())
+    .match_header("Content-Type", "application/vnd.oci.image.manifest.v1+json")
+    .match_body(Matcher::PartialJsonString(format!(
+      r#"{{
+        "artifactType": "{ARTIFACT_TYPE}",
+        "config": {{ "mediaType": "application/vnd.oci.empty.v1+json", "size": 2 }},
+        "layers": [{{
+          "mediaType": "{FILE_TYPE}",
+          "size": 15,
+          "annotations": {{ "org.opencontainers.image.title": "policy.rego" }}
+        }}],
+        "subject": {{ "digest": "{}" }},
+        "annotations": {{ "com.example.version": "1" }}
+      }}"#,
+      subject.digest
+    )))
+    .with_status(201)
+    .create();
+  // The registry does not support the referrers API, so the referrers tag gets updated.
+  let tag = format!("/v2/{name}/manifests/sha256-{}", subject.digest.encoded());
+  let tag_get_mock = server.mock("GET", tag.as_str()).with_status(404).create();
+  let tag_put_mock = server
+    .mock("PUT", tag.as_str())
+    .match_header("Content-Type", "application/vnd.oci.image.index.v1+json")
+    .match_body(Matcher::PartialJsonString(format!(
+      r#"{{ "manifests": [{{ "artifactType": "{ARTIFACT_TYPE}" }}] }}"#
+    )))
+    .with_status(201)
+    .create();
+
+  let artifact = Artifact::new(ARTIFACT_TYPE)
+    .file(&file, FILE_TYPE)
+    .annotation("com.example.version", "1")
+    .subject(subject);
+  client(&addr).push_artifact(name, Some("v1"), &artifact).await.unwrap();
+
+  head_mock.assert();
+  upload_mock.assert();
+  file_mock.assert();
+  config_mock.assert();
+  manifest_mock.assert();
+  tag_get_mock.assert();
+  tag_put_mock.assert();
+}
+
+#[tokio::test]
+async fn test_pull_artifact() {
+  let name = "repo";
+  let content = b"package example";
+  let digest = Digest::sha256(content);
+  let manifest = format!(
+    r#"{{
+      "schemaVersion": 2,
+      "mediaType": "application/vnd.oci.image.manifest.v1+json",
+      "artifactType": "{ARTIFACT_TYPE}",
+      "config": {{
+        "mediaType": "application/vnd.oci.empty.v1+json",
+        "size": 2,
+        "digest": "{}"
+      }},
+      "layers": [
+        {{
+          "mediaType": "{FILE_TYPE}",
+          "size": {},
+          "digest": "{digest}",
+          "annotations": {{ "org.opencontainers.image.title": "policy.rego" }}
+        }}
+      ]
+    }}"#,
+    Digest::sha256(b"{}"),
+    content.len()
+  );
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+
+  let _manifest_mock = server
+    .mock("GET", format!("/v2/{name}/manifests/v1").as_str())
+    .with_status(200)
+    .with_header("Content-Type", "application/vnd.oci.image.manifest.v1+json")
+    .with_body(&manifest)
+    .create();
+  let _blob_mock = server
+    .mock("GET", format!("/v2/{name}/blobs/{digest}").as_str())
+    .with_status(200)
+    .with_body(content)
+    .create();
+
+  let dir = tempfile::tempdir().unwrap();
+  let paths = client(&addr).pull_artifact(name, "v1", dir.path()).await.unwrap();
+
+  assert_eq!(vec![dir.path().join("policy.rego")], paths);
+  assert_eq!(content.to_vec(), fs::read(&paths[0]).unwrap());
+
+  // Titles must not escape the target directory.
+  let _evil_mock = server
+    .mock("GET", format!("/v2/{name}/manifests/evil").as_str())
+    .with_status(200)
+    .with_header("Content-Type", "application/vnd.oci.image.manifest.v1+json")
+    .with_body(manifest.replace("policy.rego", "../policy.rego"))
+    .create();
+  assert!(client(&addr).pull_artifact(name, "evil", dir.path()).await.is_err());
+
+  // Corrupted files are not left behind.
+  let corrupt = Digest::sha256(b"package corrupt");
+  let _corrupt_mock = server
+    .mock("GET", format!("/v2/{name}/manifests/corrupt").as_str())
+    .with_status(200)
+    .with_header("Content-Type", "application/vnd.oci.image.manifest.v1+json")
+    .with_body(
+      manifest
+        .replace("policy.rego", "corrupt.rego")
+        .replace(&digest.to_string(), &corrupt.to_string()),
+    )
+    .create();
+  let _corrupt_blob_mock = server
+    .mock("GET", format!("/v2/{name}/blobs/{corrupt}").as_str())
+    .with_status(200)
+    .with_body(content)
+    .create();
+  assert!(client(&addr).pull_artifact(name, "corrupt", dir.path()).await.is_err());
+  let files: Vec<_> = fs::read_dir(dir.path())
+    .unwrap()
+    .map(|e| e.unwrap().file_name())
+    .collect();
+  assert_eq!(vec!["policy.rego"], files);
+}
@@ -0,0 +1,118 @@
+use docker_registry::v2::{Digest, manifest::ManifestList};
+
+static AMD64: &str = "sha256:5b0bcabd1ed22e9fb1310cf6c2dec7cdef19f0ad69efa1f392e94a4333501270";
+
+fn statement(predicate_type: &str) -> Vec<u8> {
+  format!(
+    r#"{{
+      "_type": "https://in-toto.io/Statement/v0.1",
+      "predicateType": "{predicate_type}",
+      "subject": [{{ "name": "pkg:docker/repo@latest", "digest": {{ "sha256": "{}" }} }}],
+      "predicate": {{}}
+    }}"#,
+    &AMD64["sha256:".len()..]
+  )
+  .into_bytes()
+}
+
+fn index_body(image: &str, attestation: &Digest, attestation_size: usize) -> String {
+  format!(
+    r#"{{
+      "schemaVersion": 2,
+      "mediaType": "application/vnd.oci.image.index.v1+json",
+      "manifests": [
+        {{
+          "mediaType": "application/vnd.oci.image.manifest.v1+json",
+          "digest": "{image}",
+          "size": 1050,
+          "platform": {{ "architecture": "amd64", "os": "linux" }}
+        }},
+        {{
+          "mediaType": "application/vnd.oci.image.manifest.v1+json",
+          "digest": "{attestation}",
+          "size": {attestation_size},
+          "annotations": {{
+            "vnd.docker.reference.digest": "{image}",
+            "vnd.docker.reference.type": "attestation-manifest"
+          }},
+          "platform": {{ "architecture": "unknown", "os": "unknown" }}
+        }}
+      ]
+    }}"#
+  )
+}
+
+#[tokio::test]
+async fn test_get_attestations() {
+  let name = "repo";
+  let sbom = statement("https://spdx.dev/Document");
+  let provenance = statement("https://slsa.dev/provenance/v0.2");
+
+  let layer = |blob: &[u8], predicate_type: &str| {
+    format!(
+      r#"{{
+        "mediaType": "application/vnd.in-toto+json",
+        "digest": "{}",
+        "size": {},
+        "annotations": {{ "in-toto.io/predicate-type": "{predicate_type}" }}
+      }}"#,
+      Digest::sha256(blob),
+      blob.len()
+    )
+  };
+  let manifest = format!(
+    r#"{{
+      "schemaVersion": 2,
+      "mediaType": "application/vnd.oci.image.manifest.v1+json",
+      "config": {{
+        "mediaType": "application/vnd.oci.image.config.v1+json",
+        "digest": "{}",
+        "size": 2
+      }},
+      "layers": [{}, {}]
+    }}"#,
+    Digest::sha256(b"{}"),
+    layer(&sbom, "https://spdx.dev/Document"),
+    layer(&provenance, "https://slsa.dev/provenance/v0.2"),
+  );
+  let manifest_digest = Digest::sha256(manifest.as_bytes());
+  let index: ManifestList = serde_json::from_str(&index_body(AMD64, &manifest_digest, manifest.len())).unwrap();
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+
+  let manifest_mock = server
+    .mock("GET", format!("/v2/{name}/manifests/{manifest_digest}").as_str())
+    .with_status(200)
+    .with_header("Content-Type", "application/vnd.oci.image.manifest.v1+json")
+    .with_body(&manifest)
+    .create();
+  for blob in [&sbom, &provenance] {
+    server
+      .mock("GET", format!("/v2/{name}/blobs/{}", Digest::sha256(blob)).as_str())
+      .with_status(200)
+      .with_body(blob)
+      .create();
+  }
+
+  let client = docker_registry::v2::Client::configure()
+    .registry(&addr)
+    .insecure_registry(true)
+    .username(None)
+    .password(None)
+    .build()
+    .unwrap();
+
+  let image = Digest::try_from(AMD64).unwrap();
+  let statements = client.get_attestations(name, &index, &image).await.unwrap();
+
+  manifest_mock.assert();
+  assert_eq!(2, statements.len());
+  assert!(statements[0].is_spdx());
+  assert!(statements[1].is_slsa_provenance());
+  assert!(statements.iter().all(|s| s.has_subject(&image)));
+
+  // Other images have no attestations.
+  let other = Digest::sha256(b"other");
+  assert!(client.get_attestations(name, &index, &other).await.unwrap().is_empty());
+}
@@ -1,12 +1,23 @@
-use futures::{FutureExt, stream::StreamExt};
-use sha2::Digest;
+use std::fs;
+
+use docker_registry::{
+  errors::Error,
+  v2::{Digest, manifest::Descriptor},
+};
+use futures::{
+  FutureExt,
+  stream::{StreamExt, TryStreamExt},
+};
+use tokio::io::AsyncReadExt;
+
+use super::client;
 
 type Fallible<T> = Result<T, Box<dyn std::error::Error>>;
 
 #[tokio::test]
 async fn test_blobs_has_layer() {
   let name = "my-repo/my-image";
-  let digest = "fakedigest";
+  let digest = Digest::sha256(b"fakedigest");
   let binary_digest = "binarydigest";
   let ep = format!("/v2/{name}/blobs/{digest}");
 
@@ -28,7 +39,7 @@ async fn test_blobs_has_layer() {
     .build()
     .unwrap();
 
-  let res = client.has_blob(name, digest).await.unwrap();
+  let res = client.has_blob(name, &digest).await.unwrap();
 
   mock.assert_async().await;
   assert!(res);
@@ -37,7 +48,7 @@ async fn test_blobs_has_layer() {
 #[tokio::test]
 async fn test_blobs_hasnot_layer() {
   let name = "my-repo/my-image";
-  let digest = "fakedigest";
+  let digest = Digest::sha256(b"fakedigest");
   let ep = format!("/v2/{name}/blobs/{digest}");
 
   let mut server = mockito::Server::new_async().await;
@@ -53,7 +64,7 @@ async fn test_blobs_hasnot_layer() {
     .build()
     .unwrap();
 
-  let res = client.has_blob(name, digest).await.unwrap();
+  let res = client.has_blob(name, &digest).await.unwrap();
 
   mock.assert_async().await;
   assert!(!res);
@@ -63,7 +74,7 @@ async fn test_blobs_hasnot_layer() {
 async fn get_blobs_succeeds_with_consistent_layer() -> Fallible<()> {
   let name = "my-repo/my-image";
   let blob = b"hello";
-  let digest = format!("sha256:{:x}", sha2::Sha256::digest(blob));
+  let digest = Digest::sha256(blob);
   let ep = format!("/v2/{name}/blobs/{digest}");
 
   let mut server = mockito::Server::new_async().await;
@@ -96,7 +107,7 @@ async fn get_blobs_fails_with_inconsistent_layer() -> Fallible<()> {
   let name = "my-repo/my-image";
   let blob = b"hello";
   let blob2 = b"hello2";
-  let digest = format!("sha256:{:x}", sha2::Sha256::digest(blob));
+  let digest = Digest::sha256(blob);
   let ep = format!("/v2/{name}/blobs/{digest}");
 
   let mut server = mockito::Server::new_async().await;
@@ -130,7 +141,7 @@ async fn get_blobs_fails_with_inconsistent_layer() -> Fallible<()> {
 async fn get_blobs_stream() -> Fallible<()> {
   let name = "my-repo/my-image";
   let blob = b"hello";
-  let digest = format!("sha256:{:x}", sha2::Sha256::digest(blob));
+  let digest = Digest::sha256(blob);
   let ep = format!("/v2/{name}/blobs/{digest}");
 
   let mut server = mockito::Server::new_async().await;
@@ -161,3 +172,295 @@ async fn get_blobs_stream() -> Fallible<()> {
 
   Ok(())
 }
+
+#[tokio::test]
+async fn get_blobs_bytes_stream_and_reader() -> Fallible<()> {
+  let name = "my-repo/my-image";
+  let blob = vec![b'x'; 100_000];
+  let digest = Digest::sha256(&blob);
+  let ep = format!("/v2/{name}/blobs/{digest}");
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+
+  let mock = server
+    .mock("GET", ep.as_str())
+    .with_status(200)
+    .with_body(&blob)
+    .expect(2)
+    .create();
+
+  let client = client(&addr);
+
+  let res = client.get_blob_response(name, &digest).await?;
+  let chunks = res.bytes_stream().try_collect::<Vec<_>>().await?;
+  assert_eq!(blob, chunks.concat());
+
+  let mut received = Vec::new();
+  let mut reader = client.get_blob_response(name, &digest).await?.reader();
+  reader.read_to_end(&mut received).await?;
+  assert_eq!(blob, received);
+
+  mock.assert_async().await;
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn get_blobs_reader_fails_with_tampered_blob() -> Fallible<()> {
+  let name = "my-repo/my-image";
+  let digest = Digest::sha256(b"hello");
+  let ep = format!("/v2/{name}/blobs/{digest}");
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+
+  let mock = server
+    .mock("GET", ep.as_str())
+    .with_status(200)
+    .with_body(b"jello")
+    .create();
+
+  let client = client(&addr);
+  let mut reader = client.get_blob_response(name, &digest).await?.reader();
+
+  // The content is readable, the final read reports the mismatch.
+  let mut received = [0; 5];
+  reader.read_exact(&mut received).await?;
+  assert_eq!(b"jello", &received);
+  let err = reader.read(&mut [0; 16]).await.unwrap_err();
+  let err = err.into_inner().unwrap().downcast::<Error>().unwrap();
+  assert!(matches!(*err, Error::ContentDigestParse(_)), "{err:?}");
+
+  mock.assert_async().await;
+
+  Ok(())
+}
+
+#[tokio::test]
+async fn get_blobs_from_descriptor_fails_with_wrong_size() -> Fallible<()> {
+  let name = "my-repo/my-image";
+  let blob = b"hello";
+  let digest = Digest::sha256(blob);
+  let ep = format!("/v2/{name}/blobs/{digest}");
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+
+  let mock = server
+    .mock("GET", ep.as_str())
+    .with_status(200)
+    .with_body(blob)
+    .expect(2)
+    .create();
+
+  let client = docker_registry::v2::Client::configure()
+    .registry(&addr)
+    .insecure_registry(true)
+    .username(None)
+    .password(None)
+    .build()
+    .unwrap();
+
+  let media_type = "application/vnd.oci.image.layer.v1.tar+gzip";
+
+  // Declared size larger than the served blob.
+  let descriptor = docker_registry::v2::manifest::Descriptor::new(media_type, digest.clone(), 6);
+  assert!(matches!(
+    client.get_blob_from_descriptor(name, &descriptor).await,
+    Err(docker_registry::errors::Error::BlobSize { expected: 6, got: 5 })
+  ));
+
+  // Declared size smaller than the advertised Content-Length.
+  let descriptor = docker_registry::v2::manifest::Descriptor::new(media_type, digest.clone(), 4);
+  assert!(matches!(
+    client.get_blob_response_from_descriptor(name, &descriptor).await,
+    Err(docker_registry::errors::Error::BlobSize { expected: 4, got: 5 })
+  ));
+
+  mock.assert_async().await;
+
+  Ok(())
+}
+
+static RANGE_BLOB: &[u8] = b"0123456789";
+
+#[tokio::test]
+async fn test_get_blob_range() {
+  let name = "my-repo/my-image";
+  let digest = Digest::sha256(RANGE_BLOB);
+  let ep = format!("/v2/{name}/blobs/{digest}");
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+  let client = client(&addr);
+
+  let mock = server
+    .mock("GET", ep.as_str())
+    .match_header("Range", "bytes=2-5")
+    .with_status(206)
+    .with_header("Content-Range", "bytes 2-5/10")
+    .with_body(&RANGE_BLOB[2..6])
+    .create();
+  assert_eq!(
+    b"2345",
+    client
+      .get_blob_range(name, &digest, 2, Some(5))
+      .await
+      .unwrap()
+      .as_slice()
+  );
+  mock.assert();
+
+  // The returned range must be the requested one.
+  let mock = server
+    .mock("GET", ep.as_str())
+    .match_header("Range", "bytes=6-")
+    .with_status(206)
+    .with_header("Content-Range", "bytes 0-9/10")
+    .with_body(RANGE_BLOB)
+    .create();
+  let res = client.get_blob_range(name, &digest, 6, None).await;
+  assert!(matches!(res, Err(Error::InvalidContentRange(_))), "{res:?}");
+  mock.assert();
+
+  // Registries which do not support ranges return the whole blob.
+  let mock = server
+    .mock("GET", ep.as_str())
+    .match_header("Range", "bytes=0-3")
+    .with_status(200)
+    .with_body(RANGE_BLOB)
+    .create();
+  let res = client.get_blob_range(name, &digest, 0, Some(3)).await;
+  assert!(matches!(res, Err(Error::UnexpectedHttpStatus(_))), "{res:?}");
+  mock.assert();
+}
+
+#[tokio::test]
+async fn test_download_blob_resumable() {
+  let name = "my-repo/my-image";
+  let descriptor = Descriptor::new("application/octet-stream", Digest::sha256(RANGE_BLOB), 10);
+  let ep = format!("/v2/{name}/blobs/{}", descriptor.digest);
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("blob");
+  let partial = dir.path().join("blob.partial");
+  fs::write(&partial, &RANGE_BLOB[..4]).unwrap();
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+  let mock = server
+    .mock("GET", ep.as_str())
+    .match_header("Range", "bytes=4-")
+    .with_status(206)
+    .with_header("Content-Range", "bytes 4-9/10")
+    .with_body(&RANGE_BLOB[4..])
+    .create();
+
+  client(&addr)
+    .download_blob_resumable(name, &descriptor, &path)
+    .await
+    .unwrap();
+  mock.assert();
+  assert_eq!(RANGE_BLOB, fs::read(&path).unwrap().as_slice());
+  assert!(!partial.exists());
+}
+
+#[tokio::test]
+async fn test_download_blob_resumable_range_ignored() {
+  let name = "my-repo/my-image";
+  let descriptor = Descriptor::new("application/octet-stream", Digest::sha256(RANGE_BLOB), 10);
+  let ep = format!("/v2/{name}/blobs/{}", descriptor.digest);
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("blob");
+  fs::write(dir.path().join("blob.partial"), b"0123").unwrap();
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+  let mock = server
+    .mock("GET", ep.as_str())
+    .with_status(200)
+    .with_body(RANGE_BLOB)
+    .create();
+
+  client(&addr)
+    .download_blob_resumable(name, &descriptor, &path)
+    .await
+    .unwrap();
+  mock.assert();
+  assert_eq!(RANGE_BLOB, fs::read(&path).unwrap().as_slice());
+}
+
+#[tokio::test]
+async fn test_download_blob_resumable_corrupted_partial() {
+  let name = "my-repo/my-image";
+  let descriptor = Descriptor::new("application/octet-stream", Digest::sha256(RANGE_BLOB), 10);
+  let ep = format!("/v2/{name}/blobs/{}", descriptor.digest);
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("blob");
+  let partial = dir.path().join("blob.partial");
+  fs::write(&partial, b"abcd").unwrap();
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+  let mock = server
+    .mock("GET", ep.as_str())
+    .match_header("Range", "bytes=4-")
+    .with_status(206)
+    .with_header("Content-Range", "bytes 4-9/10")
+    .with_body(&RANGE_BLOB[4..])
+    .create();
+
+  let res = client(&addr).download_blob_resumable(name, &descriptor, &path).await;
+  mock.assert();
+  assert!(matches!(res, Err(Error::ContentDigestParse(_))), "{res:?}");
+  assert!(!path.exists());
+  // The next attempt starts over.
+  assert_eq!(0, fs::metadata(&partial).unwrap().len());
+}
+
+#[tokio::test]
+async fn test_download_blob_to() {
+  let name = "my-repo/my-image";
+  let descriptor = Descriptor::new("application/octet-stream", Digest::sha256(RANGE_BLOB), 10);
+  let ep = format!("/v2/{name}/blobs/{}", descriptor.digest);
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("blob");
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+  let mock = server
+    .mock("GET", ep.as_str())
+    .with_status(200)
+    .with_body(RANGE_BLOB)
+    .create();
+
+  client(&addr).download_blob_to(name, &descriptor, &path).await.unwrap();
+  mock.assert();
+  assert_eq!(RANGE_BLOB, fs::read(&path).unwrap().as_slice());
+  assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+}
+
+#[tokio::test]
+async fn test_download_blob_to_fails_with_tampered_blob() {
+  let name = "my-repo/my-image";
+  let descriptor = Descriptor::new("application/octet-stream", Digest::sha256(RANGE_BLOB), 10);
+  let ep = format!("/v2/{name}/blobs/{}", descriptor.digest);
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("blob");
+  fs::write(&path, b"previous").unwrap();
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+  let mock = server
+    .mock("GET", ep.as_str())
+    .with_status(200)
+    .with_body(b"9876543210")
+    .create();
+
+  let res = client(&addr).download_blob_to(name, &descriptor, &path).await;
+  mock.assert();
+  assert!(matches!(res, Err(Error::ContentDigestParse(_))), "{res:?}");
+  // The existing file is untouched and the temporary file is removed.
+  assert_eq!(b"previous", fs::read(&path).unwrap().as_slice());
+  assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+}
@@ -0,0 +1,186 @@
+use std::{fs, time::Duration};
+
+use docker_registry::{
+  errors::Error,
+  v2::{BlobCache, Client, Digest, manifest::Manifest},
+};
+use futures::TryStreamExt;
+
+use super::configure;
+
+fn client(addr: &str, cache: &BlobCache) -> Client {
+  configure(addr).cache(Some(cache.clone())).build().unwrap()
+}
+
+#[tokio::test]
+async fn test_cache_blobs() {
+  let name = "repo";
+  let (blob, streamed) = (b"cached blob".as_slice(), b"streamed blob".as_slice());
+  let dir = tempfile::tempdir().unwrap();
+  let cache = BlobCache::new(dir.path()).unwrap();
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+  let mocks = [blob, streamed].map(|b| {
+    server
+      .mock("GET", format!("/v2/{name}/blobs/{}", Digest::sha256(b)).as_str())
+      .with_status(200)
+      .with_body(b)
+      .expect(1)
+      .create()
+  });
+  let client = client(&addr, &cache);
+
+  assert_eq!(blob, client.get_blob(name, &Digest::sha256(blob)).await.unwrap());
+  let chunks = client
+    .get_blob_stream(name, &Digest::sha256(streamed))
+    .await
+    .unwrap()
+    .try_concat()
+    .await
+    .unwrap();
+  assert_eq!(streamed, chunks);
+  assert_eq!((blob.len() + streamed.len()) as u64, cache.size().unwrap());
+
+  // Both are now served from the cache.
+  let resp = client.get_blob_response(name, &Digest::sha256(blob)).await.unwrap();
+  assert!(resp.is_cached());
+  assert_eq!(blob, resp.bytes().await.unwrap());
+  let chunks = client
+    .get_blob_stream(name, &Digest::sha256(streamed))
+    .await
+    .unwrap()
+    .try_concat()
+    .await
+    .unwrap();
+  assert_eq!(streamed, chunks);
+  for mock in mocks {
+    mock.assert();
+  }
+}
+
+#[tokio::test]
+async fn test_cache_manifest_by_digest() {
+  let name = "repo";
+  let config = br#"{"architecture":"amd64","os":"linux"}"#;
+  let manifest = format!(
+    r#"{{"schemaVersion":2,"mediaType":"application/vnd.oci.image.manifest.v1+json","config":{{"mediaType":"application/vnd.oci.image.config.v1+json","digest":"{}","size":{}}},"layers":[]}}"#,
+    Digest::sha256(config),
+    config.len()
+  );
+  let digest = Digest::sha256(manifest.as_bytes());
+  let dir = tempfile::tempdir().unwrap();
+  let cache = BlobCache::new(dir.path()).unwrap();
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+  let manifest_mock = server
+    .mock("GET", format!("/v2/{name}/manifests/{digest}").as_str())
+    .with_status(200)
+    .with_header("Content-Type", "application/vnd.oci.image.manifest.v1+json")
+    .with_body(&manifest)
+    .expect(1)
+    .create();
+  let config_mock = server
+    .mock("GET", format!("/v2/{name}/blobs/{}", Digest::sha256(config)).as_str())
+    .with_status(200)
+    .with_body(config)
+    .expect(1)
+    .create();
+  let client = client(&addr, &cache);
+
+  for _ in 0..2 {
+    match client.get_manifest_and_ref(name, &digest.to_string()).await.unwrap() {
+      (Manifest::S2(m), _) => assert_eq!("amd64", m.architecture()),
+      other => panic!("unexpected manifest: {other:?}"),
+    }
+  }
+  assert!(cache.contains(&digest));
+  manifest_mock.assert();
+  config_mock.assert();
+}
+
+#[tokio::test]
+async fn test_cache_lru_eviction() {
+  let name = "repo";
+  let blobs = [b"aaaaaaaaaa", b"bbbbbbbbbb", b"cccccccccc"];
+  let dir = tempfile::tempdir().unwrap();
+  let cache = BlobCache::new(dir.path()).unwrap().max_size(25);
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+  let _mocks = blobs.map(|b| {
+    server
+      .mock("GET", format!("/v2/{name}/blobs/{}", Digest::sha256(b)).as_str())
+      .with_status(200)
+      .with_body(b)
+      .create()
+  });
+  let client = client(&addr, &cache);
+
+  // Using the first blob again makes the second one the least recently used.
+  for i in [0, 1, 0, 2] {
+    client.get_blob(name, &Digest::sha256(blobs[i])).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(10)).await;
+  }
+
+  assert!(cache.contains(&Digest::sha256(blobs[0])));
+  assert!(!cache.contains(&Digest::sha256(blobs[1])));
+  assert!(cache.contains(&Digest::sha256(blobs[2])));
+  assert_eq!(20, cache.size().unwrap());
+}
+
+#[tokio::test]
+async fn test_cache_corrupted_entry() {
+  let name = "repo";
+  let blob = b"some blob";
+  let digest = Digest::sha256(blob);
+  let dir = tempfile::tempdir().unwrap();
+  let cache = BlobCache::new(dir.path()).unwrap();
+  fs::create_dir_all(cache.path(&digest).parent().unwrap()).unwrap();
+  fs::write(cache.path(&digest), b"other blob").unwrap();
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+  let mock = server
+    .mock("GET", format!("/v2/{name}/blobs/{digest}").as_str())
+    .with_status(200)
+    .with_body(blob)
+    .expect(1)
+    .create();
+  let client = client(&addr, &cache);
+
+  let res = client.get_blob(name, &digest).await;
+  assert!(matches!(res, Err(Error::ContentDigestParse(_))), "{res:?}");
+  assert!(!cache.contains(&digest));
+
+  assert_eq!(blob.as_slice(), client.get_blob(name, &digest).await.unwrap());
+  assert!(cache.contains(&digest));
+  mock.assert();
+}
+
+#[tokio::test]
+async fn test_cache_unreadable_entry() {
+  let name = "repo";
+  let manifest = r#"{"schemaVersion":2,"mediaType":"application/vnd.oci.image.index.v1+json","manifests":[]}"#;
+  let digest = Digest::sha256(manifest.as_bytes());
+  let dir = tempfile::tempdir().unwrap();
+  let cache = BlobCache::new(dir.path()).unwrap();
+  // A directory in place of the cached manifest cannot be read.
+  fs::create_dir_all(cache.path(&digest)).unwrap();
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+  let mock = server
+    .mock("GET", format!("/v2/{name}/manifests/{digest}").as_str())
+    .with_status(200)
+    .with_header("Content-Type", "application/vnd.oci.image.index.v1+json")
+    .with_body(manifest)
+    .expect(1)
+    .create();
+  let client = client(&addr, &cache);
+
+  let (manifest, _) = client.get_manifest_and_ref(name, &digest.to_string()).await.unwrap();
+  assert!(matches!(manifest, Manifest::ML(_)), "{manifest:?}");
+  mock.assert();
+}
@@ -0,0 +1,145 @@
+use base64::prelude::*;
+use docker_registry::{
+  reference::Reference,
+  v2::{
+    Digest,
+    cosign::{CosignError, CosignPublicKey},
+  },
+};
+use mockito::Matcher;
+use p256::{
+  ecdsa::{Signature, SigningKey, signature::Signer},
+  pkcs8::{EncodePublicKey, LineEnding},
+};
+
+static IMAGE: &str = "sha256:e692418e4cbaf90ca69d05a66403747baa33ee08806650b51fab815ad7fc331f";
+
+fn payload(digest: &str) -> Vec<u8> {
+  format!(
+    r#"{{"critical":{{"identity":{{"docker-reference":"localhost/repo"}},"image":{{"docker-manifest-digest":"{digest}"}},"type":"cosign container image signature"}},"optional":null}}"#
+  )
+  .into_bytes()
+}
+
+fn signature_manifest(payload: &[u8], signature: &str) -> String {
+  format!(
+    r#"{{
+      "schemaVersion": 2,
+      "mediaType": "application/vnd.oci.image.manifest.v1+json",
+      "config": {{
+        "mediaType": "application/vnd.oci.image.config.v1+json",
+        "size": 2,
+        "digest": "{}"
+      }},
+      "layers": [{{
+        "mediaType": "application/vnd.dev.cosign.simplesigning.v1+json",
+        "size": {},
+        "digest": "{}",
+        "annotations": {{ "dev.cosignproject.cosign/signature": "{signature}" }}
+      }}]
+    }}"#,
+    Digest::sha256(b"{}"),
+    payload.len(),
+    Digest::sha256(payload)
+  )
+}
+
+/// Serve a cosign signature of `signed` by `key` under the `.sig` tag of `IMAGE`.
+///
+/// The referrers API answers with `referrers_status`.
+async fn verify(
+  key: &SigningKey,
+  signed: &str,
+  verifier: &CosignPublicKey,
+  referrers_status: usize,
+) -> docker_registry::errors::Result<()> {
+  let name = "repo";
+  let image = Digest::try_from(IMAGE).unwrap();
+  let payload = payload(signed);
+  let signature: Signature = key.sign(&payload);
+  let signature = BASE64_STANDARD.encode(signature.to_der().as_bytes());
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+
+  let _sig_mock = server
+    .mock(
+      "GET",
+      format!("/v2/{name}/manifests/sha256-{}.sig", image.encoded()).as_str(),
+    )
+    .with_status(200)
+    .with_header("Content-Type", "application/vnd.oci.image.manifest.v1+json")
+    .with_body(signature_manifest(&payload, &signature))
+    .create();
+  let _payload_mock = server
+    .mock("GET", format!("/v2/{name}/blobs/{}", Digest::sha256(&payload)).as_str())
+    .with_status(200)
+    .with_body(&payload)
+    .create();
+  let _referrers_mock = server
+    .mock("GET", format!("/v2/{name}/referrers/{IMAGE}").as_str())
+    .match_query(Matcher::Any)
+    .with_status(referrers_status)
+    .create();
+  let _tag_mock = server
+    .mock(
+      "GET",
+      format!("/v2/{name}/manifests/sha256-{}", image.encoded()).as_str(),
+    )
+    .with_status(404)
+    .create();
+
+  let client = docker_registry::v2::Client::configure()
+    .registry(&addr)
+    .insecure_registry(true)
+    .username(None)
+    .password(None)
+    .build()
+    .unwrap();
+  let reference = Reference::new(Some(addr.clone()), name.to_string(), None);
+
+  let verified = client.verify_cosign_signatures(&reference, &image, verifier).await?;
+  assert_eq!(1, verified.len());
+  assert_eq!(image, verified[0].critical.image.docker_manifest_digest);
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_cosign_verify() {
+  let key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+  let pem = key.verifying_key().to_public_key_pem(LineEnding::LF).unwrap();
+  let verifier = CosignPublicKey::from_pem(&pem).unwrap();
+
+  verify(&key, IMAGE, &verifier, 404).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_cosign_verify_referrers_error() {
+  let key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+  let verifier = CosignPublicKey::from(*key.verifying_key());
+
+  // The signature tag is still checked when the referrers API fails.
+  verify(&key, IMAGE, &verifier, 500).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_cosign_verify_wrong_key() {
+  let key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+  let other = SigningKey::from_slice(&[8u8; 32]).unwrap();
+  let verifier = CosignPublicKey::from(*other.verifying_key());
+
+  let res = verify(&key, IMAGE, &verifier, 404).await;
+  assert!(matches!(
+    res,
+    Err(docker_registry::errors::Error::Cosign(CosignError::NoValidSignature(1)))
+  ));
+}
+
+#[tokio::test]
+async fn test_cosign_verify_other_image() {
+  let key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+  let verifier = CosignPublicKey::from(*key.verifying_key());
+  let other = Digest::sha256(b"other image").to_string();
+
+  assert!(verify(&key, &other, &verifier, 404).await.is_err());
+}
@@ -0,0 +1,84 @@
+use std::path::PathBuf;
+
+use docker_registry::{
+  mediatypes::MediaTypes,
+  v2::{Digest, diff::FileChange},
+};
+
+use super::{client, descriptor};
+use crate::common::{gzip, layer_tar};
+
+/// A gzip-compressed layer with the given (empty) files.
+fn layer(paths: &[&str]) -> Vec<u8> {
+  let files: Vec<_> = paths.iter().map(|path| (*path, b"".as_slice())).collect();
+  gzip(&layer_tar(&files))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_diff_files() {
+  let name = "repo";
+  let base = layer(&["etc/hostname", "etc/passwd", "var/cache/x"]);
+  let removed = layer(&["app/bin", "app/old.conf", "etc/motd"]);
+  let added = [
+    layer(&["app/bin", "app/new.conf", "etc/.wh.motd", "tmp/scratch"]),
+    layer(&["./tmp/.wh.scratch", "var/cache/.wh..wh..opq", "etc/.wh.hostname"]),
+  ];
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+  // Each layer is streamed once, even when shared by both images.
+  let mocks = [&base, &removed]
+    .into_iter()
+    .chain(&added)
+    .map(|blob| {
+      server
+        .mock("GET", format!("/v2/{name}/blobs/{}", Digest::sha256(blob)).as_str())
+        .with_status(200)
+        .with_body(blob)
+        .expect(1)
+        .create()
+    })
+    .collect::<Vec<_>>();
+
+  let old = [&base, &removed].map(|blob| descriptor(MediaTypes::OciImageLayerTgz, blob));
+  let new = [&base, &added[0], &added[1]].map(|blob| descriptor(MediaTypes::OciImageLayerTgz, blob));
+  let changes = client(&addr).diff_files(name, &old, &new).await.unwrap();
+  for mock in mocks {
+    mock.assert();
+  }
+
+  // Files still provided by the base layer, and their directories, are unchanged.
+  let path = PathBuf::from;
+  assert_eq!(
+    vec![
+      FileChange::Modified(path("app/bin")),
+      FileChange::Added(path("app/new.conf")),
+      FileChange::Deleted(path("app/old.conf")),
+      FileChange::Deleted(path("etc/hostname")),
+      FileChange::Deleted(path("etc/motd")),
+      FileChange::Added(path("tmp")),
+      FileChange::Deleted(path("var/cache/x")),
+    ],
+    changes
+  );
+}
+
+#[tokio::test]
+async fn test_diff_files_digest_mismatch() {
+  let name = "repo";
+  let blob = layer(&["etc/motd"]);
+  let mut tampered = descriptor(MediaTypes::OciImageLayerTgz, &blob);
+  tampered.digest = Digest::sha256(b"other");
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+  let mock = server
+    .mock("GET", format!("/v2/{name}/blobs/{}", tampered.digest).as_str())
+    .with_status(200)
+    .with_body(&blob)
+    .create();
+
+  let res = client(&addr).diff_files(name, &[], &[tampered]).await;
+  mock.assert();
+  assert!(res.is_err());
+}
@@ -0,0 +1,201 @@
+use std::path::Path;
+
+use docker_registry::{
+  errors::Error,
+  mediatypes::MediaTypes,
+  v2::{
+    Digest,
+    files::{FileError, FileKind},
+    manifest::ManifestSchema2Spec,
+  },
+};
+
+use super::{client, descriptor};
+use crate::common::{append, gzip};
+
+const NAME: &str = "repo";
+
+/// A gzipped layer with the given entries: files, or links when `link` is set.
+fn layer(entries: &[(tar::EntryType, &str, &[u8], &str)]) -> Vec<u8> {
+  let mut builder = tar::Builder::new(Vec::new());
+  for (entry_type, path, content, link) in entries {
+    append(&mut builder, *entry_type, path, content, link, 0o644);
+  }
+  gzip(&builder.into_inner().unwrap())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_file_index_and_extract() {
+  use tar::EntryType::{Link, Regular, Symlink};
+
+  let base = layer(&[
+    (Regular, "usr/lib/os-release", b"ID=base\n", ""),
+    (Symlink, "etc/os-release", b"", "../usr/lib/os-release"),
+    (Regular, "etc/motd", b"hello\n", ""),
+    (Regular, "bin/busybox", b"busybox", ""),
+    (Link, "bin/sh", b"", "bin/busybox"),
+    (Regular, "opt/old/x", b"x", ""),
+  ]);
+  let upper = layer(&[
+    (Regular, "./usr/lib/os-release", b"ID=upper\n", ""),
+    (Regular, "etc/.wh.motd", b"", ""),
+    (Regular, "opt/new", b"new", ""),
+    (Regular, "opt/.wh..wh..opq", b"", ""),
+    (Symlink, "lib", b"", "/usr/lib"),
+  ]);
+  let config = br#"{"architecture":"amd64","os":"linux"}"#;
+  let image = ManifestSchema2Spec::builder()
+    .config(descriptor(MediaTypes::OciImageConfig, config))
+    .layers([&base, &upper].map(|l| descriptor(MediaTypes::OciImageLayerTgz, l)))
+    .build()
+    .unwrap()
+    .encode()
+    .unwrap();
+
+  let mut server = mockito::Server::new_async().await;
+  let _manifest = server
+    .mock("GET", format!("/v2/{NAME}/manifests/latest").as_str())
+    .with_status(200)
+    .with_header("Content-Type", &image.media_type)
+    .with_body(&image.bytes)
+    .create();
+  let blob_mock = |server: &mut mockito::Server, blob: &[u8], hits: usize| {
+    server
+      .mock("GET", format!("/v2/{NAME}/blobs/{}", Digest::sha256(blob)).as_str())
+      .with_status(200)
+      .with_body(blob)
+      .expect(hits)
+      .create()
+  };
+  let config_mock = blob_mock(&mut server, config, 1);
+  // Each layer is read once to build the index, then only when it provides an extracted file.
+  let base_mock = blob_mock(&mut server, &base, 2);
+  let upper_mock = blob_mock(&mut server, &upper, 4);
+  let client = client(&server.host_with_port());
+
+  let manifest = client.get_manifest(NAME, "latest").await.unwrap();
+  let index = client.get_file_index(NAME, &manifest).await.unwrap();
+  assert_eq!(2, index.layers().len());
+
+  let names = |dir: &str| -> Vec<String> {
+    index
+      .list(dir)
+      .unwrap()
+      .iter()
+      .map(|e| e.path.to_string_lossy().into_owned())
+      .collect()
+  };
+  assert_eq!(["bin", "etc", "lib", "opt", "usr"].as_slice(), names("/"));
+  assert_eq!(["etc/os-release"].as_slice(), names("etc"));
+  assert_eq!(["opt/new"].as_slice(), names("/opt/"));
+  assert_eq!(["usr/lib/os-release"].as_slice(), names("lib"));
+  assert!(index.list("bin/busybox").is_none());
+  assert!(index.stat("opt/old/x").is_none());
+  assert!(index.stat("etc/motd").is_none());
+
+  let os_release = index.stat("/etc/os-release").unwrap();
+  assert_eq!(FileKind::Symlink, os_release.kind);
+  assert_eq!(
+    Some(Path::new("../usr/lib/os-release")),
+    os_release.link_name.as_deref()
+  );
+  let resolved = index.resolve("/etc/os-release").unwrap();
+  assert_eq!((FileKind::File, 9, 1), (resolved.kind, resolved.size, resolved.layer));
+  assert_eq!(FileKind::Directory, index.stat("usr").unwrap().kind);
+
+  for path in ["/etc/os-release", "lib/os-release", "usr/lib/os-release"] {
+    assert_eq!(
+      b"ID=upper\n".as_slice(),
+      client.extract_file(NAME, &index, path).await.unwrap()
+    );
+  }
+  assert_eq!(
+    b"busybox".as_slice(),
+    client.extract_file(NAME, &index, "bin/sh").await.unwrap()
+  );
+
+  let res = client.extract_file(NAME, &index, "etc/motd").await;
+  assert!(matches!(res, Err(Error::File(FileError::NotFound(_)))), "{res:?}");
+  let res = client.extract_file(NAME, &index, "etc").await;
+  assert!(matches!(res, Err(Error::File(FileError::NotAFile(_)))), "{res:?}");
+
+  config_mock.assert();
+  base_mock.assert();
+  upper_mock.assert();
+}
+
+/// Serve the image `latest` made of `layers`.
+fn serve_image(server: &mut mockito::Server, layers: &[&[u8]]) -> Vec<mockito::Mock> {
+  let config = br#"{"architecture":"amd64","os":"linux"}"#;
+  let image = ManifestSchema2Spec::builder()
+    .config(descriptor(MediaTypes::OciImageConfig, config))
+    .layers(layers.iter().map(|l| descriptor(MediaTypes::OciImageLayerTgz, l)))
+    .build()
+    .unwrap()
+    .encode()
+    .unwrap();
+
+  let mut mocks = vec![
+    server
+      .mock("GET", format!("/v2/{NAME}/manifests/latest").as_str())
+      .with_status(200)
+      .with_header("Content-Type", &image.media_type)
+      .with_body(&image.bytes)
+      .create(),
+  ];
+  for blob in layers.iter().copied().chain([config.as_slice()]) {
+    mocks.push(
+      server
+        .mock("GET", format!("/v2/{NAME}/blobs/{}", Digest::sha256(blob)).as_str())
+        .with_status(200)
+        .with_body(blob)
+        .create(),
+    );
+  }
+  mocks
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_file_index_through_symlinked_parent() {
+  use tar::EntryType::{Regular, Symlink};
+
+  let base = layer(&[(Regular, "usr/lib/a", b"a", ""), (Symlink, "lib", b"", "usr/lib")]);
+  // Written to usr/lib/x when unpacked.
+  let upper = layer(&[(Regular, "lib/x", b"x", ""), (Regular, "lib/.wh.a", b"", "")]);
+  let mut server = mockito::Server::new_async().await;
+  let _mocks = serve_image(&mut server, &[&base, &upper]);
+  let client = client(&server.host_with_port());
+
+  let manifest = client.get_manifest(NAME, "latest").await.unwrap();
+  let index = client.get_file_index(NAME, &manifest).await.unwrap();
+
+  assert_eq!(FileKind::Symlink, index.stat("lib").unwrap().kind);
+  assert!(index.stat("usr/lib/a").is_none());
+  assert_eq!(1, index.stat("usr/lib/x").unwrap().layer);
+  assert_eq!(Path::new("usr/lib/x"), index.resolve("lib/x").unwrap().path);
+  assert_eq!(
+    b"x".as_slice(),
+    client.extract_file(NAME, &index, "lib/x").await.unwrap()
+  );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_extract_file_repeated_in_layer() {
+  use tar::EntryType::Regular;
+
+  let base = layer(&[
+    (Regular, "etc/motd", b"first\n", ""),
+    (Regular, "etc/motd", b"second\n", ""),
+  ]);
+  let mut server = mockito::Server::new_async().await;
+  let _mocks = serve_image(&mut server, &[&base]);
+  let client = client(&server.host_with_port());
+
+  let manifest = client.get_manifest(NAME, "latest").await.unwrap();
+  let index = client.get_file_index(NAME, &manifest).await.unwrap();
+  assert_eq!(7, index.stat("etc/motd").unwrap().size);
+  assert_eq!(
+    b"second\n".as_slice(),
+    client.extract_file(NAME, &index, "etc/motd").await.unwrap()
+  );
+}
@@ -0,0 +1,99 @@
+use docker_registry::{errors::Error, v2::Digest};
+
+static CONFIG: &[u8] = br#"{"architecture":"amd64","config":{"Labels":{"foo":"bar"}}}"#;
+
+fn manifest_body(config_digest: &Digest, config_size: usize) -> String {
+  format!(
+    r#"{{
+      "schemaVersion": 2,
+      "mediaType": "application/vnd.oci.image.manifest.v1+json",
+      "config": {{
+        "mediaType": "application/vnd.oci.image.config.v1+json",
+        "size": {config_size},
+        "digest": "{config_digest}"
+      }},
+      "layers": []
+    }}"#
+  )
+}
+
+async fn get_manifest_with_config(
+  manifest: String,
+  config: &[u8],
+  content_digest: Option<&str>,
+) -> docker_registry::errors::Result<Option<Digest>> {
+  let name = "my-repo/my-image";
+  let config_digest = Digest::sha256(CONFIG);
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+
+  let mut manifest_mock = server
+    .mock("GET", format!("/v2/{name}/manifests/latest").as_str())
+    .with_status(200)
+    .with_header("Content-Type", "application/vnd.oci.image.manifest.v1+json")
+    .with_body(manifest);
+  if let Some(content_digest) = content_digest {
+    manifest_mock = manifest_mock.with_header("Docker-Content-Digest", content_digest);
+  }
+  let _manifest_mock = manifest_mock.create();
+  let _config_mock = server
+    .mock("GET", format!("/v2/{name}/blobs/{config_digest}").as_str())
+    .with_status(200)
+    .with_body(config)
+    .create();
+
+  let client = docker_registry::v2::Client::configure()
+    .registry(&addr)
+    .insecure_registry(true)
+    .username(None)
+    .password(None)
+    .build()
+    .unwrap();
+
+  match client.get_manifest_and_ref(name, "latest").await? {
+    (docker_registry::v2::manifest::Manifest::S2(m), content_digest) => {
+      assert_eq!(CONFIG, m.config_bytes());
+      assert_eq!(config_digest, Digest::sha256(m.config_bytes()));
+      assert_eq!(
+        Some("bar"),
+        m.labels().unwrap_or_default().get("foo").map(String::as_str)
+      );
+      Ok(content_digest)
+    }
+    other => panic!("unexpected manifest type: {other:?}"),
+  }
+}
+
+#[tokio::test]
+async fn get_manifest_verifies_config_blob() {
+  let manifest = manifest_body(&Digest::sha256(CONFIG), CONFIG.len());
+  get_manifest_with_config(manifest, CONFIG, None).await.unwrap();
+}
+
+#[tokio::test]
+async fn get_manifest_fails_with_tampered_config_blob() {
+  let tampered = br#"{"architecture":"arm64","config":{"Labels":{"foo":"baz"}}}"#;
+  let manifest = manifest_body(&Digest::sha256(CONFIG), CONFIG.len());
+
+  let res = get_manifest_with_config(manifest, tampered, None).await;
+  assert!(matches!(res, Err(Error::ContentDigestParse(_))), "{res:?}");
+}
+
+#[tokio::test]
+async fn get_manifest_fails_with_wrong_config_size() {
+  let manifest = manifest_body(&Digest::sha256(CONFIG), CONFIG.len() - 1);
+
+  let res = get_manifest_with_config(manifest, CONFIG, None).await;
+  assert!(matches!(res, Err(Error::BlobSize { .. })), "{res:?}");
+}
+
+#[tokio::test]
+async fn get_manifest_ignores_malformed_content_digest() {
+  let manifest = manifest_body(&Digest::sha256(CONFIG), CONFIG.len());
+
+  let content_digest = get_manifest_with_config(manifest, CONFIG, Some("sha256:nothex"))
+    .await
+    .unwrap();
+  assert_eq!(None, content_digest);
+}
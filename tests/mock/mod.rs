@@ -1,6 +1,40 @@
 mod api_version;
+mod artifacts;
+mod attestations;
 mod base_client;
 mod blobs_download;
+mod cache;
 mod catalog;
+mod cosign;
+mod diff;
+mod files;
+mod manifest_config;
+mod pull;
+mod referrers;
+mod size;
 mod tags_dockerv2;
 mod tags_quay;
+
+use docker_registry::{
+  mediatypes::MediaTypes,
+  v2::{Client, Config, Digest, manifest::Descriptor},
+};
+
+/// Configuration of a client of the mock registry at `addr`.
+fn configure(addr: &str) -> Config {
+  Client::configure()
+    .registry(addr)
+    .insecure_registry(true)
+    .username(None)
+    .password(None)
+}
+
+/// Client of the mock registry at `addr`.
+fn client(addr: &str) -> Client {
+  configure(addr).build().unwrap()
+}
+
+/// Descriptor of `blob` with the given media type.
+fn descriptor(media_type: MediaTypes, blob: &[u8]) -> Descriptor {
+  Descriptor::new(&media_type.to_string(), Digest::sha256(blob), blob.len() as u64)
+}
@@ -0,0 +1,323 @@
+use std::sync::Mutex;
+
+use docker_registry::{
+  errors::Error,
+  mediatypes::MediaTypes,
+  render::UnpackOptions,
+  v2::{
+    Digest,
+    manifest::{Manifest, ManifestError, ManifestList, ManifestSchema2Spec, Platform},
+    pull::{PullEvent, PullOptions},
+  },
+};
+
+use super::{client, descriptor};
+use crate::common::{gzip, layer_tar};
+
+const NAME: &str = "repo";
+
+/// Serve an image with `layers` and fetch its manifest; layers have to be mocked separately.
+async fn image(server: &mut mockito::Server, layers: &[&[u8]]) -> Manifest {
+  let diff_ids = layers
+    .iter()
+    .map(|l| format!("\"{}\"", Digest::sha256(l)))
+    .collect::<Vec<_>>();
+  let config = format!(
+    r#"{{"architecture":"amd64","os":"linux","rootfs":{{"type":"layers","diff_ids":[{}]}}}}"#,
+    diff_ids.join(",")
+  );
+  let manifest = ManifestSchema2Spec::builder()
+    .config(descriptor(MediaTypes::OciImageConfig, config.as_bytes()))
+    .layers(layers.iter().map(|l| descriptor(MediaTypes::OciImageLayerTgz, l)))
+    .build()
+    .unwrap()
+    .encode()
+    .unwrap();
+
+  server
+    .mock("GET", format!("/v2/{NAME}/manifests/latest").as_str())
+    .with_status(200)
+    .with_header("Content-Type", &manifest.media_type)
+    .with_body(&manifest.bytes)
+    .create();
+  server
+    .mock(
+      "GET",
+      format!("/v2/{NAME}/blobs/{}", Digest::sha256(config.as_bytes())).as_str(),
+    )
+    .with_status(200)
+    .with_body(&config)
+    .create();
+  client(&server.host_with_port())
+    .get_manifest(NAME, "latest")
+    .await
+    .unwrap()
+}
+
+fn layer_mock(server: &mut mockito::Server, layer: &[u8], body: &[u8]) -> mockito::Mock {
+  server
+    .mock("GET", format!("/v2/{NAME}/blobs/{}", Digest::sha256(layer)).as_str())
+    .with_status(200)
+    .with_body(body)
+    .expect(1)
+    .create()
+}
+
+#[tokio::test]
+async fn test_pull_layers() {
+  let layers: [&[u8]; 3] = [&[b'a'; 1000], &[b'b'; 10], &[b'c'; 200_000]];
+  let mut server = mockito::Server::new_async().await;
+  let manifest = image(&mut server, &layers).await;
+  let mocks = layers.map(|l| layer_mock(&mut server, l, l));
+
+  let events = Mutex::new(Vec::new());
+  let options = PullOptions::default().concurrency(2);
+  let blobs = client(&server.host_with_port())
+    .pull_layers(NAME, &manifest, &options, |event| events.lock().unwrap().push(event))
+    .await
+    .unwrap();
+  for mock in mocks {
+    mock.assert();
+  }
+
+  assert_eq!(3, blobs.len());
+  for (blob, layer) in blobs.iter().zip(layers) {
+    assert_eq!(layer, blob.bytes);
+    assert_eq!(Some(Digest::sha256(layer)), blob.diff_id);
+    assert_eq!(Some(MediaTypes::OciImageLayerTgz.to_string()), blob.media_type);
+  }
+
+  let events = events.into_inner().unwrap();
+  for (index, layer) in layers.iter().enumerate() {
+    let digest = Digest::sha256(layer);
+    let total = Some(layer.len() as u64);
+    let events: Vec<_> = events
+      .iter()
+      .filter(|e| match e {
+        PullEvent::Started { index: i, .. }
+        | PullEvent::Received { index: i, .. }
+        | PullEvent::Verified { index: i, .. }
+        | PullEvent::Retried { index: i, .. }
+        | PullEvent::Failed { index: i, .. } => *i == index,
+      })
+      .collect();
+    assert_eq!(
+      &PullEvent::Started {
+        index,
+        digest: digest.clone(),
+        total
+      },
+      events[0]
+    );
+    assert_eq!(
+      &PullEvent::Received {
+        index,
+        digest: digest.clone(),
+        received: layer.len() as u64,
+        total
+      },
+      events[events.len() - 2]
+    );
+    assert_eq!(&PullEvent::Verified { index, digest }, events[events.len() - 1]);
+  }
+}
+
+#[tokio::test]
+async fn test_pull_layers_retry() {
+  let layer = b"some layer".as_slice();
+  let mut server = mockito::Server::new_async().await;
+  let manifest = image(&mut server, &[layer]).await;
+  // The first response is corrupted, the retry gets the right content.
+  let corrupted = layer_mock(&mut server, layer, b"some other");
+  let valid = layer_mock(&mut server, layer, layer);
+
+  let events = Mutex::new(Vec::new());
+  let blobs = client(&server.host_with_port())
+    .pull_layers(NAME, &manifest, &PullOptions::default(), |event| {
+      events.lock().unwrap().push(event)
+    })
+    .await
+    .unwrap();
+  corrupted.assert();
+  valid.assert();
+
+  assert_eq!(layer, blobs[0].bytes);
+  let events = events.into_inner().unwrap();
+  assert!(
+    matches!(
+      &events[..],
+      [
+        PullEvent::Started { .. },
+        PullEvent::Received { .. },
+        PullEvent::Retried { attempt: 1, .. },
+        PullEvent::Started { .. },
+        PullEvent::Received { .. },
+        PullEvent::Verified { .. },
+      ]
+    ),
+    "{events:?}"
+  );
+}
+
+#[tokio::test]
+async fn test_pull_layers_failed() {
+  let layers: [&[u8]; 2] = [b"first layer", b"missing layer"];
+  let mut server = mockito::Server::new_async().await;
+  let manifest = image(&mut server, &layers).await;
+  let _first = server
+    .mock(
+      "GET",
+      format!("/v2/{NAME}/blobs/{}", Digest::sha256(layers[0])).as_str(),
+    )
+    .with_status(200)
+    .with_body(layers[0])
+    .create();
+  // Missing blobs are not retried.
+  let missing = server
+    .mock(
+      "GET",
+      format!("/v2/{NAME}/blobs/{}", Digest::sha256(layers[1])).as_str(),
+    )
+    .with_status(404)
+    .with_header("Content-Type", "application/json")
+    .with_body(r#"{"errors":[{"code":"BLOB_UNKNOWN","message":"blob unknown to registry"}]}"#)
+    .expect(1)
+    .create();
+
+  let events = Mutex::new(Vec::new());
+  let res = client(&server.host_with_port())
+    .pull_layers(NAME, &manifest, &PullOptions::default(), |event| {
+      events.lock().unwrap().push(event)
+    })
+    .await;
+  missing.assert();
+
+  assert!(matches!(res, Err(Error::Api(_))), "{res:?}");
+  let events = events.into_inner().unwrap();
+  assert!(
+    events.iter().any(|e| matches!(e, PullEvent::Failed { index: 1, .. })),
+    "{events:?}"
+  );
+  assert!(!events.iter().any(|e| matches!(e, PullEvent::Retried { .. })));
+}
+
+#[tokio::test]
+async fn test_pull_layers_to() {
+  let layers: [&[u8]; 2] = [b"first layer", b"second layer"];
+  let mut server = mockito::Server::new_async().await;
+  let manifest = image(&mut server, &layers).await;
+  let _mocks = layers.map(|l| layer_mock(&mut server, l, l));
+  let dir = tempfile::tempdir().unwrap();
+
+  let paths = client(&server.host_with_port())
+    .pull_layers_to(NAME, &manifest, dir.path(), &PullOptions::default(), |_| {})
+    .await
+    .unwrap();
+
+  assert_eq!(2, paths.len());
+  for (path, layer) in paths.iter().zip(layers) {
+    assert_eq!(&dir.path().join(Digest::sha256(layer).encoded()), path);
+    assert_eq!(layer, std::fs::read(path).unwrap());
+  }
+  assert_eq!(2, std::fs::read_dir(dir.path()).unwrap().count());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_pull_and_unpack() {
+  let base = layer_tar(&[("etc/os-release", b"ID=test\n"), ("etc/motd", b"hello\n")]);
+  let app = layer_tar(&[("etc/.wh.motd", b""), ("app/bin", b"#!/bin/sh\n")]);
+  let layers = [gzip(&base), gzip(&app)];
+  let config = format!(
+    r#"{{"architecture":"arm64","os":"linux","rootfs":{{"type":"layers","diff_ids":["{}","{}"]}}}}"#,
+    Digest::sha256(&base),
+    Digest::sha256(&app)
+  );
+  let image = ManifestSchema2Spec::builder()
+    .config(descriptor(MediaTypes::OciImageConfig, config.as_bytes()))
+    .layers(layers.iter().map(|l| descriptor(MediaTypes::OciImageLayerTgz, l)))
+    .build()
+    .unwrap()
+    .encode()
+    .unwrap();
+  let platform = |architecture: &str| Platform {
+    architecture: architecture.to_string(),
+    os: "linux".to_string(),
+    ..Default::default()
+  };
+  // Only the arm64 image is served.
+  let mut amd64 = image.descriptor();
+  amd64.digest = Digest::sha256(b"amd64 image");
+  amd64.platform = Some(platform("amd64"));
+  let mut arm64 = image.descriptor();
+  arm64.platform = Some(platform("arm64"));
+  let index = ManifestList::builder()
+    .manifests([amd64, arm64])
+    .build()
+    .unwrap()
+    .encode()
+    .unwrap();
+
+  let mut server = mockito::Server::new_async().await;
+  let mut mocks = Vec::new();
+  for (reference, manifest) in [("latest".to_string(), &index), (image.digest.to_string(), &image)] {
+    mocks.push(
+      server
+        .mock("GET", format!("/v2/{NAME}/manifests/{reference}").as_str())
+        .with_status(200)
+        .with_header("Content-Type", &manifest.media_type)
+        .with_body(&manifest.bytes)
+        .create(),
+    );
+  }
+  for blob in [config.as_bytes(), &layers[0], &layers[1]] {
+    mocks.push(layer_mock(&mut server, blob, blob));
+  }
+  let dir = tempfile::tempdir().unwrap();
+
+  client(&server.host_with_port())
+    .pull_and_unpack(
+      NAME,
+      "latest",
+      dir.path(),
+      Some(&platform("arm64")),
+      &UnpackOptions::default(),
+    )
+    .await
+    .unwrap();
+  for mock in mocks {
+    mock.assert();
+  }
+
+  assert_eq!(
+    b"ID=test\n".as_slice(),
+    std::fs::read(dir.path().join("etc/os-release")).unwrap()
+  );
+  assert!(!dir.path().join("etc/motd").exists());
+  assert!(dir.path().join("app/bin").exists());
+
+  let res = client(&server.host_with_port())
+    .pull_and_unpack(
+      NAME,
+      "latest",
+      dir.path(),
+      Some(&platform("s390x")),
+      &UnpackOptions::default(),
+    )
+    .await;
+  assert!(
+    matches!(res, Err(Error::Manifest(ManifestError::PlatformNotFound(ref p))) if p == "linux/s390x"),
+    "{res:?}"
+  );
+
+  let empty = ManifestList::builder().build().unwrap().encode().unwrap();
+  let _mock = server
+    .mock("GET", format!("/v2/{NAME}/manifests/empty").as_str())
+    .with_status(200)
+    .with_header("Content-Type", &empty.media_type)
+    .with_body(&empty.bytes)
+    .create();
+  let res = client(&server.host_with_port())
+    .pull_and_unpack(NAME, "empty", dir.path(), None, &UnpackOptions::default())
+    .await;
+  assert!(matches!(res, Err(Error::Manifest(ManifestError::NoImage))), "{res:?}");
+}
@@ -0,0 +1,157 @@
+use docker_registry::v2::Digest;
+use mockito::Matcher;
+
+use super::client;
+
+static SUBJECT: &str = "sha256:e692418e4cbaf90ca69d05a66403747baa33ee08806650b51fab815ad7fc331f";
+static SIGNATURE: &str = "application/vnd.example.signature";
+static SBOM: &str = "application/vnd.example.sbom";
+
+fn index_body(artifact_types: &[&str]) -> String {
+  let manifests = artifact_types
+    .iter()
+    .map(|artifact_type| {
+      format!(
+        r#"{{
+          "mediaType": "application/vnd.oci.image.manifest.v1+json",
+          "size": 123,
+          "digest": "{}",
+          "artifactType": "{artifact_type}"
+        }}"#,
+        Digest::sha256(artifact_type.as_bytes())
+      )
+    })
+    .collect::<Vec<_>>()
+    .join(",");
+  format!(
+    r#"{{
+      "schemaVersion": 2,
+      "mediaType": "application/vnd.oci.image.index.v1+json",
+      "manifests": [{manifests}]
+    }}"#
+  )
+}
+
+#[tokio::test]
+async fn test_referrers_paginate() {
+  let name = "repo";
+  let subject = Digest::try_from(SUBJECT).unwrap();
+  let ep = format!("/v2/{name}/referrers/{SUBJECT}");
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+
+  let mock1 = server
+    .mock("GET", ep.as_str())
+    .match_query(Matcher::UrlEncoded("artifactType".into(), SIGNATURE.into()))
+    .with_status(200)
+    .with_header("Content-Type", "application/vnd.oci.image.index.v1+json")
+    .with_header("OCI-Filters-Applied", "artifactType")
+    .with_header(
+      "Link",
+      &format!(r#"<{ep}?artifactType={SIGNATURE}&last=1>; rel="next""#),
+    )
+    .with_body(index_body(&[SIGNATURE]))
+    .create();
+  let mock2 = server
+    .mock("GET", ep.as_str())
+    .match_query(Matcher::UrlEncoded("last".into(), "1".into()))
+    .with_status(200)
+    .with_header("Content-Type", "application/vnd.oci.image.index.v1+json")
+    .with_header("OCI-Filters-Applied", "artifactType")
+    .with_body(index_body(&[SIGNATURE]))
+    .create();
+
+  let referrers = client(&addr)
+    .get_referrers(name, &subject, Some(SIGNATURE))
+    .await
+    .unwrap();
+
+  mock1.assert();
+  mock2.assert();
+  assert!(referrers.filter_applied);
+  assert!(!referrers.tag_fallback);
+  assert_eq!(2, referrers.index.manifests.len());
+}
+
+#[tokio::test]
+async fn test_referrers_filter_locally() {
+  let name = "repo";
+  let subject = Digest::try_from(SUBJECT).unwrap();
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+
+  let mock = server
+    .mock("GET", format!("/v2/{name}/referrers/{SUBJECT}").as_str())
+    .match_query(Matcher::Any)
+    .with_status(200)
+    .with_header("Content-Type", "application/vnd.oci.image.index.v1+json")
+    .with_body(index_body(&[SIGNATURE, SBOM]))
+    .create();
+
+  let referrers = client(&addr).get_referrers(name, &subject, Some(SBOM)).await.unwrap();
+
+  mock.assert();
+  assert!(!referrers.filter_applied);
+  assert_eq!(1, referrers.index.manifests.len());
+  assert_eq!(Some(SBOM), referrers.index.manifests[0].artifact_type.as_deref());
+}
+
+#[tokio::test]
+async fn test_referrers_tag_fallback() {
+  let name = "repo";
+  let subject = Digest::try_from(SUBJECT).unwrap();
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+
+  let api_mock = server
+    .mock("GET", format!("/v2/{name}/referrers/{SUBJECT}").as_str())
+    .with_status(404)
+    .create();
+  let tag_mock = server
+    .mock(
+      "GET",
+      format!("/v2/{name}/manifests/sha256-{}", subject.encoded()).as_str(),
+    )
+    .with_status(200)
+    .with_header("Content-Type", "application/vnd.oci.image.index.v1+json")
+    .with_body(index_body(&[SIGNATURE, SBOM]))
+    .create();
+
+  let referrers = client(&addr).get_referrers(name, &subject, None).await.unwrap();
+
+  api_mock.assert();
+  tag_mock.assert();
+  assert!(referrers.tag_fallback);
+  assert_eq!(2, referrers.index.manifests.len());
+}
+
+#[tokio::test]
+async fn test_referrers_tag_fallback_missing() {
+  let name = "repo";
+  let subject = Digest::try_from(SUBJECT).unwrap();
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+
+  let _api_mock = server
+    .mock("GET", format!("/v2/{name}/referrers/{SUBJECT}").as_str())
+    .match_query(Matcher::Any)
+    .with_status(404)
+    .create();
+  let _tag_mock = server
+    .mock(
+      "GET",
+      format!("/v2/{name}/manifests/sha256-{}", subject.encoded()).as_str(),
+    )
+    .with_status(404)
+    .create();
+
+  let referrers = client(&addr).get_referrers(name, &subject, Some(SBOM)).await.unwrap();
+
+  assert!(referrers.tag_fallback);
+  assert!(referrers.index.manifests.is_empty());
+  assert_eq!("application/vnd.oci.image.index.v1+json", referrers.index.media_type());
+}
@@ -0,0 +1,146 @@
+use docker_registry::{
+  errors::Error,
+  mediatypes::MediaTypes,
+  v2::{
+    Digest,
+    manifest::{ManifestList, ManifestSchema2Spec, Platform},
+  },
+};
+
+use super::{client, descriptor};
+use crate::common::gzip;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_get_repository_size() {
+  let name = "repo";
+  let (a, b, c) = (vec![b'a'; 1000], vec![b'b'; 2000], vec![b'c'; 3000]);
+  let (layer_a, layer_b, layer_c) = (gzip(&a), gzip(&b), gzip(&c));
+  let config_amd64 = br#"{"architecture":"amd64","os":"linux"}"#.to_vec();
+  let config_arm64 = br#"{"architecture":"arm64","os":"linux"}"#.to_vec();
+
+  let image = |config: &[u8], layers: [&[u8]; 2]| {
+    ManifestSchema2Spec::builder()
+      .config(descriptor(MediaTypes::OciImageConfig, config))
+      .layers(layers.map(|l| descriptor(MediaTypes::OciImageLayerTgz, l)))
+      .build()
+      .unwrap()
+      .encode()
+      .unwrap()
+  };
+  let amd64 = image(&config_amd64, [&layer_a, &layer_b]);
+  let arm64 = image(&config_arm64, [&layer_a, &layer_c]);
+  let platform = |architecture: &str| {
+    let mut descriptor = amd64.descriptor();
+    descriptor.platform = Some(Platform {
+      architecture: architecture.to_string(),
+      os: "linux".to_string(),
+      ..Default::default()
+    });
+    descriptor
+  };
+  let mut arm64_entry = platform("arm64");
+  arm64_entry.digest = arm64.digest.clone();
+  arm64_entry.size = arm64.bytes.len() as u64;
+  let index = ManifestList::builder()
+    .manifests([platform("amd64"), arm64_entry])
+    .build()
+    .unwrap()
+    .encode()
+    .unwrap();
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+  let tags_mock = server
+    .mock("GET", format!("/v2/{name}/tags/list").as_str())
+    .with_status(200)
+    .with_header("Content-Type", "application/json")
+    .with_body(r#"{"name": "repo", "tags": ["v1", "v2"]}"#)
+    .create();
+  let mut mocks = vec![tags_mock];
+  for (reference, manifest) in [
+    ("v1".to_string(), &amd64),
+    ("v2".to_string(), &index),
+    (arm64.digest.to_string(), &arm64),
+  ] {
+    mocks.push(
+      server
+        .mock("GET", format!("/v2/{name}/manifests/{reference}").as_str())
+        .with_status(200)
+        .with_header("Content-Type", &manifest.media_type)
+        .with_header("Docker-Content-Digest", &manifest.digest.to_string())
+        .with_body(&manifest.bytes)
+        .create(),
+    );
+  }
+  // Each blob is fetched once, even when shared between images.
+  for blob in [&config_amd64, &config_arm64, &layer_a, &layer_b, &layer_c] {
+    mocks.push(
+      server
+        .mock("GET", format!("/v2/{name}/blobs/{}", Digest::sha256(blob)).as_str())
+        .with_status(200)
+        .with_body(blob)
+        .expect(1)
+        .create(),
+    );
+  }
+
+  let size = client(&addr).get_repository_size(name, true).await.unwrap();
+  for mock in mocks {
+    mock.assert();
+  }
+
+  assert_eq!(2, size.images);
+  assert_eq!(5, size.blobs);
+  let configs = (config_amd64.len() + config_arm64.len()) as u64;
+  assert_eq!(
+    configs + (layer_a.len() + layer_b.len() + layer_c.len()) as u64,
+    size.compressed
+  );
+  assert_eq!(Some(configs + 6000), size.uncompressed);
+}
+
+#[tokio::test]
+async fn test_get_image_size_schema1() {
+  let name = "repo";
+  let manifest = std::fs::read("tests/fixtures/quayio_coreos_etcd_latest.json").unwrap();
+  let manifest = docker_registry::v2::manifest::ManifestSchema1Signed::from_bytes(manifest).unwrap();
+  let layers = manifest.get_layers_digests();
+  let unique = layers.iter().collect::<std::collections::HashSet<_>>();
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+  // Schema 1 manifests do not record sizes, so blobs are asked for theirs.
+  let mock = server
+    .mock("HEAD", mockito::Matcher::Regex(format!("^/v2/{name}/blobs/sha256:")))
+    .with_status(200)
+    .with_header("Content-Length", "100")
+    .expect(unique.len())
+    .create();
+
+  let manifest = docker_registry::v2::manifest::Manifest::S1Signed(manifest);
+  let size = client(&addr).get_image_size(name, &manifest, false).await.unwrap();
+  mock.assert();
+
+  assert_eq!(1, size.images);
+  assert_eq!(unique.len(), size.blobs);
+  assert_eq!(100 * unique.len() as u64, size.compressed);
+  assert_eq!(None, size.uncompressed);
+}
+
+#[tokio::test]
+async fn test_get_image_size_server_error() {
+  let name = "repo";
+  let manifest = std::fs::read("tests/fixtures/quayio_coreos_etcd_latest.json").unwrap();
+  let manifest = docker_registry::v2::manifest::ManifestSchema1Signed::from_bytes(manifest).unwrap();
+
+  let mut server = mockito::Server::new_async().await;
+  let addr = server.host_with_port();
+  let _mock = server
+    .mock("HEAD", mockito::Matcher::Regex(format!("^/v2/{name}/blobs/sha256:")))
+    .with_status(503)
+    .create();
+
+  let manifest = docker_registry::v2::manifest::Manifest::S1Signed(manifest);
+  let res = client(&addr).get_image_size(name, &manifest, false).await;
+  assert!(matches!(res, Err(Error::Server { .. })), "{res:?}");
+}
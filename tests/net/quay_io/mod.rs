@@ -269,7 +269,7 @@ fn test_quayio_auth_layer_blob() {
       .get_manifest(image, reference)
       .await
       .and_then(|manifest| {
-        let layers: Vec<String> = manifest.layers_digests(None)?;
+        let layers: Vec<docker_registry::v2::Digest> = manifest.layers_digests(None)?;
         let num_layers = layers.len();
         assert!(num_layers == 1, "layers length: {num_layers}");
         let digest = layers[0].clone();
@@ -117,3 +117,31 @@ fn ipv4_registry_and_library_with_tag() -> Result<(), Box<dyn std::error::Error>
 
   Ok(())
 }
+
+#[test]
+fn digest_references() -> Result<(), Box<dyn std::error::Error>> {
+  let digest = "sha256:ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff";
+  let dkr_ref = Reference::from_str(&format!("quay.io/library/busybox@{digest}"))?;
+
+  assert_eq!(dkr_ref.version(), digest);
+  assert_eq!(dkr_ref.digest().map(ToString::to_string).as_deref(), Some(digest));
+  assert!(
+    Reference::from_str("quay.io/library/busybox:latest")?
+      .digest()
+      .is_none()
+  );
+
+  for invalid in [
+    "busybox@sha256:fff",
+    "busybox@sha256:FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+    "busybox@sha512:ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+    "busybox@ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+  ] {
+    assert!(
+      Reference::from_str(invalid).is_err(),
+      "expected {invalid} to be rejected"
+    );
+  }
+
+  Ok(())
+}
@@ -0,0 +1,555 @@
+use std::{
+  fs,
+  io::{self, Read},
+};
+
+use common::{append, gzip, layer_tar};
+use docker_registry::{
+  render::{self, IdMapping, LayerBlob, LayerReader, Ownership, RenderError, UnpackOptions},
+  v2::Digest,
+};
+
+mod common;
+
+/// Relative paths of the files and directories under `dir`, sorted.
+fn list_files(dir: &std::path::Path) -> Vec<String> {
+  fn walk(root: &std::path::Path, dir: &std::path::Path, files: &mut Vec<String>) {
+    for entry in fs::read_dir(dir).unwrap() {
+      let path = entry.unwrap().path();
+      files.push(path.strip_prefix(root).unwrap().to_string_lossy().into_owned());
+      if path.symlink_metadata().unwrap().is_dir() {
+        walk(root, &path, files);
+      }
+    }
+  }
+  let mut files = Vec::new();
+  walk(dir, dir, &mut files);
+  files.sort();
+  files
+}
+
+fn layer_blob(tar: &[u8], diff_id: Option<Digest>) -> LayerBlob {
+  LayerBlob {
+    bytes: gzip(tar),
+    media_type: Some("application/vnd.oci.image.layer.v1.tar+gzip".to_string()),
+    diff_id,
+  }
+}
+
+#[test]
+fn test_unpack_layers_verifies_diff_ids() {
+  let base = layer_tar(&[("etc/os-release", b"ID=test\n")]);
+  let app = layer_tar(&[("app/bin", b"#!/bin/sh\n")]);
+  let diff_ids = [Digest::sha256(&base), Digest::sha256(&app)];
+
+  let dir = tempfile::tempdir().unwrap();
+  let layers = [
+    layer_blob(&base, Some(diff_ids[0].clone())),
+    layer_blob(&app, Some(diff_ids[1].clone())),
+  ];
+  render::unpack_layers(&layers, dir.path()).unwrap();
+  assert_eq!(
+    b"ID=test\n",
+    fs::read(dir.path().join("etc/os-release")).unwrap().as_slice()
+  );
+  assert!(dir.path().join("app/bin").exists());
+
+  // Layers in the wrong order do not match the diff_ids of the config.
+  let dir = tempfile::tempdir().unwrap();
+  let layers = [
+    layer_blob(&app, Some(diff_ids[0].clone())),
+    layer_blob(&base, Some(diff_ids[1].clone())),
+  ];
+  match render::unpack_layers(&layers, dir.path()) {
+    Err(RenderError::DiffIdMismatch { expected, got }) => {
+      assert_eq!(diff_ids[0], expected);
+      assert_eq!(diff_ids[1], got);
+    }
+    res => panic!("unexpected result: {res:?}"),
+  }
+}
+
+/// Reader counting the bytes read from it.
+struct CountingReader<'a> {
+  inner: &'a [u8],
+  read: usize,
+}
+
+impl Read for CountingReader<'_> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let n = self.inner.read(buf)?;
+    self.read += n;
+    Ok(n)
+  }
+}
+
+#[test]
+fn test_unpack_layer_reader() {
+  let base = layer_tar(&[("etc/os-release", b"ID=test\n"), ("etc/motd", b"hello\n")]);
+  let app = layer_tar(&[("etc/.wh.motd", b""), ("app/bin", b"#!/bin/sh\n")]);
+  let dir = tempfile::tempdir().unwrap();
+
+  let base_blob = gzip(&base);
+  let mut reader = CountingReader {
+    inner: &base_blob,
+    read: 0,
+  };
+  let layer = LayerReader {
+    reader: &mut reader,
+    media_type: Some("application/vnd.oci.image.layer.v1.tar+gzip".to_string()),
+    diff_id: None,
+  };
+  render::unpack_layer_reader(layer, dir.path()).unwrap();
+  // The whole blob is consumed, even without a diff_id to verify.
+  assert_eq!(base_blob.len(), reader.read);
+
+  let app_blob = zstd::encode_all(app.as_slice(), 0).unwrap();
+  let layer = LayerReader {
+    reader: app_blob.as_slice(),
+    media_type: Some("application/vnd.oci.image.layer.v1.tar+zstd".to_string()),
+    diff_id: Some(Digest::sha256(&app)),
+  };
+  render::unpack_layer_reader(layer, dir.path()).unwrap();
+
+  assert!(dir.path().join("etc/os-release").exists());
+  assert!(!dir.path().join("etc/motd").exists());
+  assert!(dir.path().join("app/bin").exists());
+}
+
+#[tokio::test]
+async fn test_unpack_layer_async() {
+  let tar = layer_tar(&[("etc/os-release", b"ID=test\n")]);
+  let dir = tempfile::tempdir().unwrap();
+
+  let layer = LayerReader {
+    reader: io::Cursor::new(gzip(&tar)),
+    media_type: None,
+    diff_id: Some(Digest::sha256(&tar)),
+  };
+  render::unpack_layer_async(layer, dir.path()).await.unwrap();
+  assert!(dir.path().join("etc/os-release").exists());
+
+  let layer = LayerReader {
+    reader: io::Cursor::new(gzip(&tar)),
+    media_type: None,
+    diff_id: Some(Digest::sha256(b"other")),
+  };
+  let res = render::unpack_layer_async(layer, dir.path()).await;
+  assert!(matches!(res, Err(RenderError::DiffIdMismatch { .. })), "{res:?}");
+}
+
+#[test]
+fn test_unpack_opaque_whiteouts() {
+  let base = layer_tar(&[
+    ("etc/", b""),
+    ("etc/passwd", b"root\n"),
+    ("etc/conf.d/", b""),
+    ("etc/conf.d/old.conf", b"old\n"),
+    ("etc/conf.d/keep/old.conf", b"old\n"),
+    // Siblings sharing a prefix with the kept directory are hidden all the same.
+    ("etc/conf.d/kee", b"old\n"),
+    ("etc/conf.d/keep0/old.conf", b"old\n"),
+    ("usr/bin/sh", b"sh\n"),
+  ]);
+  let expected = [
+    "etc",
+    "etc/conf.d",
+    "etc/conf.d/keep",
+    "etc/conf.d/keep/new.conf",
+    "etc/conf.d/new.conf",
+    "etc/passwd",
+    "usr",
+    "usr/bin",
+    "usr/bin/sh",
+  ];
+
+  // The opaque marker comes first, or after the entries of the layer in the directory.
+  let uppers = [
+    layer_tar(&[
+      ("etc/conf.d/", b""),
+      ("etc/conf.d/.wh..wh..opq", b""),
+      ("etc/conf.d/new.conf", b"new\n"),
+      ("etc/conf.d/keep/new.conf", b"new\n"),
+    ]),
+    layer_tar(&[
+      ("etc/conf.d/", b""),
+      ("etc/conf.d/keep/", b""),
+      ("etc/conf.d/keep/new.conf", b"new\n"),
+      ("./etc/conf.d/new.conf", b"new\n"),
+      ("etc/conf.d/.wh..wh..opq", b""),
+    ]),
+  ];
+  for upper in &uppers {
+    let dir = tempfile::tempdir().unwrap();
+    render::unpack_layers(&[layer_blob(&base, None), layer_blob(upper, None)], dir.path()).unwrap();
+    assert_eq!(expected.as_slice(), list_files(dir.path()));
+    assert_eq!(
+      b"new\n",
+      fs::read(dir.path().join("etc/conf.d/keep/new.conf"))
+        .unwrap()
+        .as_slice()
+    );
+  }
+
+  // An opaque directory in a single layer is just created.
+  let dir = tempfile::tempdir().unwrap();
+  render::unpack_layers(&[layer_blob(&uppers[0], None)], dir.path()).unwrap();
+  assert_eq!(
+    [
+      "etc",
+      "etc/conf.d",
+      "etc/conf.d/keep",
+      "etc/conf.d/keep/new.conf",
+      "etc/conf.d/new.conf"
+    ]
+    .as_slice(),
+    list_files(dir.path())
+  );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unpack_opaque_whiteout_through_symlink() {
+  let outside = tempfile::tempdir().unwrap();
+  fs::write(outside.path().join("precious"), b"keep me").unwrap();
+
+  let mut builder = tar::Builder::new(Vec::new());
+  let mut header = tar::Header::new_gnu();
+  header.set_entry_type(tar::EntryType::Symlink);
+  header.set_size(0);
+  header.set_mode(0o777);
+  builder.append_link(&mut header, "escape", outside.path()).unwrap();
+  let base = builder.into_inner().unwrap();
+  let upper = layer_tar(&[("escape/.wh..wh..opq", b"")]);
+
+  let dir = tempfile::tempdir().unwrap();
+  render::unpack_layers(&[layer_blob(&base, None), layer_blob(&upper, None)], dir.path()).unwrap();
+  assert!(outside.path().join("precious").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unpack_directory_over_symlink() {
+  let mut builder = tar::Builder::new(Vec::new());
+  append(&mut builder, tar::EntryType::Directory, "b/", b"", "", 0o755);
+  append(&mut builder, tar::EntryType::Regular, "b/old", b"", "", 0o644);
+  append(&mut builder, tar::EntryType::Symlink, "a", b"", "b", 0o777);
+  let base = builder.into_inner().unwrap();
+  let upper = layer_tar(&[("a/", b""), ("a/new", b"new\n")]);
+
+  let dir = tempfile::tempdir().unwrap();
+  render::unpack_layers(&[layer_blob(&base, None), layer_blob(&upper, None)], dir.path()).unwrap();
+
+  assert!(dir.path().join("a").symlink_metadata().unwrap().is_dir());
+  assert_eq!(["a", "a/new", "b", "b/old"].as_slice(), list_files(dir.path()));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unpack_symlink_after_directory() {
+  let outside = tempfile::tempdir().unwrap();
+  let outside_path = outside.path().to_str().unwrap();
+
+  // Directories are applied at the end of the layer, after the symlink replaced their parent.
+  let mut builder = tar::Builder::new(Vec::new());
+  append(&mut builder, tar::EntryType::Directory, "a/b/", b"", "", 0o755);
+  append(&mut builder, tar::EntryType::Symlink, "a", b"", outside_path, 0o777);
+  let nested = builder.into_inner().unwrap();
+  // Or the directory itself.
+  let mut builder = tar::Builder::new(Vec::new());
+  append(&mut builder, tar::EntryType::Directory, "c/", b"", "", 0o755);
+  append(&mut builder, tar::EntryType::Symlink, "c", b"", outside_path, 0o777);
+  let replaced = builder.into_inner().unwrap();
+
+  for overlay in [false, true] {
+    let dir = tempfile::tempdir().unwrap();
+    let unpack = |layer: &[u8]| {
+      let layers = [layer_blob(layer, None)];
+      match overlay {
+        false => render::unpack_layers(&layers, dir.path()),
+        true => render::unpack_layers_overlay(&layers, dir.path(), &UnpackOptions::default()).map(|_| ()),
+      }
+    };
+
+    unpack(&nested).unwrap();
+    let res = unpack(&replaced);
+    assert!(res.is_err(), "{res:?}");
+    assert!(list_files(outside.path()).is_empty(), "overlay: {overlay}");
+  }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unpack_symlink_escapes() {
+  let outside = tempfile::tempdir().unwrap();
+  fs::write(outside.path().join("precious"), b"keep me").unwrap();
+
+  let mut builder = tar::Builder::new(Vec::new());
+  append(
+    &mut builder,
+    tar::EntryType::Symlink,
+    "abs",
+    b"",
+    outside.path().to_str().unwrap(),
+    0o777,
+  );
+  append(
+    &mut builder,
+    tar::EntryType::Symlink,
+    "rel",
+    b"",
+    "../../../../../../..",
+    0o777,
+  );
+  let base = builder.into_inner().unwrap();
+  let upper = layer_tar(&[
+    ("abs/precious", b"overwritten"),
+    ("rel/etc/passwd", b"root\n"),
+    ("abs/.wh.other", b""),
+  ]);
+  let last = layer_tar(&[("abs/.wh.precious", b"")]);
+
+  let dir = tempfile::tempdir().unwrap();
+  render::unpack_layers(&[layer_blob(&base, None), layer_blob(&upper, None)], dir.path()).unwrap();
+
+  // Symlinks are resolved as if the target directory was the root.
+  assert_eq!(
+    b"keep me",
+    fs::read(outside.path().join("precious")).unwrap().as_slice()
+  );
+  let inside = dir.path().join(outside.path().strip_prefix("/").unwrap());
+  assert_eq!(b"overwritten", fs::read(inside.join("precious")).unwrap().as_slice());
+  assert_eq!(b"root\n", fs::read(dir.path().join("etc/passwd")).unwrap().as_slice());
+
+  render::unpack_layers(&[layer_blob(&last, None)], dir.path()).unwrap();
+  assert!(outside.path().join("precious").exists());
+  assert!(!inside.join("precious").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unpack_hard_links() {
+  use std::os::unix::fs::MetadataExt;
+
+  let base = layer_tar(&[("bin/busybox", b"busybox")]);
+  let mut builder = tar::Builder::new(Vec::new());
+  append(&mut builder, tar::EntryType::Link, "bin/sh", b"", "bin/busybox", 0o755);
+  append(
+    &mut builder,
+    tar::EntryType::Link,
+    "usr/bin/ls",
+    b"",
+    "./bin/busybox",
+    0o755,
+  );
+  let upper = builder.into_inner().unwrap();
+
+  let dir = tempfile::tempdir().unwrap();
+  render::unpack_layers(&[layer_blob(&base, None), layer_blob(&upper, None)], dir.path()).unwrap();
+  let busybox = fs::metadata(dir.path().join("bin/busybox")).unwrap();
+  for link in ["bin/sh", "usr/bin/ls"] {
+    assert_eq!(busybox.ino(), fs::metadata(dir.path().join(link)).unwrap().ino());
+  }
+
+  // Hard links to files outside of the target directory are resolved inside it.
+  let outside = tempfile::tempdir().unwrap();
+  fs::write(outside.path().join("secret"), b"secret").unwrap();
+  let mut builder = tar::Builder::new(Vec::new());
+  let secret = outside.path().join("secret");
+  append(
+    &mut builder,
+    tar::EntryType::Link,
+    "leak",
+    b"",
+    secret.to_str().unwrap(),
+    0o644,
+  );
+  let leak = builder.into_inner().unwrap();
+  let res = render::unpack_layers(&[layer_blob(&leak, None)], dir.path());
+  assert!(matches!(res, Err(RenderError::Io(_))), "{res:?}");
+  assert!(!dir.path().join("leak").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unpack_rootless() {
+  use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+  let mut builder = tar::Builder::new(Vec::new());
+  append(&mut builder, tar::EntryType::Char, "dev/null", b"", "", 0o666);
+  let mut header = tar::Header::new_gnu();
+  header.set_size(4);
+  header.set_mode(0o4755);
+  header.set_uid(1000);
+  header.set_gid(1000);
+  builder
+    .append_data(&mut header, "bin/su", b"su\n\n".as_slice())
+    .unwrap();
+  let layer = builder.into_inner().unwrap();
+
+  let dir = tempfile::tempdir().unwrap();
+  let host = fs::metadata(dir.path()).unwrap();
+  let options = UnpackOptions::default()
+    .rootless(true)
+    .preserve_permissions(false)
+    .ownership(Ownership::Map {
+      uids: vec![IdMapping {
+        container_id: 1000,
+        host_id: host.uid(),
+        size: 1,
+      }],
+      gids: vec![IdMapping {
+        container_id: 1000,
+        host_id: host.gid(),
+        size: 1,
+      }],
+    });
+  render::unpack_layers_with(&[layer_blob(&layer, None)], dir.path(), &options).unwrap();
+
+  assert!(dir.path().join("dev/null").symlink_metadata().is_err());
+  let su = fs::metadata(dir.path().join("bin/su")).unwrap();
+  assert_eq!(0o755, su.permissions().mode() & 0o7777);
+  assert_eq!((host.uid(), host.gid()), (su.uid(), su.gid()));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unpack_mapped_ownership_keeps_special_bits() {
+  use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+  let mut builder = tar::Builder::new(Vec::new());
+  for (entry_type, path, mode) in [
+    (tar::EntryType::Directory, "shared/", 0o2775),
+    (tar::EntryType::Regular, "bin/su", 0o4755),
+  ] {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_size(0);
+    header.set_mode(mode);
+    header.set_uid(1000);
+    header.set_gid(1000);
+    builder.append_data(&mut header, path, io::empty()).unwrap();
+  }
+  let layer = builder.into_inner().unwrap();
+
+  let dir = tempfile::tempdir().unwrap();
+  let host = fs::metadata(dir.path()).unwrap();
+  let mapping = |host_id| {
+    vec![IdMapping {
+      container_id: 1000,
+      host_id,
+      size: 1,
+    }]
+  };
+  let options = UnpackOptions::default().ownership(Ownership::Map {
+    uids: mapping(host.uid()),
+    gids: mapping(host.gid()),
+  });
+  render::unpack_layers_with(&[layer_blob(&layer, None)], dir.path(), &options).unwrap();
+
+  // Changing the owner clears setuid and setgid, which must be applied again.
+  for (path, mode) in [("shared", 0o2775), ("bin/su", 0o4755)] {
+    let metadata = fs::metadata(dir.path().join(path)).unwrap();
+    assert_eq!(mode, metadata.permissions().mode() & 0o7777, "{path}");
+    assert_eq!((host.uid(), host.gid()), (metadata.uid(), metadata.gid()));
+  }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_unpack_layers_overlay() {
+  use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+  let base = layer_tar(&[("etc/a", b"a"), ("etc/b", b"b"), ("opt/x", b"x")]);
+  let upper = layer_tar(&[
+    ("etc/.wh.a", b""),
+    ("opt/", b""),
+    ("opt/.wh..wh..opq", b""),
+    ("opt/y", b"y"),
+  ]);
+  let diff_ids = [Digest::sha256(&base), Digest::sha256(&upper)];
+
+  for (rootless, xattr) in [(false, "trusted.overlay.opaque"), (true, "user.overlay.opaque")] {
+    let dir = tempfile::tempdir().unwrap();
+    let options = UnpackOptions::default().rootless(rootless);
+    let layers = [layer_blob(&base, None), layer_blob(&upper, None)];
+    let paths = match render::unpack_layers_overlay(&layers, dir.path(), &options) {
+      Err(RenderError::Io(e)) if e.kind() == io::ErrorKind::PermissionDenied => {
+        eprintln!("skipping overlay test without privileges: {e}");
+        return;
+      }
+      res => res.unwrap(),
+    };
+    assert_eq!(
+      diff_ids
+        .iter()
+        .map(|d| render::overlay_layer_path(dir.path(), d))
+        .collect::<Vec<_>>(),
+      paths
+    );
+    assert_eq!(
+      ["etc", "etc/a", "etc/b", "opt", "opt/x"].as_slice(),
+      list_files(&paths[0])
+    );
+    assert_eq!(["etc", "etc/a", "opt", "opt/y"].as_slice(), list_files(&paths[1]));
+
+    let whiteout = fs::symlink_metadata(paths[1].join("etc/a")).unwrap();
+    assert!(whiteout.file_type().is_char_device());
+    assert_eq!(0, whiteout.rdev());
+    let mut value = [0; 8];
+    let n = rustix::fs::lgetxattr(paths[1].join("opt"), xattr, &mut value).unwrap();
+    assert_eq!(b"y", &value[..n]);
+    assert!(rustix::fs::lgetxattr(paths[1].join("etc"), xattr, &mut value).is_err());
+  }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_unpack_layers_overlay_reuse() {
+  let tar = layer_tar(&[("etc/os-release", b"ID=test\n")]);
+  let diff_id = Digest::sha256(&tar);
+  let dir = tempfile::tempdir().unwrap();
+  let options = UnpackOptions::default();
+
+  let paths = render::unpack_layers_overlay(&[layer_blob(&tar, Some(diff_id.clone()))], dir.path(), &options).unwrap();
+  fs::write(paths[0].join("marker"), b"").unwrap();
+
+  // A known layer is not read again.
+  let layer = LayerBlob::new(b"not even a tarball".to_vec(), None).diff_id(diff_id.clone());
+  assert_eq!(
+    paths,
+    render::unpack_layers_overlay(&[layer], dir.path(), &options).unwrap()
+  );
+  // An unknown one is unpacked, then found to be there already.
+  assert_eq!(
+    paths,
+    render::unpack_layers_overlay(&[layer_blob(&tar, None)], dir.path(), &options).unwrap()
+  );
+  assert!(paths[0].join("marker").exists());
+
+  // A layer not matching its diff_id leaves nothing behind.
+  let other = layer_tar(&[("etc/other", b"")]);
+  let res = render::unpack_layers_overlay(
+    &[layer_blob(&other, Some(Digest::sha256(b"other")))],
+    dir.path(),
+    &options,
+  );
+  assert!(matches!(res, Err(RenderError::DiffIdMismatch { .. })), "{res:?}");
+  assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+}
+
+#[test]
+fn test_chain_ids() {
+  let diff_ids = [Digest::sha256(b"a"), Digest::sha256(b"b"), Digest::sha256(b"c")];
+  let chain_ids = render::chain_ids(&diff_ids);
+  assert_eq!(
+    vec![
+      diff_ids[0].to_string(),
+      "sha256:51c0c8ace48498d6f5fee6b0592cc06f2da0f3cbe09c5a34a97dce85c3889676".to_string(),
+      "sha256:2fce7f8ce91bcf0a1428b36e1024639fdbd9469eea762dba98aa749631885106".to_string(),
+    ],
+    chain_ids.iter().map(Digest::to_string).collect::<Vec<_>>()
+  );
+  assert_eq!(chain_ids.last(), render::chain_id(&diff_ids).as_ref());
+  assert_eq!(None, render::chain_id(&[]));
+}